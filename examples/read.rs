@@ -9,7 +9,7 @@ async fn main() -> io::Result<()> {
     env_logger::init();
 
     let ftdis = Ftdi::list_devices().await?;
-    if ftdis.len() == 0 {
+    if ftdis.is_empty() {
         println!("Not FTDIs connected!");
         return Ok(());
     }
@@ -43,7 +43,7 @@ async fn reader(mut ftdi: Ftdi) -> io::Result<()> {
         print!("0x{:x} ", x);
         print_cnt += 1;
         if print_cnt == 8 {
-            print!("\n");
+            println!();
             print_cnt = 0;
         }
     }
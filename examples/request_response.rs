@@ -8,7 +8,7 @@ async fn main() -> io::Result<()> {
     env_logger::init();
 
     let ftdis = Ftdi::list_devices().await?;
-    if ftdis.len() == 0 {
+    if ftdis.is_empty() {
         println!("Not FTDIs connected!");
         return Ok(());
     }
@@ -31,7 +31,7 @@ async fn main() -> io::Result<()> {
         for x in read_buf {
             print!("0x{:x} ", x);
         }
-        print!("\n");
+        println!();
     }
 
     Ok(())
@@ -0,0 +1,53 @@
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use async_ftdi::sync_fifo::{SyncFifoConfig, SyncFifoEvent};
+use async_ftdi::{DataBits, Ftdi, Parity, SerialParams, StopBits};
+use futures_core::Stream;
+
+/// Throughput of the FT232H/FT2232H FIFO interface.
+const TARGET: f64 = 40e6;
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+
+    let ftdis = Ftdi::list_devices().await?;
+    let Some(ftdi_info) = ftdis.first() else {
+        println!("Not FTDIs connected!");
+        return Ok(());
+    };
+    println!("Measuring FIFO throughput of {}", &ftdi_info.serial_number);
+    let params = SerialParams {
+        baud: 115200,
+        data_bits: DataBits::Eight,
+        stop_bits: StopBits::One,
+        parity: Parity::None,
+    };
+    let ftdi = Ftdi::open(&ftdi_info.serial_number, &params).await?;
+    let mut fifo = ftdi.into_sync_fifo(SyncFifoConfig::default()).await?;
+
+    let start = Instant::now();
+    let mut received = 0;
+    let mut lost = 0;
+    while start.elapsed() < Duration::from_secs(5) {
+        match poll_fn(|cx| Pin::new(&mut fifo).poll_next(cx)).await {
+            Some(Ok(SyncFifoEvent::Data(x))) => received += x.len(),
+            Some(Ok(SyncFifoEvent::Overrun { lost_bytes })) => lost += lost_bytes,
+            Some(Err(err)) => return Err(err),
+            None => break,
+        }
+    }
+    let rate = received as f64 / start.elapsed().as_secs_f64();
+    println!(
+        "{:.1} MB/s ({:.0}% of {:.0} MB/s), {} bytes lost",
+        rate / 1e6,
+        rate / TARGET * 100.0,
+        TARGET / 1e6,
+        lost
+    );
+    fifo.close().await;
+    Ok(())
+}
@@ -9,7 +9,7 @@ async fn main() -> io::Result<()> {
     env_logger::init();
 
     let ftdis = Ftdi::list_devices().await?;
-    if ftdis.len() == 0 {
+    if ftdis.is_empty() {
         println!("Not FTDIs connected!");
         return Ok(());
     }
@@ -0,0 +1,129 @@
+//! Dump, restore and provision the EEPROM of FTDI devices.
+//!
+//! Usage: ftdi-eeprom [--serial <serial>] <command>
+//!
+//! Commands:
+//!
+//! * dump <file>
+//! * restore <file>
+//! * provision <template> <pattern> <counter-file> [--vid <vid>] [--pid <pid>] [--reprovision]
+
+use std::io;
+use std::process;
+
+use async_ftdi::eeprom::EepromConfig;
+use async_ftdi::provision::{matching_devices, provision_all, ProvisionConfig};
+use async_ftdi::{Ftdi, SerialParams};
+
+const USAGE: &str = "Usage: ftdi-eeprom [--serial <serial>] <command>
+
+Commands:
+    dump <file>                     Save the EEPROM configuration as JSON
+    restore <file>                  Program and verify a saved configuration
+    provision <template> <pattern> <counter-file>
+                                    Program the template into every matching device,
+                                    serial numbers are allocated from the pattern,
+                                    e.g. FIX-####, and the counter file
+
+Provision options:
+    --vid <vid>                     Only provision devices with this vendor ID
+    --pid <pid>                     Only provision devices with this product ID
+    --reprovision                   Include devices already matching the pattern
+    --dry-run                       Only list the devices which would be provisioned";
+
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn usage_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
+
+fn parse_id(x: &str) -> io::Result<u16> {
+    let parsed = match x.strip_prefix("0x").or_else(|| x.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => x.parse(),
+    };
+    parsed.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid number: {}", x),
+        )
+    })
+}
+
+async fn run() -> io::Result<()> {
+    let mut serial = None;
+    let mut vendor_id = None;
+    let mut product_id = None;
+    let mut reprovision = false;
+    let mut dry_run = false;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(usage_error);
+        match arg.as_str() {
+            "--serial" => serial = Some(value()?),
+            "--vid" => vendor_id = Some(parse_id(&value()?)?),
+            "--pid" => product_id = Some(parse_id(&value()?)?),
+            "--reprovision" => reprovision = true,
+            "--dry-run" => dry_run = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let (command, args) = positional.split_first().ok_or_else(usage_error)?;
+
+    match (command.as_str(), args) {
+        ("dump", [file]) => {
+            let ftdi = Ftdi::open_or_first(serial.as_deref(), &SerialParams::default()).await?;
+            let config = ftdi.eeprom_dump(file).await?;
+            println!("{}", config.to_json());
+        }
+        ("restore", [file]) => {
+            let ftdi = Ftdi::open_or_first(serial.as_deref(), &SerialParams::default()).await?;
+            ftdi.eeprom_restore(file).await?;
+            println!("EEPROM programmed and verified, replug the device to apply it");
+        }
+        ("provision", [template, pattern, counter_file]) => {
+            let mut config =
+                ProvisionConfig::new(EepromConfig::load(template)?, pattern, counter_file.into());
+            config.vendor_id = vendor_id;
+            config.product_id = product_id;
+            config.reprovision = reprovision;
+            if dry_run {
+                for device in matching_devices(&config).await? {
+                    println!("{} ({})", device.serial_number, device.description);
+                }
+                return Ok(());
+            }
+            let mut failed = 0;
+            for x in provision_all(&config).await? {
+                let serial = x.serial_number.as_deref().unwrap_or("-");
+                let previous = format!("{} (0x{:x})", x.previous.serial_number, x.location_id);
+                match x.result {
+                    Ok(()) => println!("{} -> {}: ok", previous, serial),
+                    Err(err) => {
+                        failed += 1;
+                        println!("{} -> {}: {}", previous, serial, err);
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(io::Error::other(format!(
+                    "{} devices failed to provision",
+                    failed
+                )));
+            }
+        }
+        _ => return Err(usage_error()),
+    }
+    Ok(())
+}
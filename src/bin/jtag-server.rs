@@ -0,0 +1,112 @@
+//! Expose an FTDI JTAG adapter over OpenOCD `remote_bitbang` and Xilinx Virtual Cable.
+//!
+//! Usage: jtag-server [--serial <serial>] [--frequency <hz>] [--remote-bitbang <addr>]
+//!                    [--xvc <addr>] [--simulate <idcode>]
+//!
+//! Only one client is served at a time, further clients wait until it disconnected.
+//! With `--simulate` no device is opened and clients talk to a simulated TAP instead.
+
+use std::io;
+use std::process;
+
+use async_ftdi::jtag::Jtag;
+use async_ftdi::jtag_server::{serve_remote_bitbang, serve_xvc, JtagBackend, SimulatedTap};
+use async_ftdi::{Ftdi, SerialParams};
+use tokio::net::TcpListener;
+
+const USAGE: &str = "Usage: jtag-server [options]
+
+Options:
+    --serial <serial>         Serial number of the FTDI device, defaults to the first device
+    --frequency <hz>          TCK frequency, defaults to 1 MHz
+    --remote-bitbang <addr>   Listen address for remote_bitbang, defaults to 127.0.0.1:3335
+    --xvc <addr>              Listen address for XVC, defaults to 127.0.0.1:2542
+    --simulate <idcode>       Serve a simulated TAP instead of a device";
+
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn usage_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
+
+fn parse_number(x: &str) -> io::Result<u32> {
+    let parsed = match x.strip_prefix("0x").or_else(|| x.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => x.parse(),
+    };
+    parsed.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid number: {}", x),
+        )
+    })
+}
+
+async fn run() -> io::Result<()> {
+    let mut serial = None;
+    let mut frequency = 1_000_000;
+    let mut bitbang_addr = "127.0.0.1:3335".to_string();
+    let mut xvc_addr = "127.0.0.1:2542".to_string();
+    let mut simulate = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(usage_error);
+        match arg.as_str() {
+            "--serial" => serial = Some(value()?),
+            "--frequency" => frequency = parse_number(&value()?)?,
+            "--remote-bitbang" => bitbang_addr = value()?,
+            "--xvc" => xvc_addr = value()?,
+            "--simulate" => simulate = Some(parse_number(&value()?)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => return Err(usage_error()),
+        }
+    }
+
+    let bitbang = TcpListener::bind(&bitbang_addr).await?;
+    let xvc = TcpListener::bind(&xvc_addr).await?;
+    println!("remote_bitbang listening on {}", bitbang_addr);
+    println!("XVC listening on {}", xvc_addr);
+
+    if let Some(idcode) = simulate {
+        return serve(SimulatedTap::new(idcode), bitbang, xvc).await;
+    }
+    let ftdi = Ftdi::open_or_first(serial.as_deref(), &SerialParams::default()).await?;
+    let jtag = Jtag::new(ftdi, frequency).await?;
+    serve(jtag, bitbang, xvc).await
+}
+
+async fn serve(
+    mut backend: impl JtagBackend,
+    bitbang: TcpListener,
+    xvc: TcpListener,
+) -> io::Result<()> {
+    loop {
+        let result = tokio::select! {
+            x = bitbang.accept() => {
+                let (stream, peer) = x?;
+                println!("remote_bitbang client {} connected", peer);
+                stream.set_nodelay(true)?;
+                serve_remote_bitbang(stream, &mut backend).await
+            }
+            x = xvc.accept() => {
+                let (stream, peer) = x?;
+                println!("XVC client {} connected", peer);
+                stream.set_nodelay(true)?;
+                serve_xvc(stream, &mut backend).await
+            }
+        };
+        match result {
+            Ok(()) => println!("Client disconnected"),
+            Err(err) => eprintln!("Client error: {}", err),
+        }
+    }
+}
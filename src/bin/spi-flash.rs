@@ -0,0 +1,151 @@
+//! Flash SPI NOR chips connected to the MPSSE pins of an FTDI device.
+//!
+//! Usage: spi-flash [--serial <serial>] [--frequency <hz>] <command>
+//!
+//! Commands:
+//!
+//! * id
+//! * read <address> <length> <file>
+//! * write <address> <file>
+//! * verify <address> <file>
+//! * erase <address> <length>
+//! * erase-chip
+
+use std::future::poll_fn;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::process;
+
+use async_ftdi::spi::{MpsseSpi, SpiConfig};
+use async_ftdi::spi_flash::{FlashGeometry, FlashProgressStream, FlashStage, SpiFlash};
+use async_ftdi::{Ftdi, SerialParams};
+use futures_core::Stream;
+
+const USAGE: &str = "Usage: spi-flash [--serial <serial>] [--frequency <hz>] <command>
+
+Commands:
+    id                              Print the JEDEC ID and flash geometry
+    read <address> <length> <file>  Read flash contents into a file
+    write <address> <file>          Erase, program and verify a file
+    verify <address> <file>         Compare flash contents against a file
+    erase <address> <length>        Erase all sectors in the range
+    erase-chip                      Erase the whole chip";
+
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn usage_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
+
+fn parse_number(x: &str) -> io::Result<u32> {
+    let parsed = match x.strip_prefix("0x").or_else(|| x.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => x.parse(),
+    };
+    parsed.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid number: {}", x),
+        )
+    })
+}
+
+async fn run() -> io::Result<()> {
+    let mut serial = None;
+    let mut config = SpiConfig::default();
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--serial" => serial = Some(args.next().ok_or_else(usage_error)?),
+            "--frequency" => {
+                config.frequency = parse_number(&args.next().ok_or_else(usage_error)?)?
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let (command, args) = positional.split_first().ok_or_else(usage_error)?;
+
+    let ftdi = Ftdi::open_or_first(serial.as_deref(), &SerialParams::default()).await?;
+    let spi = MpsseSpi::new(ftdi, config).await?;
+    // flashes without SFDP can still be identified and read with the default geometry
+    let mut flash = SpiFlash::with_geometry(spi, FlashGeometry::default());
+    let id = flash.probe().await?;
+    let sfdp = flash.detect_geometry().await;
+    let progress = tokio::spawn(print_progress(flash.progress()));
+
+    match (command.as_str(), args) {
+        ("id", []) => {
+            println!(
+                "JEDEC ID: {:02x} {:02x} {:02x}",
+                id.manufacturer, id.memory_type, id.capacity
+            );
+            match sfdp {
+                Ok(()) => println!("{:#?}", flash.geometry()),
+                Err(err) => println!("Geometry unknown: {}", err),
+            }
+        }
+        _ if sfdp.is_err() && matches!(command.as_str(), "write" | "erase" | "erase-chip") => {
+            return sfdp;
+        }
+        ("read", [address, length, file]) => {
+            let mut data = vec![0; parse_number(length)? as usize];
+            flash.read(parse_number(address)?, &mut data).await?;
+            std::fs::write(file, data)?;
+        }
+        ("write", [address, file]) => {
+            let data = std::fs::read(file)?;
+            flash.flash(parse_number(address)?, &data).await?;
+        }
+        ("verify", [address, file]) => {
+            let data = std::fs::read(file)?;
+            flash.verify(parse_number(address)?, &data).await?;
+        }
+        ("erase", [address, length]) => {
+            flash
+                .erase(parse_number(address)?, parse_number(length)? as usize)
+                .await?;
+        }
+        ("erase-chip", []) => flash.erase_chip().await?,
+        _ => return Err(usage_error()),
+    }
+
+    drop(flash);
+    let _ = progress.await;
+    Ok(())
+}
+
+async fn print_progress(mut stream: FlashProgressStream) {
+    let mut last = None;
+    while let Some(progress) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        let stage = match progress.stage {
+            FlashStage::Erase => "Erasing",
+            FlashStage::Program => "Programming",
+            FlashStage::Read => "Reading",
+            FlashStage::Verify => "Verifying",
+        };
+        let percent = progress.done * 100 / progress.total.max(1);
+        if last == Some((progress.stage, percent)) {
+            continue;
+        }
+        if last.is_some_and(|(x, _)| x != progress.stage) {
+            println!();
+        }
+        last = Some((progress.stage, percent));
+        print!("\r{}: {:3}%", stage, percent);
+        let _ = io::stdout().flush();
+    }
+    if last.is_some() {
+        println!();
+    }
+}
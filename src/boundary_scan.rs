@@ -0,0 +1,231 @@
+//! Boundary scan of a device described by a BSDL file, on top of [`Jtag`].
+//!
+//! Pin levels are staged with [`BoundaryScan::set_pin`] and [`BoundaryScan::release_pin`]
+//! and applied with [`BoundaryScan::update`], which also captures all input cells. Initially
+//! all outputs are disabled.
+
+use std::io;
+
+use crate::bsdl::Bsdl;
+use crate::jtag::{Jtag, TapState};
+
+/// Position of the device in a chain, all other devices are put into BYPASS.
+#[derive(Debug, Clone, Default)]
+pub struct ChainPosition {
+    /// Number of devices between this device and TDO.
+    pub devices_after: usize,
+    /// Sum of the instruction register lengths of the devices between this device and TDO.
+    pub ir_bits_after: usize,
+    /// Number of devices between TDI and this device.
+    pub devices_before: usize,
+    /// Sum of the instruction register lengths of the devices between TDI and this device.
+    pub ir_bits_before: usize,
+}
+
+pub struct BoundaryScan<'a> {
+    jtag: &'a mut Jtag,
+    bsdl: Bsdl,
+    chain: ChainPosition,
+    /// Values shifted into the boundary register, indexed by cell number.
+    outputs: Vec<bool>,
+    /// Values captured by the last update.
+    captured: Vec<bool>,
+}
+
+fn pack(bits: &[bool]) -> Vec<u8> {
+    let mut ret = vec![0; bits.len().div_ceil(8)];
+    for (idx, x) in bits.iter().enumerate() {
+        ret[idx / 8] |= (*x as u8) << (idx % 8);
+    }
+    ret
+}
+
+fn unpack(data: &[u8], bits: usize) -> Vec<bool> {
+    (0..bits)
+        .map(|x| data[x / 8] & (1 << (x % 8)) != 0)
+        .collect()
+}
+
+/// Safe values of all cells with every output disabled, indexed by cell number.
+fn safe_outputs(bsdl: &Bsdl) -> Vec<bool> {
+    let mut outputs = vec![false; bsdl.boundary_length];
+    for cell in &bsdl.cells {
+        outputs[cell.number] = cell.safe.unwrap_or(false);
+    }
+    for cell in &bsdl.cells {
+        if let (Some(control), Some(disable)) = (cell.control, cell.disable_value) {
+            outputs[control] = disable;
+        }
+    }
+    outputs
+}
+
+impl<'a> BoundaryScan<'a> {
+    pub fn new(jtag: &'a mut Jtag, bsdl: Bsdl) -> Self {
+        Self::with_chain(jtag, bsdl, ChainPosition::default())
+    }
+
+    pub fn with_chain(jtag: &'a mut Jtag, bsdl: Bsdl, chain: ChainPosition) -> Self {
+        let outputs = safe_outputs(&bsdl);
+        let captured = vec![false; bsdl.boundary_length];
+        Self {
+            jtag,
+            bsdl,
+            chain,
+            outputs,
+            captured,
+        }
+    }
+
+    pub fn bsdl(&self) -> &Bsdl {
+        &self.bsdl
+    }
+
+    /// Load SAMPLE/PRELOAD, preload the staged outputs and capture the pins.
+    ///
+    /// The pins are not affected, this is safe to use while the device is operating.
+    pub async fn sample(&mut self) -> io::Result<()> {
+        let opcode = self.opcode(&["SAMPLE", "PRELOAD"])?;
+        self.load_instruction(&opcode).await?;
+        self.update().await
+    }
+
+    /// Preload the staged outputs, then load EXTEST to take control over the pins.
+    pub async fn extest(&mut self) -> io::Result<()> {
+        self.sample().await?;
+        let opcode = self.opcode(&["EXTEST"])?;
+        self.load_instruction(&opcode).await
+    }
+
+    /// Shift the staged outputs into the boundary register and capture the input cells.
+    ///
+    /// The outputs only reach the pins while EXTEST is loaded.
+    pub async fn update(&mut self) -> io::Result<()> {
+        let mut bits = vec![false; self.chain.devices_after];
+        bits.extend(&self.outputs);
+        bits.extend(vec![false; self.chain.devices_before]);
+        let tdo = self
+            .jtag
+            .shift_dr(&pack(&bits), bits.len(), TapState::RunTestIdle)
+            .await?;
+        let tdo = unpack(&tdo, bits.len());
+        let start = self.chain.devices_after;
+        self.captured = tdo[start..start + self.outputs.len()].to_vec();
+        Ok(())
+    }
+
+    /// Stage `pin` to be driven to `high`, `pin` is a port name or a package pin.
+    pub fn set_pin(&mut self, pin: &str, high: bool) -> io::Result<()> {
+        let port = self.resolve(pin)?;
+        let cell = self
+            .bsdl
+            .cells
+            .iter()
+            .find(|x| x.port.as_ref() == Some(&port) && x.function.is_output())
+            .ok_or_else(|| pin_error(pin, "has no output cell"))?;
+        self.outputs[cell.number] = high;
+        if let (Some(control), Some(disable)) = (cell.control, cell.disable_value) {
+            self.outputs[control] = !disable;
+        }
+        Ok(())
+    }
+
+    /// Stage the output driver of `pin` to be disabled.
+    pub fn release_pin(&mut self, pin: &str) -> io::Result<()> {
+        let port = self.resolve(pin)?;
+        let cell = self
+            .bsdl
+            .cells
+            .iter()
+            .find(|x| x.port.as_ref() == Some(&port) && x.function.is_output())
+            .ok_or_else(|| pin_error(pin, "has no output cell"))?;
+        match (cell.control, cell.disable_value) {
+            (Some(control), Some(disable)) => {
+                self.outputs[control] = disable;
+                Ok(())
+            }
+            _ => Err(pin_error(pin, "cannot be disabled")),
+        }
+    }
+
+    /// Level of `pin` as captured by the last update.
+    pub fn pin(&self, pin: &str) -> io::Result<bool> {
+        let port = self.resolve(pin)?;
+        let cell = self
+            .bsdl
+            .cells
+            .iter()
+            .find(|x| x.port.as_ref() == Some(&port) && x.function.is_input())
+            .ok_or_else(|| pin_error(pin, "has no input cell"))?;
+        Ok(self.captured[cell.number])
+    }
+
+    /// Apply the staged outputs and return the captured level of `pin`.
+    pub async fn read_pin(&mut self, pin: &str) -> io::Result<bool> {
+        self.update().await?;
+        self.pin(pin)
+    }
+
+    /// Move the TAP to Test-Logic-Reset, which releases all pins.
+    pub async fn release(&mut self) -> io::Result<()> {
+        self.jtag.reset().await
+    }
+
+    fn resolve(&self, pin: &str) -> io::Result<String> {
+        self.bsdl
+            .resolve_pin(pin)
+            .ok_or_else(|| pin_error(pin, "is unknown"))
+    }
+
+    fn opcode(&self, names: &[&str]) -> io::Result<Vec<bool>> {
+        names
+            .iter()
+            .find_map(|x| self.bsdl.opcode(x))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{} does not support {}", self.bsdl.entity, names[0]),
+                )
+            })
+    }
+
+    async fn load_instruction(&mut self, opcode: &[bool]) -> io::Result<()> {
+        let mut bits = vec![true; self.chain.ir_bits_after];
+        bits.extend(opcode);
+        bits.extend(vec![true; self.chain.ir_bits_before]);
+        self.jtag
+            .shift_ir(&pack(&bits), bits.len(), TapState::RunTestIdle)
+            .await?;
+        Ok(())
+    }
+}
+
+fn pin_error(pin: &str, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Pin {} {}", pin, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_outputs_disable_all_drivers() {
+        let bsdl = Bsdl::parse(include_str!("../tests/fixtures/tiny.bsdl")).unwrap();
+        let outputs = safe_outputs(&bsdl);
+        // cell 0 disables LED with 1, cell 5 disables D(1:0) with its safe value 1
+        assert_eq!(outputs, vec![true, false, false, false, false, true, false]);
+        // cell 0 is closest to TDO and therefore shifted first, into bit 0
+        assert_eq!(pack(&outputs), vec![0x21]);
+        assert_eq!(unpack(&[0x21], 7), outputs);
+    }
+
+    #[test]
+    fn pack_spans_bytes() {
+        let mut bits = vec![false; 10];
+        bits[0] = true;
+        bits[8] = true;
+        bits[9] = true;
+        assert_eq!(pack(&bits), vec![0x01, 0x03]);
+        assert_eq!(unpack(&[0x01, 0x03], 10), bits);
+    }
+}
@@ -0,0 +1,447 @@
+//! Parser for the subset of BSDL (IEEE 1149.1 Boundary Scan Description Language)
+//! required for boundary scan: ports, pin map, instructions and the boundary register.
+
+use std::collections::HashMap;
+use std::io;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CellFunction {
+    Input,
+    Clock,
+    Output2,
+    Output3,
+    Control,
+    ControlR,
+    Internal,
+    Bidir,
+    ObserveOnly,
+}
+
+impl CellFunction {
+    fn parse(x: &str) -> io::Result<Self> {
+        Ok(match x.to_ascii_lowercase().as_str() {
+            "input" => CellFunction::Input,
+            "clock" => CellFunction::Clock,
+            "output2" => CellFunction::Output2,
+            "output3" => CellFunction::Output3,
+            "control" => CellFunction::Control,
+            "controlr" => CellFunction::ControlR,
+            "internal" => CellFunction::Internal,
+            "bidir" => CellFunction::Bidir,
+            "observe_only" => CellFunction::ObserveOnly,
+            _ => return Err(invalid(format!("Unknown cell function {}", x))),
+        })
+    }
+
+    /// Whether the cell drives a pin.
+    pub fn is_output(self) -> bool {
+        matches!(
+            self,
+            CellFunction::Output2 | CellFunction::Output3 | CellFunction::Bidir
+        )
+    }
+
+    /// Whether the cell captures the level of a pin.
+    pub fn is_input(self) -> bool {
+        matches!(
+            self,
+            CellFunction::Input
+                | CellFunction::Clock
+                | CellFunction::Bidir
+                | CellFunction::ObserveOnly
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BoundaryCell {
+    /// Position in the boundary register, cell 0 is closest to TDO.
+    pub number: usize,
+    pub cell_type: String,
+    /// Port name as used in the port declaration, e.g. `D(3)`, `None` for internal cells.
+    pub port: Option<String>,
+    pub function: CellFunction,
+    /// Safe value, `None` if the BSDL file specifies `X`.
+    pub safe: Option<bool>,
+    /// Control cell which enables this output cell.
+    pub control: Option<usize>,
+    /// Value of the control cell which disables this output.
+    pub disable_value: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Bsdl {
+    pub entity: String,
+    /// Index ranges of all bus ports, in declaration order.
+    pub ports: HashMap<String, Vec<i64>>,
+    /// Package pins of every port, ports of bus type list one pin per index.
+    pub pin_map: HashMap<String, Vec<String>>,
+    pub instruction_length: usize,
+    /// Opcodes of every instruction, as written in the file (MSB first).
+    pub instructions: HashMap<String, Vec<String>>,
+    pub idcode: Option<String>,
+    pub boundary_length: usize,
+    pub cells: Vec<BoundaryCell>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("BSDL: {}", msg))
+}
+
+/// Split `text` at `separator`, ignoring separators inside parentheses and quotes.
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let mut depth = 0_i32;
+    let mut quoted = false;
+    let mut start = 0;
+    for (idx, x) in text.char_indices() {
+        match x {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            x if x == separator && !quoted && depth == 0 => {
+                ret.push(&text[start..idx]);
+                start = idx + x.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    ret.push(&text[start..]);
+    ret
+}
+
+/// Concatenate all string literals of a value like `"a" & "b"`.
+fn string_value(value: &str) -> String {
+    value
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// The part of `text` inside the outermost parentheses.
+fn parenthesized(text: &str) -> Option<(&str, &str)> {
+    let open = text.find('(')?;
+    let close = text.rfind(')')?;
+    if close < open {
+        return None;
+    }
+    Some((text[..open].trim(), &text[open + 1..close]))
+}
+
+fn parse_number<T: std::str::FromStr>(x: &str) -> io::Result<T> {
+    x.trim()
+        .parse()
+        .map_err(|_| invalid(format!("Invalid number {}", x.trim())))
+}
+
+impl Bsdl {
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let text: String = text
+            .lines()
+            .map(|x| match x.find("--") {
+                Some(idx) => &x[..idx],
+                None => x,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut ret = Bsdl::default();
+        let mut attributes = HashMap::new();
+        let mut pin_maps = HashMap::new();
+        let mut physical_pin_map = None;
+        for statement in split_top_level(&text, ';') {
+            let statement = statement.trim();
+            let lower = statement.to_ascii_lowercase();
+            let words: Vec<&str> = lower
+                .split(|x: char| x.is_whitespace() || x == '(')
+                .filter(|x| !x.is_empty())
+                .collect();
+            match words.first() {
+                Some(&"entity") if words.len() >= 2 => {
+                    ret.entity = statement.split_whitespace().nth(1).unwrap().to_string();
+                    if let Some(idx) = lower.find("physical_pin_map") {
+                        physical_pin_map = Some(string_value(&statement[idx..]));
+                    }
+                }
+                Some(&"port") => {
+                    let (_, body) = parenthesized(statement)
+                        .ok_or_else(|| invalid("Invalid port declaration".into()))?;
+                    ret.parse_ports(body)?;
+                }
+                Some(&"attribute") if words.contains(&"is") => {
+                    let name = words[1].to_ascii_uppercase();
+                    let idx = lower.find(" is ").unwrap() + 4;
+                    attributes.insert(name, statement[idx..].trim().to_string());
+                }
+                Some(&"constant") if lower.contains("pin_map_string") => {
+                    let name = statement.split_whitespace().nth(1).unwrap_or_default();
+                    let name = name.trim_end_matches(':').to_string();
+                    pin_maps.insert(name, string_value(statement));
+                }
+                _ => {}
+            }
+        }
+        if ret.entity.is_empty() {
+            return Err(invalid("No entity found".into()));
+        }
+
+        let pin_map = match physical_pin_map.and_then(|x| pin_maps.remove(&x)) {
+            Some(x) => Some(x),
+            None => pin_maps.into_values().next(),
+        };
+        if let Some(x) = pin_map {
+            ret.parse_pin_map(&x)?;
+        }
+
+        let attribute = |name: &str| {
+            attributes
+                .get(name)
+                .ok_or_else(|| invalid(format!("Attribute {} missing", name)))
+        };
+        ret.instruction_length = parse_number(attribute("INSTRUCTION_LENGTH")?)?;
+        ret.parse_instructions(&string_value(attribute("INSTRUCTION_OPCODE")?))?;
+        ret.idcode = attributes.get("IDCODE_REGISTER").map(|x| string_value(x));
+        ret.boundary_length = parse_number(attribute("BOUNDARY_LENGTH")?)?;
+        ret.parse_boundary_register(&string_value(attribute("BOUNDARY_REGISTER")?))?;
+        Ok(ret)
+    }
+
+    fn parse_ports(&mut self, body: &str) -> io::Result<()> {
+        for declaration in split_top_level(body, ';') {
+            let (names, kind) = match declaration.split_once(':') {
+                Some(x) => x,
+                None => continue,
+            };
+            let indices = match parenthesized(kind) {
+                Some((_, range)) => {
+                    let range = range.to_ascii_lowercase();
+                    if let Some((a, b)) = range.split_once("downto") {
+                        let (a, b): (i64, i64) = (parse_number(a)?, parse_number(b)?);
+                        (b..=a).rev().collect()
+                    } else if let Some((a, b)) = range.split_once("to") {
+                        let (a, b): (i64, i64) = (parse_number(a)?, parse_number(b)?);
+                        (a..=b).collect()
+                    } else {
+                        return Err(invalid(format!("Invalid range {}", range)));
+                    }
+                }
+                None => Vec::new(),
+            };
+            for name in names.split(',') {
+                self.ports
+                    .insert(name.trim().to_ascii_uppercase(), indices.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_pin_map(&mut self, map: &str) -> io::Result<()> {
+        for entry in split_top_level(map, ',') {
+            let (port, pins) = match entry.split_once(':') {
+                Some(x) => x,
+                None if entry.trim().is_empty() => continue,
+                None => return Err(invalid(format!("Invalid pin map entry {}", entry))),
+            };
+            let pins = match parenthesized(pins) {
+                Some((_, list)) => list.split(',').map(|x| x.trim().to_string()).collect(),
+                None => vec![pins.trim().to_string()],
+            };
+            self.pin_map.insert(port.trim().to_ascii_uppercase(), pins);
+        }
+        Ok(())
+    }
+
+    fn parse_instructions(&mut self, opcodes: &str) -> io::Result<()> {
+        for entry in split_top_level(opcodes, ',') {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let (name, codes) = parenthesized(entry)
+                .ok_or_else(|| invalid(format!("Invalid instruction {}", entry)))?;
+            let codes: Vec<String> = codes.split(',').map(|x| x.trim().to_string()).collect();
+            if codes.iter().any(|x| x.len() != self.instruction_length) {
+                return Err(invalid(format!("Invalid opcode length for {}", name)));
+            }
+            self.instructions.insert(name.to_ascii_uppercase(), codes);
+        }
+        Ok(())
+    }
+
+    fn parse_boundary_register(&mut self, register: &str) -> io::Result<()> {
+        for entry in split_top_level(register, ',') {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let (number, fields) = parenthesized(entry)
+                .ok_or_else(|| invalid(format!("Invalid boundary cell {}", entry)))?;
+            let fields: Vec<&str> = split_top_level(fields, ',')
+                .into_iter()
+                .map(|x| x.trim())
+                .collect();
+            if fields.len() < 4 {
+                return Err(invalid(format!("Invalid boundary cell {}", entry)));
+            }
+            let bit = |x: &str| match x {
+                "0" => Ok(Some(false)),
+                "1" => Ok(Some(true)),
+                "X" | "x" => Ok(None),
+                x => Err(invalid(format!("Invalid cell value {}", x))),
+            };
+            let port = match fields[1] {
+                "*" => None,
+                x => Some(x.replace(' ', "").to_ascii_uppercase()),
+            };
+            let (control, disable_value) = if fields.len() >= 6 {
+                (Some(parse_number(fields[4])?), bit(fields[5])?)
+            } else {
+                (None, None)
+            };
+            self.cells.push(BoundaryCell {
+                number: parse_number(number)?,
+                cell_type: fields[0].to_string(),
+                port,
+                function: CellFunction::parse(fields[2])?,
+                safe: bit(fields[3])?,
+                control,
+                disable_value,
+            });
+        }
+        self.cells.sort_by_key(|x| x.number);
+        if self.cells.iter().any(|x| x.number >= self.boundary_length) {
+            return Err(invalid("Boundary cell exceeds the boundary length".into()));
+        }
+        if self
+            .cells
+            .iter()
+            .any(|x| x.control.is_some_and(|x| x >= self.boundary_length))
+        {
+            return Err(invalid("Control cell exceeds the boundary length".into()));
+        }
+        Ok(())
+    }
+
+    /// Opcode of the instruction `name` as bits in shift order (LSB first).
+    pub fn opcode(&self, name: &str) -> Option<Vec<bool>> {
+        let code = self.instructions.get(&name.to_ascii_uppercase())?.first()?;
+        Some(code.chars().rev().map(|x| x == '1').collect())
+    }
+
+    /// Resolve a port name like `D(3)` or a package pin like `A7` to a port name.
+    pub fn resolve_pin(&self, name: &str) -> Option<String> {
+        let upper = name.replace(' ', "").to_ascii_uppercase();
+        if self.cells.iter().any(|x| x.port.as_ref() == Some(&upper)) {
+            return Some(upper);
+        }
+        for (port, pins) in &self.pin_map {
+            let idx = match pins.iter().position(|x| x.eq_ignore_ascii_case(name)) {
+                Some(x) => x,
+                None => continue,
+            };
+            return match self.ports.get(port) {
+                Some(indices) if !indices.is_empty() => {
+                    Some(format!("{}({})", port, indices.get(idx)?))
+                }
+                _ => Some(port.clone()),
+            };
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TINY: &str = include_str!("../tests/fixtures/tiny.bsdl");
+
+    fn cell(
+        number: usize,
+        port: Option<&str>,
+        function: CellFunction,
+        safe: Option<bool>,
+        control: Option<(usize, bool)>,
+    ) -> BoundaryCell {
+        BoundaryCell {
+            number,
+            cell_type: "BC_1".to_string(),
+            port: port.map(|x| x.to_string()),
+            function,
+            safe,
+            control: control.map(|x| x.0),
+            disable_value: control.map(|x| x.1),
+        }
+    }
+
+    #[test]
+    fn parse_header() {
+        let bsdl = Bsdl::parse(TINY).unwrap();
+        assert_eq!(bsdl.entity, "TINY");
+        assert_eq!(bsdl.instruction_length, 4);
+        assert_eq!(
+            bsdl.idcode.as_deref(),
+            Some("00010000000000000001000000001111")
+        );
+        assert_eq!(bsdl.ports["D"], vec![1, 0]);
+        assert!(bsdl.ports["LED"].is_empty());
+        assert_eq!(bsdl.pin_map["D"], vec!["5", "6"]);
+        assert_eq!(bsdl.pin_map["LED"], vec!["8"]);
+    }
+
+    #[test]
+    fn opcodes_in_shift_order() {
+        let bsdl = Bsdl::parse(TINY).unwrap();
+        assert_eq!(bsdl.instructions["SAMPLE"], vec!["0010", "1010"]);
+        // the first opcode is used, its LSB is shifted first
+        assert_eq!(bsdl.opcode("sample"), Some(vec![false, true, false, false]));
+        assert_eq!(bsdl.opcode("IDCODE"), Some(vec![true, false, false, false]));
+        assert_eq!(bsdl.opcode("HIGHZ"), None);
+    }
+
+    #[test]
+    fn boundary_register_ordering() {
+        let bsdl = Bsdl::parse(TINY).unwrap();
+        assert_eq!(bsdl.boundary_length, 7);
+        // sorted by cell number, such that the index is the distance from TDO
+        assert_eq!(
+            bsdl.cells,
+            vec![
+                cell(0, None, CellFunction::Control, Some(false), None),
+                cell(1, Some("LED"), CellFunction::Output3, None, Some((0, true))),
+                cell(2, None, CellFunction::Internal, Some(false), None),
+                cell(3, Some("D(0)"), CellFunction::Bidir, None, Some((5, true))),
+                cell(4, Some("D(1)"), CellFunction::Bidir, None, Some((5, true))),
+                cell(5, None, CellFunction::Control, Some(true), None),
+                cell(6, Some("CLK"), CellFunction::Input, None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_pins() {
+        let bsdl = Bsdl::parse(TINY).unwrap();
+        assert_eq!(bsdl.resolve_pin("d(1)").as_deref(), Some("D(1)"));
+        // D is declared `1 downto 0`, so the first pin maps to index 1
+        assert_eq!(bsdl.resolve_pin("5").as_deref(), Some("D(1)"));
+        assert_eq!(bsdl.resolve_pin("6").as_deref(), Some("D(0)"));
+        assert_eq!(bsdl.resolve_pin("8").as_deref(), Some("LED"));
+        assert_eq!(bsdl.resolve_pin("99"), None);
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        let short = TINY.replace(
+            "BOUNDARY_LENGTH of TINY : entity is 7",
+            "BOUNDARY_LENGTH of TINY : entity is 6",
+        );
+        assert!(Bsdl::parse(&short).is_err());
+        let opcode = TINY.replace("EXTEST (0000)", "EXTEST (000)");
+        assert!(Bsdl::parse(&opcode).is_err());
+        let control = TINY.replace("D(1), bidir, X, 5, 1, Z", "D(1), bidir, X, 7, 1, Z");
+        assert!(Bsdl::parse(&control).is_err());
+        let function = TINY.replace("internal", "sideways");
+        assert!(Bsdl::parse(&function).is_err());
+        assert!(Bsdl::parse("-- nothing").is_err());
+    }
+}
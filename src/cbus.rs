@@ -0,0 +1,182 @@
+//! GPIO control of the CBUS pins using CBUS bit-bang mode.
+//!
+//! CBUS bit-bang mode leaves the UART untouched, so the byte stream of the [`Ftdi`]
+//! keeps working while the pins are toggled. A pin can only be used as GPIO if
+//! it is configured as `IOMODE` in the EEPROM of the chip.
+//!
+//! On the FT232H the four GPIOs are ACBUS5, ACBUS6, ACBUS8 and ACBUS9.
+
+use std::io;
+use std::mem;
+
+use libftd2xx::{DeviceType, Ftdi as FtdiBase, FtdiCommon};
+use libftd2xx_ffi::{
+    FT_DEVICE_232H, FT_DEVICE_232R, FT_DEVICE_X_SERIES, FT_EEPROM_232H, FT_EEPROM_232R,
+    FT_EEPROM_X_SERIES,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::eeprom::read_raw;
+use crate::{request, status_to_io_error, BitMode, Command, Ftdi, PinDirection};
+
+const FT_232R_CBUS_IOMODE: u8 = 0x0A;
+const FT_232H_CBUS_IOMODE: u8 = 0x08;
+const FT_X_SERIES_CBUS_IOMODE: u8 = 0x08;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CbusPin {
+    Cbus0,
+    Cbus1,
+    Cbus2,
+    Cbus3,
+}
+
+impl CbusPin {
+    pub const ALL: [CbusPin; 4] = [
+        CbusPin::Cbus0,
+        CbusPin::Cbus1,
+        CbusPin::Cbus2,
+        CbusPin::Cbus3,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn mask(self) -> u8 {
+        1 << self.index()
+    }
+}
+
+/// Determine which CBUS bit-bang pins are configured as `IOMODE` in the EEPROM.
+pub(crate) fn iomode_pins(device: &mut FtdiBase) -> io::Result<[bool; 4]> {
+    let device_type = device.device_type().map_err(status_to_io_error)?;
+    let functions = match device_type {
+        DeviceType::FT232R => {
+            let mut eeprom: FT_EEPROM_232R = unsafe { mem::zeroed() };
+            eeprom.common.deviceType = FT_DEVICE_232R as _;
+            read_raw(device, &mut eeprom)?;
+            [eeprom.Cbus0, eeprom.Cbus1, eeprom.Cbus2, eeprom.Cbus3]
+                .map(|x| x == FT_232R_CBUS_IOMODE)
+        }
+        DeviceType::FT232H => {
+            let mut eeprom: FT_EEPROM_232H = unsafe { mem::zeroed() };
+            eeprom.common.deviceType = FT_DEVICE_232H as _;
+            read_raw(device, &mut eeprom)?;
+            [eeprom.Cbus5, eeprom.Cbus6, eeprom.Cbus8, eeprom.Cbus9]
+                .map(|x| x == FT_232H_CBUS_IOMODE)
+        }
+        DeviceType::FT_X_SERIES => {
+            let mut eeprom: FT_EEPROM_X_SERIES = unsafe { mem::zeroed() };
+            eeprom.common.deviceType = FT_DEVICE_X_SERIES as _;
+            read_raw(device, &mut eeprom)?;
+            [eeprom.Cbus0, eeprom.Cbus1, eeprom.Cbus2, eeprom.Cbus3]
+                .map(|x| x == FT_X_SERIES_CBUS_IOMODE)
+        }
+        x => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("CBUS bit-bang mode is not supported by {:?}", x),
+            ))
+        }
+    };
+    Ok(functions)
+}
+
+/// Handle to the CBUS GPIOs of a device, obtained from [`Ftdi::cbus_gpio`].
+///
+/// The handle stays usable while the UART stream of the [`Ftdi`] is read and written.
+#[derive(Debug)]
+pub struct CbusGpio {
+    command_tx: UnboundedSender<Command>,
+    available: [bool; 4],
+    direction: u8,
+    level: u8,
+}
+
+impl CbusGpio {
+    pub(crate) async fn new(command_tx: UnboundedSender<Command>) -> io::Result<Self> {
+        let available = request(&command_tx, |answer| Command::CbusIoModePins { answer }).await?;
+        if !available.iter().any(|x| *x) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "None of the CBUS pins is configured as IOMODE in the EEPROM",
+            ));
+        }
+        let this = Self {
+            command_tx,
+            available,
+            direction: 0,
+            level: 0,
+        };
+        this.apply().await?;
+        Ok(this)
+    }
+
+    /// Pins which are configured as `IOMODE` and can thus be used as GPIO.
+    pub fn available_pins(&self) -> Vec<CbusPin> {
+        CbusPin::ALL
+            .into_iter()
+            .filter(|x| self.available[x.index()])
+            .collect()
+    }
+
+    pub async fn set_direction(&mut self, pin: CbusPin, direction: PinDirection) -> io::Result<()> {
+        self.check_available(pin)?;
+        match direction {
+            PinDirection::Input => self.direction &= !pin.mask(),
+            PinDirection::Output => self.direction |= pin.mask(),
+        }
+        self.apply().await
+    }
+
+    /// Set the output level of `pin`, which only takes effect while it is an output.
+    pub async fn set_level(&mut self, pin: CbusPin, high: bool) -> io::Result<()> {
+        self.check_available(pin)?;
+        if high {
+            self.level |= pin.mask();
+        } else {
+            self.level &= !pin.mask();
+        }
+        self.apply().await
+    }
+
+    pub async fn get_level(&self, pin: CbusPin) -> io::Result<bool> {
+        self.check_available(pin)?;
+        Ok(self.read().await? & pin.mask() != 0)
+    }
+
+    /// Read the current level of all four pins, CBUS0 in bit 0.
+    pub async fn read(&self) -> io::Result<u8> {
+        let pins = request(&self.command_tx, |answer| Command::ReadPins { answer }).await?;
+        Ok(pins & 0x0F)
+    }
+
+    fn check_available(&self, pin: CbusPin) -> io::Result<()> {
+        if self.available[pin.index()] {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{:?} is not configured as IOMODE in the EEPROM", pin),
+            ))
+        }
+    }
+
+    async fn apply(&self) -> io::Result<()> {
+        let mask = (self.direction << 4) | (self.level & 0x0F);
+        request(&self.command_tx, |answer| Command::SetBitMode {
+            mask,
+            mode: BitMode::CbusBitbang,
+            answer,
+        })
+        .await
+    }
+}
+
+impl Ftdi {
+    /// Use the CBUS pins as GPIO, all pins start out as inputs.
+    pub async fn cbus_gpio(&self) -> io::Result<CbusGpio> {
+        CbusGpio::new(self.command_tx.clone()).await
+    }
+}
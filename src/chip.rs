@@ -0,0 +1,221 @@
+//! Grouping the channels of multi-channel chips like the FT2232H and FT4232H.
+//!
+//! The D2XX library lists every channel as a separate device. Channels of one chip share
+//! the serial number and description up to a channel suffix `A` to `D` and their location
+//! IDs only differ in the lowest nibble.
+
+use std::io;
+
+use tokio::task::spawn_blocking;
+
+use crate::selector::{base_identity, channel, list_located, LocatedDevice, OpenTarget};
+use crate::{ChipType, DeviceInfo, Ftdi, SerialParams};
+
+#[derive(Debug, Clone)]
+pub struct ChannelInfo {
+    /// Channel letter, `'A'` for single channel chips.
+    pub channel: char,
+    pub location_id: u32,
+    pub info: DeviceInfo,
+}
+
+impl ChannelInfo {
+    /// Whether the channel has an MPSSE engine, e.g. only A and B on the FT4232H.
+    pub fn supports_mpsse(&self) -> bool {
+        match self.info.chip_type {
+            ChipType::Ft232H | ChipType::Ft2232H => true,
+            ChipType::Ft4232H => matches!(self.channel, 'A' | 'B'),
+            _ => false,
+        }
+    }
+}
+
+/// A physical chip with all of its channels.
+#[derive(Debug, Clone)]
+pub struct ChipInfo {
+    pub chip_type: ChipType,
+    /// Serial number without the channel suffix.
+    pub serial_number: String,
+    /// Description without the channel suffix.
+    pub description: String,
+    /// Channels sorted by their letter.
+    pub channels: Vec<ChannelInfo>,
+}
+
+impl ChipInfo {
+    pub fn channel(&self, channel: char) -> Option<&ChannelInfo> {
+        let channel = channel.to_ascii_uppercase();
+        self.channels.iter().find(|x| x.channel == channel)
+    }
+}
+
+fn group(devices: Vec<LocatedDevice>) -> Vec<ChipInfo> {
+    let mut chips: Vec<(u32, ChipInfo)> = Vec::new();
+    for device in devices {
+        let (serial_number, description) = base_identity(&device.info);
+        let (serial_number, description) = (serial_number.to_string(), description.to_string());
+        let channel = channel(&device.info);
+        let parent = device.location_id >> 4;
+        let existing = chips.iter_mut().find(|(location, chip)| {
+            *location == parent
+                && chip.chip_type == device.info.chip_type
+                && chip.serial_number == serial_number
+                && channel.is_some_and(|x| chip.channel(x).is_none())
+        });
+        let info = ChannelInfo {
+            channel: channel.unwrap_or('A'),
+            location_id: device.location_id,
+            info: device.info,
+        };
+        match existing {
+            Some((_, chip)) => chip.channels.push(info),
+            None => chips.push((
+                parent,
+                ChipInfo {
+                    chip_type: info.info.chip_type,
+                    serial_number,
+                    description,
+                    channels: vec![info],
+                },
+            )),
+        }
+    }
+    chips
+        .into_iter()
+        .map(|(_, mut chip)| {
+            chip.channels.sort_by_key(|x| x.channel);
+            chip
+        })
+        .collect()
+}
+
+/// Handle to a chip from which its channels are opened independently.
+///
+/// Every channel is a separate [`Ftdi`], so e.g. channel A can run MPSSE JTAG while
+/// channel B is used as UART.
+#[derive(Debug, Clone)]
+pub struct ChipHandle {
+    chip: ChipInfo,
+}
+
+impl ChipHandle {
+    pub fn new(chip: ChipInfo) -> Self {
+        Self { chip }
+    }
+
+    pub fn info(&self) -> &ChipInfo {
+        &self.chip
+    }
+
+    /// Open `channel` by its location.
+    pub async fn open_channel(&self, channel: char, params: &SerialParams) -> io::Result<Ftdi> {
+        let info = self.chip.channel(channel).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Chip {} has no channel {}",
+                    self.chip.serial_number, channel
+                ),
+            )
+        })?;
+        Ftdi::open_target(OpenTarget::Location(info.location_id), params).await
+    }
+}
+
+impl Ftdi {
+    /// List all chips, channels of multi-channel chips are grouped together.
+    pub async fn list_chips() -> io::Result<Vec<ChipInfo>> {
+        spawn_blocking(|| list_located().map(group)).await.unwrap()
+    }
+
+    /// Find the chip with the serial number `serial_number`, without channel suffix.
+    pub async fn open_chip(serial_number: &str) -> io::Result<ChipHandle> {
+        Self::list_chips()
+            .await?
+            .into_iter()
+            .find(|x| x.serial_number == serial_number)
+            .map(ChipHandle::new)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No chip with serial number {}", serial_number),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(
+        chip_type: ChipType,
+        serial: &str,
+        description: &str,
+        location_id: u32,
+    ) -> LocatedDevice {
+        LocatedDevice {
+            index: 0,
+            info: DeviceInfo {
+                port_open: false,
+                chip_type,
+                vendor_id: 0x0403,
+                product_id: 0x6010,
+                serial_number: serial.to_string(),
+                description: description.to_string(),
+            },
+            location_id,
+        }
+    }
+
+    fn channels(chip: &ChipInfo) -> Vec<(char, u32)> {
+        chip.channels
+            .iter()
+            .map(|x| (x.channel, x.location_id))
+            .collect()
+    }
+
+    #[test]
+    fn group_blank_serials_by_parent() {
+        let chips = group(vec![
+            device(ChipType::Ft2232H, "", "Dual RS232-HS A", 0x1011),
+            device(ChipType::Ft2232H, "", "Dual RS232-HS A", 0x2011),
+            device(ChipType::Ft2232H, "", "Dual RS232-HS B", 0x2012),
+            device(ChipType::Ft2232H, "", "Dual RS232-HS B", 0x1012),
+        ]);
+        assert_eq!(chips.len(), 2);
+        for chip in &chips {
+            assert_eq!(chip.serial_number, "");
+            assert_eq!(chip.description, "Dual RS232-HS");
+        }
+        assert_eq!(channels(&chips[0]), [('A', 0x1011), ('B', 0x1012)]);
+        assert_eq!(channels(&chips[1]), [('A', 0x2011), ('B', 0x2012)]);
+    }
+
+    #[test]
+    fn group_channels_out_of_order() {
+        let chips = group(vec![
+            device(ChipType::Ft4232H, "FT9876D", "Quad RS232-HS D", 0x1014),
+            device(ChipType::Ft232H, "FT1111", "Single RS232-HS", 0x1020),
+            device(ChipType::Ft4232H, "FT9876B", "Quad RS232-HS B", 0x1012),
+            device(ChipType::Ft4232H, "FT9876A", "Quad RS232-HS A", 0x1011),
+            device(ChipType::Ft4232H, "FT9876C", "Quad RS232-HS C", 0x1013),
+        ]);
+        assert_eq!(chips.len(), 2);
+
+        let quad = &chips[0];
+        assert_eq!(quad.chip_type, ChipType::Ft4232H);
+        assert_eq!(quad.serial_number, "FT9876");
+        assert_eq!(quad.description, "Quad RS232-HS");
+        assert_eq!(
+            channels(quad),
+            [('A', 0x1011), ('B', 0x1012), ('C', 0x1013), ('D', 0x1014)]
+        );
+        assert!(quad.channel('b').unwrap().supports_mpsse());
+        assert!(!quad.channel('C').unwrap().supports_mpsse());
+
+        let single = &chips[1];
+        assert_eq!(single.serial_number, "FT1111");
+        assert_eq!(channels(single), [('A', 0x1020)]);
+    }
+}
@@ -0,0 +1,136 @@
+//! Device information and status codes owned by this crate.
+//!
+//! These mirror the types of `libftd2xx`, such that upgrading it does not change the
+//! public API of this crate.
+
+use std::error::Error;
+use std::fmt;
+
+use libftd2xx::{DeviceType, FtStatus};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ChipType {
+    Ft232R,
+    Ft232H,
+    Ft2232H,
+    Ft4232H,
+    FtXSeries,
+    /// Any other chip, not specifically supported by this crate.
+    Other,
+}
+
+impl ChipType {
+    /// Whether the chip shows up as several devices, one per channel.
+    pub fn is_multi_channel(self) -> bool {
+        matches!(self, ChipType::Ft2232H | ChipType::Ft4232H)
+    }
+
+    pub(crate) fn from_d2xx(x: DeviceType) -> Self {
+        match x {
+            DeviceType::FT232R => ChipType::Ft232R,
+            DeviceType::FT232H => ChipType::Ft232H,
+            DeviceType::FT2232H => ChipType::Ft2232H,
+            DeviceType::FT4232H => ChipType::Ft4232H,
+            DeviceType::FT_X_SERIES => ChipType::FtXSeries,
+            _ => ChipType::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    /// Whether the device is already opened, by this or another process.
+    pub port_open: bool,
+    pub chip_type: ChipType,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial_number: String,
+    pub description: String,
+}
+
+impl DeviceInfo {
+    pub(crate) fn from_d2xx(x: libftd2xx::DeviceInfo) -> Self {
+        Self {
+            port_open: x.port_open,
+            chip_type: ChipType::from_d2xx(x.device_type),
+            vendor_id: x.vendor_id,
+            product_id: x.product_id,
+            serial_number: x.serial_number,
+            description: x.description,
+        }
+    }
+}
+
+/// Status reported by the D2XX library, carried as the inner error of [`std::io::Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Status {
+    InvalidHandle,
+    DeviceNotFound,
+    DeviceNotOpened,
+    IoError,
+    InsufficientResources,
+    InvalidParameter,
+    InvalidBaudRate,
+    EepromReadFailed,
+    EepromWriteFailed,
+    EepromNotPresent,
+    EepromNotProgrammed,
+    NotSupported,
+    /// Any other status, described by the library.
+    Other(String),
+}
+
+impl Status {
+    /// Statuses reported once the device vanished from the bus.
+    pub fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            Status::IoError | Status::InvalidHandle | Status::DeviceNotOpened
+        )
+    }
+
+    pub(crate) fn from_d2xx(x: FtStatus) -> Self {
+        match x {
+            FtStatus::INVALID_HANDLE => Status::InvalidHandle,
+            FtStatus::DEVICE_NOT_FOUND => Status::DeviceNotFound,
+            FtStatus::DEVICE_NOT_OPENED => Status::DeviceNotOpened,
+            FtStatus::IO_ERROR => Status::IoError,
+            FtStatus::INSUFFICIENT_RESOURCES => Status::InsufficientResources,
+            FtStatus::INVALID_PARAMETER => Status::InvalidParameter,
+            FtStatus::INVALID_BAUD_RATE => Status::InvalidBaudRate,
+            FtStatus::EEPROM_READ_FAILED => Status::EepromReadFailed,
+            FtStatus::EEPROM_WRITE_FAILED => Status::EepromWriteFailed,
+            FtStatus::EEPROM_NOT_PRESENT => Status::EepromNotPresent,
+            FtStatus::EEPROM_NOT_PROGRAMMED => Status::EepromNotProgrammed,
+            FtStatus::NOT_SUPPORTED => Status::NotSupported,
+            x => Status::Other(x.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Status::InvalidHandle => "Invalid handle",
+            Status::DeviceNotFound => "Device not found",
+            Status::DeviceNotOpened => "Device not opened",
+            Status::IoError => "I/O error",
+            Status::InsufficientResources => "Insufficient resources",
+            Status::InvalidParameter => "Invalid parameter",
+            Status::InvalidBaudRate => "Invalid baud rate",
+            Status::EepromReadFailed => "EEPROM read failed",
+            Status::EepromWriteFailed => "EEPROM write failed",
+            Status::EepromNotPresent => "EEPROM not present",
+            Status::EepromNotProgrammed => "EEPROM not programmed",
+            Status::NotSupported => "Not supported",
+            Status::Other(x) => x,
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl Error for Status {}
@@ -0,0 +1,848 @@
+//! Reading and programming the EEPROM of the supported chips.
+//!
+//! The configuration is read with [`Ftdi::eeprom_read`] into a typed [`EepromConfig`],
+//! which can be modified, saved to and loaded from JSON files, and written back with
+//! [`Ftdi::eeprom_program`]. Programming reads the EEPROM back and fails if the contents
+//! differ from the requested configuration.
+
+use std::ffi::{c_void, CString};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::raw::c_char;
+use std::path::Path;
+
+use libftd2xx::{DeviceType, FtStatus, Ftdi as FtdiBase, FtdiCommon};
+use libftd2xx_ffi::{
+    FT_EEPROM_Program, FT_EEPROM_Read, FT_DEVICE_2232H, FT_DEVICE_232H, FT_DEVICE_232R,
+    FT_DEVICE_4232H, FT_DEVICE_X_SERIES, FT_EEPROM_2232H, FT_EEPROM_232H, FT_EEPROM_232R,
+    FT_EEPROM_4232H, FT_EEPROM_HEADER, FT_EEPROM_X_SERIES, FT_STATUS,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{request, status_to_io_error, Command, Ftdi, Handler};
+
+/// Size of the string buffers handed to `FT_EEPROM_Read`.
+const STRING_BUFFER_LEN: usize = 64;
+
+/// Maximum current which can be requested from the USB host.
+const MAX_POWER_MA: u16 = 500;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RawStrings {
+    pub(crate) manufacturer: String,
+    pub(crate) manufacturer_id: String,
+    pub(crate) description: String,
+    pub(crate) serial_number: String,
+}
+
+/// Read the EEPROM into `eeprom`, one of the `FT_EEPROM_*` structures.
+///
+/// The `deviceType` field of the structure header must be set by the caller.
+pub(crate) fn read_raw<T>(device: &mut FtdiBase, eeprom: &mut T) -> io::Result<RawStrings> {
+    let mut manufacturer = [0 as c_char; STRING_BUFFER_LEN];
+    let mut manufacturer_id = [0 as c_char; STRING_BUFFER_LEN];
+    let mut description = [0 as c_char; STRING_BUFFER_LEN];
+    let mut serial_number = [0 as c_char; STRING_BUFFER_LEN];
+    let status: FT_STATUS = unsafe {
+        FT_EEPROM_Read(
+            device.handle(),
+            eeprom as *mut T as *mut c_void,
+            mem::size_of::<T>() as _,
+            manufacturer.as_mut_ptr(),
+            manufacturer_id.as_mut_ptr(),
+            description.as_mut_ptr(),
+            serial_number.as_mut_ptr(),
+        )
+    };
+    if status != 0 {
+        return Err(status_to_io_error(FtStatus::from(status)));
+    }
+    Ok(RawStrings {
+        manufacturer: c_string(&manufacturer),
+        manufacturer_id: c_string(&manufacturer_id),
+        description: c_string(&description),
+        serial_number: c_string(&serial_number),
+    })
+}
+
+/// Program the EEPROM from `eeprom`, one of the `FT_EEPROM_*` structures.
+pub(crate) fn program_raw<T>(
+    device: &mut FtdiBase,
+    eeprom: &mut T,
+    strings: &RawStrings,
+) -> io::Result<()> {
+    let string = |x: &str| {
+        CString::new(x).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "EEPROM strings must not contain NUL characters",
+            )
+        })
+    };
+    let manufacturer = string(&strings.manufacturer)?;
+    let manufacturer_id = string(&strings.manufacturer_id)?;
+    let description = string(&strings.description)?;
+    let serial_number = string(&strings.serial_number)?;
+    let status: FT_STATUS = unsafe {
+        FT_EEPROM_Program(
+            device.handle(),
+            eeprom as *mut T as *mut c_void,
+            mem::size_of::<T>() as _,
+            manufacturer.as_ptr() as *mut c_char,
+            manufacturer_id.as_ptr() as *mut c_char,
+            description.as_ptr() as *mut c_char,
+            serial_number.as_ptr() as *mut c_char,
+        )
+    };
+    if status != 0 {
+        return Err(status_to_io_error(FtStatus::from(status)));
+    }
+    Ok(())
+}
+
+fn c_string(buf: &[c_char]) -> String {
+    let bytes: Vec<u8> = buf
+        .iter()
+        .take_while(|x| **x != 0)
+        .map(|x| *x as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn invalid_value(field: &str, value: u8) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid EEPROM value 0x{:02x} for {}", value, field),
+    )
+}
+
+macro_rules! raw_enum {
+    ($(#[$meta:meta])* $name:ident { $($(#[$variant_meta:meta])* $variant:ident = $value:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+        #[serde(rename_all = "snake_case")]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+        }
+
+        impl $name {
+            fn from_raw(x: u8) -> io::Result<Self> {
+                match x {
+                    $($value => Ok($name::$variant),)*
+                    x => Err(invalid_value(stringify!($name), x)),
+                }
+            }
+
+            fn to_raw(self) -> u8 {
+                match self {
+                    $($name::$variant => $value,)*
+                }
+            }
+        }
+    };
+}
+
+raw_enum!(
+    /// Driver loaded by Windows for a channel.
+    DriverType {
+        D2xx = 0,
+        Vcp = 1,
+    }
+);
+
+raw_enum!(
+    DriveCurrent {
+        Ma4 = 4,
+        Ma8 = 8,
+        Ma12 = 12,
+        Ma16 = 16,
+    }
+);
+
+raw_enum!(
+    /// Function of a CBUS pin of the FT232R.
+    Cbus232r {
+        TxdEnable = 0,
+        PowerOn = 1,
+        RxLed = 2,
+        TxLed = 3,
+        TxRxLed = 4,
+        Sleep = 5,
+        Clk48 = 6,
+        Clk24 = 7,
+        Clk12 = 8,
+        Clk6 = 9,
+        IoMode = 10,
+        BitbangWr = 11,
+        BitbangRd = 12,
+    }
+);
+
+raw_enum!(
+    /// Function of a CBUS pin of the FT232H.
+    Cbus232h {
+        Tristate = 0,
+        TxLed = 1,
+        RxLed = 2,
+        TxRxLed = 3,
+        PowerEnable = 4,
+        Sleep = 5,
+        Drive0 = 6,
+        Drive1 = 7,
+        IoMode = 8,
+        TxdEnable = 9,
+        Clk30 = 10,
+        Clk15 = 11,
+        Clk7_5 = 12,
+    }
+);
+
+raw_enum!(
+    /// Function of a CBUS pin of the FT-X series.
+    CbusX {
+        Tristate = 0,
+        TxLed = 1,
+        RxLed = 2,
+        TxRxLed = 3,
+        PowerEnable = 4,
+        Sleep = 5,
+        Drive0 = 6,
+        Drive1 = 7,
+        IoMode = 8,
+        TxdEnable = 9,
+        Clk24 = 10,
+        Clk12 = 11,
+        Clk6 = 12,
+        BcdCharger = 13,
+        BcdChargerN = 14,
+        I2cTxe = 15,
+        I2cRxf = 16,
+        VbusSense = 17,
+        BitbangWr = 18,
+        BitbangRd = 19,
+        Timestamp = 20,
+        KeepAwake = 21,
+    }
+);
+
+/// Electrical configuration of a group of pins.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct PinGroup {
+    pub slow_slew: bool,
+    pub schmitt_input: bool,
+    pub drive_current: DriveCurrent,
+}
+
+impl PinGroup {
+    fn from_raw(slow_slew: u8, schmitt_input: u8, drive_current: u8) -> io::Result<Self> {
+        Ok(Self {
+            slow_slew: slow_slew != 0,
+            schmitt_input: schmitt_input != 0,
+            drive_current: DriveCurrent::from_raw(drive_current)?,
+        })
+    }
+}
+
+/// Inverted UART signals of the FT232R and FT-X series.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SignalInversion {
+    pub txd: bool,
+    pub rxd: bool,
+    pub rts: bool,
+    pub cts: bool,
+    pub dtr: bool,
+    pub dsr: bool,
+    pub dcd: bool,
+    pub ri: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelType {
+    Uart,
+    /// 245 FIFO mode, required for [`Ftdi::into_sync_fifo`].
+    Fifo,
+    /// CPU style FIFO mode.
+    CpuFifo,
+    FastSerial,
+    Ft1248,
+}
+
+impl ChannelType {
+    fn from_raw(fifo: u8, fifo_tar: u8, fast_serial: u8, ft1248: u8) -> Self {
+        if fifo != 0 {
+            ChannelType::Fifo
+        } else if fifo_tar != 0 {
+            ChannelType::CpuFifo
+        } else if fast_serial != 0 {
+            ChannelType::FastSerial
+        } else if ft1248 != 0 {
+            ChannelType::Ft1248
+        } else {
+            ChannelType::Uart
+        }
+    }
+
+    /// Flags in the order fifo, fifo_tar, fast_serial, ft1248.
+    fn to_raw(self) -> [u8; 4] {
+        match self {
+            ChannelType::Uart => [0, 0, 0, 0],
+            ChannelType::Fifo => [1, 0, 0, 0],
+            ChannelType::CpuFifo => [0, 1, 0, 0],
+            ChannelType::FastSerial => [0, 0, 1, 0],
+            ChannelType::Ft1248 => [0, 0, 0, 1],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Ft1248Config {
+    pub clock_polarity_high: bool,
+    pub lsb_first: bool,
+    pub flow_control: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Ft232rEeprom {
+    pub high_current_io: bool,
+    pub external_oscillator: bool,
+    pub invert: SignalInversion,
+    pub cbus: [Cbus232r; 5],
+    pub driver: DriverType,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Ft232hEeprom {
+    pub acbus: PinGroup,
+    pub adbus: PinGroup,
+    /// Functions of ACBUS0 to ACBUS9.
+    pub cbus: [Cbus232h; 10],
+    pub ft1248: Ft1248Config,
+    pub channel: ChannelType,
+    pub power_save: bool,
+    pub driver: DriverType,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Ft2232hEeprom {
+    pub al: PinGroup,
+    pub ah: PinGroup,
+    pub bl: PinGroup,
+    pub bh: PinGroup,
+    pub channel_a: ChannelType,
+    pub channel_b: ChannelType,
+    pub power_save: bool,
+    pub driver_a: DriverType,
+    pub driver_b: DriverType,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Ft4232hEeprom {
+    /// Pin groups of channels A to D.
+    pub pins: [PinGroup; 4],
+    /// Whether RI of channels A to D is used as TXDEN for RS485.
+    pub ri_is_txden: [bool; 4],
+    pub drivers: [DriverType; 4],
+}
+
+/// Battery charger detection of the FT-X series.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BcdConfig {
+    pub enable: bool,
+    pub force_cbus_power_enable: bool,
+    pub disable_sleep: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct FtXEeprom {
+    pub cbus_pins: PinGroup,
+    pub dbus_pins: PinGroup,
+    pub cbus: [CbusX; 7],
+    pub invert: SignalInversion,
+    pub bcd: BcdConfig,
+    pub i2c_slave_address: u16,
+    pub i2c_device_id: u32,
+    pub i2c_disable_schmitt: bool,
+    pub ft1248: Ft1248Config,
+    pub rs485_echo_suppress: bool,
+    pub power_save: bool,
+    pub driver: DriverType,
+}
+
+/// Chip specific part of the EEPROM configuration.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(tag = "chip", rename_all = "snake_case")]
+pub enum ChipEeprom {
+    Ft232r(Ft232rEeprom),
+    Ft232h(Ft232hEeprom),
+    Ft2232h(Ft2232hEeprom),
+    Ft4232h(Ft4232hEeprom),
+    FtX(FtXEeprom),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct EepromConfig {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: String,
+    pub manufacturer_id: String,
+    pub description: String,
+    pub serial_number: String,
+    pub serial_number_enable: bool,
+    /// Current drawn from the bus in mA.
+    pub max_power: u16,
+    pub self_powered: bool,
+    pub remote_wakeup: bool,
+    /// Pull down the IO pins in USB suspend.
+    pub pull_down_enable: bool,
+    pub chip: ChipEeprom,
+}
+
+impl EepromConfig {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Dump the configuration as JSON to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    /// Load a configuration previously written by [`EepromConfig::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    fn header_from_raw(header: &FT_EEPROM_HEADER, strings: RawStrings, chip: ChipEeprom) -> Self {
+        Self {
+            vendor_id: header.VendorId,
+            product_id: header.ProductId,
+            manufacturer: strings.manufacturer,
+            manufacturer_id: strings.manufacturer_id,
+            description: strings.description,
+            serial_number: strings.serial_number,
+            serial_number_enable: header.SerNumEnable != 0,
+            max_power: header.MaxPower,
+            self_powered: header.SelfPowered != 0,
+            remote_wakeup: header.RemoteWakeup != 0,
+            pull_down_enable: header.PullDownEnable != 0,
+            chip,
+        }
+    }
+
+    fn header_to_raw(&self, header: &mut FT_EEPROM_HEADER) {
+        header.VendorId = self.vendor_id;
+        header.ProductId = self.product_id;
+        header.SerNumEnable = self.serial_number_enable as _;
+        header.MaxPower = self.max_power;
+        header.SelfPowered = self.self_powered as _;
+        header.RemoteWakeup = self.remote_wakeup as _;
+        header.PullDownEnable = self.pull_down_enable as _;
+    }
+
+    fn strings(&self) -> RawStrings {
+        RawStrings {
+            manufacturer: self.manufacturer.clone(),
+            manufacturer_id: self.manufacturer_id.clone(),
+            description: self.description.clone(),
+            serial_number: self.serial_number.clone(),
+        }
+    }
+}
+
+impl Ftdi {
+    /// Read and decode the EEPROM of the device.
+    pub async fn eeprom_read(&self) -> io::Result<EepromConfig> {
+        request(&self.command_tx, |answer| Command::EepromRead { answer }).await
+    }
+
+    /// Program the EEPROM with `config` and verify it by reading it back.
+    ///
+    /// The chip type of `config` must match the device. Most changes only take effect
+    /// after the device was re-enumerated.
+    pub async fn eeprom_program(&self, config: EepromConfig) -> io::Result<()> {
+        request(&self.command_tx, |answer| Command::EepromProgram {
+            config,
+            answer,
+        })
+        .await
+    }
+
+    /// Whether the EEPROM contents equal `config`.
+    pub async fn eeprom_verify(&self, config: &EepromConfig) -> io::Result<bool> {
+        Ok(self.eeprom_read().await? == *config)
+    }
+
+    /// Read the EEPROM and save it as JSON to `path`.
+    pub async fn eeprom_dump(&self, path: impl AsRef<Path>) -> io::Result<EepromConfig> {
+        let config = self.eeprom_read().await?;
+        config.save(path)?;
+        Ok(config)
+    }
+
+    /// Program the EEPROM from a JSON file written by [`Ftdi::eeprom_dump`].
+    pub async fn eeprom_restore(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let config = EepromConfig::load(path)?;
+        self.eeprom_program(config).await
+    }
+}
+
+fn unsupported(device_type: DeviceType) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("EEPROM access is not supported for {:?}", device_type),
+    )
+}
+
+impl Handler {
+    pub(crate) fn eeprom_read(&mut self) -> io::Result<EepromConfig> {
+        let device_type = self.device.device_type().map_err(status_to_io_error)?;
+        let config = match device_type {
+            DeviceType::FT232R => {
+                let mut ee: FT_EEPROM_232R = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_232R as _;
+                let strings = read_raw(&mut self.device, &mut ee)?;
+                let cbus = [ee.Cbus0, ee.Cbus1, ee.Cbus2, ee.Cbus3, ee.Cbus4];
+                let chip = ChipEeprom::Ft232r(Ft232rEeprom {
+                    high_current_io: ee.IsHighCurrent != 0,
+                    external_oscillator: ee.UseExtOsc != 0,
+                    invert: SignalInversion {
+                        txd: ee.InvertTXD != 0,
+                        rxd: ee.InvertRXD != 0,
+                        rts: ee.InvertRTS != 0,
+                        cts: ee.InvertCTS != 0,
+                        dtr: ee.InvertDTR != 0,
+                        dsr: ee.InvertDSR != 0,
+                        dcd: ee.InvertDCD != 0,
+                        ri: ee.InvertRI != 0,
+                    },
+                    cbus: [
+                        Cbus232r::from_raw(cbus[0])?,
+                        Cbus232r::from_raw(cbus[1])?,
+                        Cbus232r::from_raw(cbus[2])?,
+                        Cbus232r::from_raw(cbus[3])?,
+                        Cbus232r::from_raw(cbus[4])?,
+                    ],
+                    driver: DriverType::from_raw(ee.DriverType)?,
+                });
+                EepromConfig::header_from_raw(&ee.common, strings, chip)
+            }
+            DeviceType::FT232H => {
+                let mut ee: FT_EEPROM_232H = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_232H as _;
+                let strings = read_raw(&mut self.device, &mut ee)?;
+                let cbus = [
+                    ee.Cbus0, ee.Cbus1, ee.Cbus2, ee.Cbus3, ee.Cbus4, ee.Cbus5, ee.Cbus6, ee.Cbus7,
+                    ee.Cbus8, ee.Cbus9,
+                ];
+                let mut functions = [Cbus232h::Tristate; 10];
+                for (function, raw) in functions.iter_mut().zip(cbus) {
+                    *function = Cbus232h::from_raw(raw)?;
+                }
+                let chip = ChipEeprom::Ft232h(Ft232hEeprom {
+                    acbus: PinGroup::from_raw(ee.ACSlowSlew, ee.ACSchmittInput, ee.ACDriveCurrent)?,
+                    adbus: PinGroup::from_raw(ee.ADSlowSlew, ee.ADSchmittInput, ee.ADDriveCurrent)?,
+                    cbus: functions,
+                    ft1248: Ft1248Config {
+                        clock_polarity_high: ee.FT1248Cpol != 0,
+                        lsb_first: ee.FT1248Lsb != 0,
+                        flow_control: ee.FT1248FlowControl != 0,
+                    },
+                    channel: ChannelType::from_raw(
+                        ee.IsFifo,
+                        ee.IsFifoTar,
+                        ee.IsFastSer,
+                        ee.IsFT1248,
+                    ),
+                    power_save: ee.PowerSaveEnable != 0,
+                    driver: DriverType::from_raw(ee.DriverType)?,
+                });
+                EepromConfig::header_from_raw(&ee.common, strings, chip)
+            }
+            DeviceType::FT2232H => {
+                let mut ee: FT_EEPROM_2232H = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_2232H as _;
+                let strings = read_raw(&mut self.device, &mut ee)?;
+                let chip = ChipEeprom::Ft2232h(Ft2232hEeprom {
+                    al: PinGroup::from_raw(ee.ALSlowSlew, ee.ALSchmittInput, ee.ALDriveCurrent)?,
+                    ah: PinGroup::from_raw(ee.AHSlowSlew, ee.AHSchmittInput, ee.AHDriveCurrent)?,
+                    bl: PinGroup::from_raw(ee.BLSlowSlew, ee.BLSchmittInput, ee.BLDriveCurrent)?,
+                    bh: PinGroup::from_raw(ee.BHSlowSlew, ee.BHSchmittInput, ee.BHDriveCurrent)?,
+                    channel_a: ChannelType::from_raw(ee.AIsFifo, ee.AIsFifoTar, ee.AIsFastSer, 0),
+                    channel_b: ChannelType::from_raw(ee.BIsFifo, ee.BIsFifoTar, ee.BIsFastSer, 0),
+                    power_save: ee.PowerSaveEnable != 0,
+                    driver_a: DriverType::from_raw(ee.ADriverType)?,
+                    driver_b: DriverType::from_raw(ee.BDriverType)?,
+                });
+                EepromConfig::header_from_raw(&ee.common, strings, chip)
+            }
+            DeviceType::FT4232H => {
+                let mut ee: FT_EEPROM_4232H = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_4232H as _;
+                let strings = read_raw(&mut self.device, &mut ee)?;
+                let chip = ChipEeprom::Ft4232h(Ft4232hEeprom {
+                    pins: [
+                        PinGroup::from_raw(ee.ASlowSlew, ee.ASchmittInput, ee.ADriveCurrent)?,
+                        PinGroup::from_raw(ee.BSlowSlew, ee.BSchmittInput, ee.BDriveCurrent)?,
+                        PinGroup::from_raw(ee.CSlowSlew, ee.CSchmittInput, ee.CDriveCurrent)?,
+                        PinGroup::from_raw(ee.DSlowSlew, ee.DSchmittInput, ee.DDriveCurrent)?,
+                    ],
+                    ri_is_txden: [
+                        ee.ARIIsTXDEN != 0,
+                        ee.BRIIsTXDEN != 0,
+                        ee.CRIIsTXDEN != 0,
+                        ee.DRIIsTXDEN != 0,
+                    ],
+                    drivers: [
+                        DriverType::from_raw(ee.ADriverType)?,
+                        DriverType::from_raw(ee.BDriverType)?,
+                        DriverType::from_raw(ee.CDriverType)?,
+                        DriverType::from_raw(ee.DDriverType)?,
+                    ],
+                });
+                EepromConfig::header_from_raw(&ee.common, strings, chip)
+            }
+            DeviceType::FT_X_SERIES => {
+                let mut ee: FT_EEPROM_X_SERIES = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_X_SERIES as _;
+                let strings = read_raw(&mut self.device, &mut ee)?;
+                let cbus = [
+                    ee.Cbus0, ee.Cbus1, ee.Cbus2, ee.Cbus3, ee.Cbus4, ee.Cbus5, ee.Cbus6,
+                ];
+                let mut functions = [CbusX::Tristate; 7];
+                for (function, raw) in functions.iter_mut().zip(cbus) {
+                    *function = CbusX::from_raw(raw)?;
+                }
+                let chip = ChipEeprom::FtX(FtXEeprom {
+                    cbus_pins: PinGroup::from_raw(
+                        ee.ACSlowSlew,
+                        ee.ACSchmittInput,
+                        ee.ACDriveCurrent,
+                    )?,
+                    dbus_pins: PinGroup::from_raw(
+                        ee.ADSlowSlew,
+                        ee.ADSchmittInput,
+                        ee.ADDriveCurrent,
+                    )?,
+                    cbus: functions,
+                    invert: SignalInversion {
+                        txd: ee.InvertTXD != 0,
+                        rxd: ee.InvertRXD != 0,
+                        rts: ee.InvertRTS != 0,
+                        cts: ee.InvertCTS != 0,
+                        dtr: ee.InvertDTR != 0,
+                        dsr: ee.InvertDSR != 0,
+                        dcd: ee.InvertDCD != 0,
+                        ri: ee.InvertRI != 0,
+                    },
+                    bcd: BcdConfig {
+                        enable: ee.BCDEnable != 0,
+                        force_cbus_power_enable: ee.BCDForceCbusPWREN != 0,
+                        disable_sleep: ee.BCDDisableSleep != 0,
+                    },
+                    i2c_slave_address: ee.I2CSlaveAddress as _,
+                    i2c_device_id: ee.I2CDeviceId as _,
+                    i2c_disable_schmitt: ee.I2CDisableSchmitt != 0,
+                    ft1248: Ft1248Config {
+                        clock_polarity_high: ee.FT1248Cpol != 0,
+                        lsb_first: ee.FT1248Lsb != 0,
+                        flow_control: ee.FT1248FlowControl != 0,
+                    },
+                    rs485_echo_suppress: ee.RS485EchoSuppress != 0,
+                    power_save: ee.PowerSaveEnable != 0,
+                    driver: DriverType::from_raw(ee.DriverType)?,
+                });
+                EepromConfig::header_from_raw(&ee.common, strings, chip)
+            }
+            x => return Err(unsupported(x)),
+        };
+        Ok(config)
+    }
+
+    pub(crate) fn eeprom_program(&mut self, config: &EepromConfig) -> io::Result<()> {
+        if config.max_power > MAX_POWER_MA {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Max power of {} mA exceeds {} mA",
+                    config.max_power, MAX_POWER_MA
+                ),
+            ));
+        }
+        let device_type = self.device.device_type().map_err(status_to_io_error)?;
+        let strings = config.strings();
+        match (&config.chip, device_type) {
+            (ChipEeprom::Ft232r(chip), DeviceType::FT232R) => {
+                let mut ee: FT_EEPROM_232R = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_232R as _;
+                config.header_to_raw(&mut ee.common);
+                ee.IsHighCurrent = chip.high_current_io as _;
+                ee.UseExtOsc = chip.external_oscillator as _;
+                ee.InvertTXD = chip.invert.txd as _;
+                ee.InvertRXD = chip.invert.rxd as _;
+                ee.InvertRTS = chip.invert.rts as _;
+                ee.InvertCTS = chip.invert.cts as _;
+                ee.InvertDTR = chip.invert.dtr as _;
+                ee.InvertDSR = chip.invert.dsr as _;
+                ee.InvertDCD = chip.invert.dcd as _;
+                ee.InvertRI = chip.invert.ri as _;
+                ee.Cbus0 = chip.cbus[0].to_raw();
+                ee.Cbus1 = chip.cbus[1].to_raw();
+                ee.Cbus2 = chip.cbus[2].to_raw();
+                ee.Cbus3 = chip.cbus[3].to_raw();
+                ee.Cbus4 = chip.cbus[4].to_raw();
+                ee.DriverType = chip.driver.to_raw();
+                program_raw(&mut self.device, &mut ee, &strings)?;
+            }
+            (ChipEeprom::Ft232h(chip), DeviceType::FT232H) => {
+                let mut ee: FT_EEPROM_232H = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_232H as _;
+                config.header_to_raw(&mut ee.common);
+                ee.ACSlowSlew = chip.acbus.slow_slew as _;
+                ee.ACSchmittInput = chip.acbus.schmitt_input as _;
+                ee.ACDriveCurrent = chip.acbus.drive_current.to_raw();
+                ee.ADSlowSlew = chip.adbus.slow_slew as _;
+                ee.ADSchmittInput = chip.adbus.schmitt_input as _;
+                ee.ADDriveCurrent = chip.adbus.drive_current.to_raw();
+                let cbus = chip.cbus.map(Cbus232h::to_raw);
+                ee.Cbus0 = cbus[0];
+                ee.Cbus1 = cbus[1];
+                ee.Cbus2 = cbus[2];
+                ee.Cbus3 = cbus[3];
+                ee.Cbus4 = cbus[4];
+                ee.Cbus5 = cbus[5];
+                ee.Cbus6 = cbus[6];
+                ee.Cbus7 = cbus[7];
+                ee.Cbus8 = cbus[8];
+                ee.Cbus9 = cbus[9];
+                ee.FT1248Cpol = chip.ft1248.clock_polarity_high as _;
+                ee.FT1248Lsb = chip.ft1248.lsb_first as _;
+                ee.FT1248FlowControl = chip.ft1248.flow_control as _;
+                [ee.IsFifo, ee.IsFifoTar, ee.IsFastSer, ee.IsFT1248] = chip.channel.to_raw();
+                ee.PowerSaveEnable = chip.power_save as _;
+                ee.DriverType = chip.driver.to_raw();
+                program_raw(&mut self.device, &mut ee, &strings)?;
+            }
+            (ChipEeprom::Ft2232h(chip), DeviceType::FT2232H) => {
+                if chip.channel_a == ChannelType::Ft1248 || chip.channel_b == ChannelType::Ft1248 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "The FT2232H does not support FT1248 mode",
+                    ));
+                }
+                let mut ee: FT_EEPROM_2232H = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_2232H as _;
+                config.header_to_raw(&mut ee.common);
+                ee.ALSlowSlew = chip.al.slow_slew as _;
+                ee.ALSchmittInput = chip.al.schmitt_input as _;
+                ee.ALDriveCurrent = chip.al.drive_current.to_raw();
+                ee.AHSlowSlew = chip.ah.slow_slew as _;
+                ee.AHSchmittInput = chip.ah.schmitt_input as _;
+                ee.AHDriveCurrent = chip.ah.drive_current.to_raw();
+                ee.BLSlowSlew = chip.bl.slow_slew as _;
+                ee.BLSchmittInput = chip.bl.schmitt_input as _;
+                ee.BLDriveCurrent = chip.bl.drive_current.to_raw();
+                ee.BHSlowSlew = chip.bh.slow_slew as _;
+                ee.BHSchmittInput = chip.bh.schmitt_input as _;
+                ee.BHDriveCurrent = chip.bh.drive_current.to_raw();
+                [ee.AIsFifo, ee.AIsFifoTar, ee.AIsFastSer, _] = chip.channel_a.to_raw();
+                [ee.BIsFifo, ee.BIsFifoTar, ee.BIsFastSer, _] = chip.channel_b.to_raw();
+                ee.PowerSaveEnable = chip.power_save as _;
+                ee.ADriverType = chip.driver_a.to_raw();
+                ee.BDriverType = chip.driver_b.to_raw();
+                program_raw(&mut self.device, &mut ee, &strings)?;
+            }
+            (ChipEeprom::Ft4232h(chip), DeviceType::FT4232H) => {
+                let mut ee: FT_EEPROM_4232H = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_4232H as _;
+                config.header_to_raw(&mut ee.common);
+                let [a, b, c, d] = chip.pins;
+                ee.ASlowSlew = a.slow_slew as _;
+                ee.ASchmittInput = a.schmitt_input as _;
+                ee.ADriveCurrent = a.drive_current.to_raw();
+                ee.BSlowSlew = b.slow_slew as _;
+                ee.BSchmittInput = b.schmitt_input as _;
+                ee.BDriveCurrent = b.drive_current.to_raw();
+                ee.CSlowSlew = c.slow_slew as _;
+                ee.CSchmittInput = c.schmitt_input as _;
+                ee.CDriveCurrent = c.drive_current.to_raw();
+                ee.DSlowSlew = d.slow_slew as _;
+                ee.DSchmittInput = d.schmitt_input as _;
+                ee.DDriveCurrent = d.drive_current.to_raw();
+                let txden = chip.ri_is_txden.map(|x| x as u8);
+                [ee.ARIIsTXDEN, ee.BRIIsTXDEN, ee.CRIIsTXDEN, ee.DRIIsTXDEN] = txden;
+                let drivers = chip.drivers.map(DriverType::to_raw);
+                [
+                    ee.ADriverType,
+                    ee.BDriverType,
+                    ee.CDriverType,
+                    ee.DDriverType,
+                ] = drivers;
+                program_raw(&mut self.device, &mut ee, &strings)?;
+            }
+            (ChipEeprom::FtX(chip), DeviceType::FT_X_SERIES) => {
+                let mut ee: FT_EEPROM_X_SERIES = unsafe { mem::zeroed() };
+                ee.common.deviceType = FT_DEVICE_X_SERIES as _;
+                config.header_to_raw(&mut ee.common);
+                ee.ACSlowSlew = chip.cbus_pins.slow_slew as _;
+                ee.ACSchmittInput = chip.cbus_pins.schmitt_input as _;
+                ee.ACDriveCurrent = chip.cbus_pins.drive_current.to_raw();
+                ee.ADSlowSlew = chip.dbus_pins.slow_slew as _;
+                ee.ADSchmittInput = chip.dbus_pins.schmitt_input as _;
+                ee.ADDriveCurrent = chip.dbus_pins.drive_current.to_raw();
+                let cbus = chip.cbus.map(CbusX::to_raw);
+                ee.Cbus0 = cbus[0];
+                ee.Cbus1 = cbus[1];
+                ee.Cbus2 = cbus[2];
+                ee.Cbus3 = cbus[3];
+                ee.Cbus4 = cbus[4];
+                ee.Cbus5 = cbus[5];
+                ee.Cbus6 = cbus[6];
+                ee.InvertTXD = chip.invert.txd as _;
+                ee.InvertRXD = chip.invert.rxd as _;
+                ee.InvertRTS = chip.invert.rts as _;
+                ee.InvertCTS = chip.invert.cts as _;
+                ee.InvertDTR = chip.invert.dtr as _;
+                ee.InvertDSR = chip.invert.dsr as _;
+                ee.InvertDCD = chip.invert.dcd as _;
+                ee.InvertRI = chip.invert.ri as _;
+                ee.BCDEnable = chip.bcd.enable as _;
+                ee.BCDForceCbusPWREN = chip.bcd.force_cbus_power_enable as _;
+                ee.BCDDisableSleep = chip.bcd.disable_sleep as _;
+                ee.I2CSlaveAddress = chip.i2c_slave_address as _;
+                ee.I2CDeviceId = chip.i2c_device_id as _;
+                ee.I2CDisableSchmitt = chip.i2c_disable_schmitt as _;
+                ee.FT1248Cpol = chip.ft1248.clock_polarity_high as _;
+                ee.FT1248Lsb = chip.ft1248.lsb_first as _;
+                ee.FT1248FlowControl = chip.ft1248.flow_control as _;
+                ee.RS485EchoSuppress = chip.rs485_echo_suppress as _;
+                ee.PowerSaveEnable = chip.power_save as _;
+                ee.DriverType = chip.driver.to_raw();
+                program_raw(&mut self.device, &mut ee, &strings)?;
+            }
+            (ChipEeprom::Ft232r(_), x)
+            | (ChipEeprom::Ft232h(_), x)
+            | (ChipEeprom::Ft2232h(_), x)
+            | (ChipEeprom::Ft4232h(_), x)
+            | (ChipEeprom::FtX(_), x) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("EEPROM configuration does not match the device {:?}", x),
+                ))
+            }
+        }
+
+        if self.eeprom_read()? != *config {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "EEPROM verification failed",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! Implementations of the `embedded-io` and `embedded-io-async` traits for the UART stream.
+//!
+//! This allows running transports written for microcontrollers directly over an FTDI link.
+
+use std::fmt;
+use std::io;
+
+use embedded_io::ErrorKind;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{clone_io_error, Ftdi};
+
+/// Error type of the `embedded-io` implementations, wrapping the underlying [`io::Error`].
+#[derive(Debug)]
+pub struct EmbeddedIoError(pub io::Error);
+
+impl fmt::Display for EmbeddedIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for EmbeddedIoError {}
+
+impl From<io::Error> for EmbeddedIoError {
+    fn from(x: io::Error) -> Self {
+        EmbeddedIoError(x)
+    }
+}
+
+impl From<EmbeddedIoError> for io::Error {
+    fn from(x: EmbeddedIoError) -> Self {
+        x.0
+    }
+}
+
+impl embedded_io::Error for EmbeddedIoError {
+    fn kind(&self) -> ErrorKind {
+        match self.0.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::ConnectionRefused => ErrorKind::ConnectionRefused,
+            io::ErrorKind::ConnectionReset => ErrorKind::ConnectionReset,
+            io::ErrorKind::ConnectionAborted => ErrorKind::ConnectionAborted,
+            io::ErrorKind::NotConnected => ErrorKind::NotConnected,
+            io::ErrorKind::AddrInUse => ErrorKind::AddrInUse,
+            io::ErrorKind::AddrNotAvailable => ErrorKind::AddrNotAvailable,
+            io::ErrorKind::BrokenPipe => ErrorKind::BrokenPipe,
+            io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+            io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+            io::ErrorKind::InvalidData => ErrorKind::InvalidData,
+            io::ErrorKind::TimedOut => ErrorKind::TimedOut,
+            io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+            io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+            io::ErrorKind::OutOfMemory => ErrorKind::OutOfMemory,
+            io::ErrorKind::WriteZero => ErrorKind::WriteZero,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_io::ErrorType for Ftdi {
+    type Error = EmbeddedIoError;
+}
+
+impl embedded_io_async::Read for Ftdi {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(AsyncReadExt::read(self, buf).await?)
+    }
+}
+
+impl embedded_io_async::Write for Ftdi {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(AsyncWriteExt::write(self, buf).await?)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(AsyncWriteExt::flush(self).await?)
+    }
+}
+
+impl embedded_io::ReadReady for Ftdi {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        if let Err(x) = self.poll_event_queue() {
+            self.error = Some(x);
+        }
+        if let Some(err) = &self.error {
+            return Err(EmbeddedIoError(clone_io_error(err)));
+        }
+        Ok(!self.buffer.is_empty())
+    }
+}
+
+impl embedded_io::WriteReady for Ftdi {
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        // writes are queued to the handler thread without back-pressure
+        match &self.error {
+            Some(err) => Err(EmbeddedIoError(clone_io_error(err))),
+            None => Ok(true),
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! Configuration of FPGAs on top of the MPSSE engine.
+//!
+//! Lattice iCE40 devices are configured in SPI slave mode:
+//!
+//! * ADBUS0: SPI_SCK
+//! * ADBUS1: SPI_SI
+//! * ADBUS3: SPI_SS
+//! * CRESET_B and CDONE on GPIO pins, see [`Ice40Pins`]
+//!
+//! Xilinx devices are configured in slave serial mode:
+//!
+//! * ADBUS0: CCLK
+//! * ADBUS1: DIN
+//! * PROG_B, INIT_B and DONE on GPIO pins, see [`XilinxPins`]
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::mpsse::{Mpsse, MpsseCmd, MpsseGpioPin};
+use crate::spi::{MpsseSpi, SpiConfig, SpiMode, SpiOperation};
+use crate::{Ftdi, PinDirection};
+
+const SCK: u8 = 0x01;
+const MOSI: u8 = 0x02;
+const CS: u8 = 0x08;
+
+/// Time CRESET_B is held low, the datasheet requires at least 200 ns.
+const ICE40_RESET_PULSE: Duration = Duration::from_millis(1);
+/// Time for clearing the configuration memory, 1200 us for the largest devices.
+const ICE40_CLEAR_DELAY: Duration = Duration::from_millis(2);
+/// Time PROG_B is held low, the datasheet requires at least 250 ns.
+const XILINX_PROGRAM_PULSE: Duration = Duration::from_millis(1);
+const INIT_TIMEOUT: Duration = Duration::from_millis(100);
+const DONE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Dummy bytes clocked out while waiting for DONE to rise, and after it rose.
+const STARTUP_CLOCK_BYTES: usize = 13;
+
+#[derive(Debug)]
+pub enum FpgaError {
+    /// INIT_B did not rise after pulsing PROG_B.
+    InitTimeout,
+    /// The FPGA signalled a CRC error by pulling INIT_B low.
+    CrcError,
+    /// DONE did not rise after the whole bitstream was sent.
+    DoneTimeout,
+    Io(io::Error),
+}
+
+impl fmt::Display for FpgaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FpgaError::InitTimeout => write!(f, "INIT_B did not rise after PROG_B"),
+            FpgaError::CrcError => write!(f, "FPGA reported a CRC error in the bitstream"),
+            FpgaError::DoneTimeout => write!(f, "DONE did not rise after configuration"),
+            FpgaError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FpgaError {}
+
+impl From<io::Error> for FpgaError {
+    fn from(x: io::Error) -> Self {
+        FpgaError::Io(x)
+    }
+}
+
+impl From<FpgaError> for io::Error {
+    fn from(x: FpgaError) -> Self {
+        match x {
+            FpgaError::Io(err) => err,
+            x => io::Error::other(x),
+        }
+    }
+}
+
+/// Header fields and configuration data of a Xilinx `.bit` file.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct BitFile {
+    pub design: String,
+    pub part: String,
+    pub date: String,
+    pub time: String,
+    pub data: Vec<u8>,
+}
+
+impl BitFile {
+    pub fn parse(file: &[u8]) -> io::Result<Self> {
+        let mut reader = BitReader { file, pos: 0 };
+        // the first field is a fixed 9 byte magic, followed by a length of 1 and the key 'a'
+        let len = reader.read_u16()? as usize;
+        reader.read_bytes(len)?;
+        reader.read_u16()?;
+        let mut result = BitFile::default();
+        loop {
+            let key = reader.read_bytes(1)?[0];
+            if key == b'e' {
+                let len = reader.read_u32()? as usize;
+                result.data = reader.read_bytes(len)?.to_vec();
+                return Ok(result);
+            }
+            let len = reader.read_u16()? as usize;
+            let value = reader.read_bytes(len)?;
+            let value = String::from_utf8_lossy(value)
+                .trim_end_matches('\0')
+                .to_string();
+            match key {
+                b'a' => result.design = value,
+                b'b' => result.part = value,
+                b'c' => result.date = value,
+                b'd' => result.time = value,
+                x => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unknown .bit header field '{}'", x as char),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+struct BitReader<'a> {
+    file: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let data = self
+            .file
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated .bit file"))?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let x = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([x[0], x[1]]))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let x = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+    }
+}
+
+/// Load a bitstream from a `.bit` or `.bin` file, the header of `.bit` files is stripped.
+pub fn load_bitstream(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let file = std::fs::read(path)?;
+    let is_bit = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("bit"));
+    if is_bit {
+        Ok(BitFile::parse(&file)?.data)
+    } else {
+        Ok(file)
+    }
+}
+
+/// Wait until `pin` reads `high`, returns false on timeout.
+async fn wait_for_level(pin: &MpsseGpioPin, high: bool, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if pin.get_level().await? == high {
+            return Ok(true);
+        }
+        if Instant::now() > deadline {
+            return Ok(false);
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ice40Pins {
+    /// GPIO pin number as used by [`Mpsse::gpio_pin`].
+    pub creset: u8,
+    pub cdone: u8,
+}
+
+impl Default for Ice40Pins {
+    fn default() -> Self {
+        Self {
+            creset: 7,
+            cdone: 6,
+        }
+    }
+}
+
+pub struct Ice40 {
+    spi: MpsseSpi,
+    creset: MpsseGpioPin,
+    cdone: MpsseGpioPin,
+}
+
+impl Ice40 {
+    pub async fn new(ftdi: Ftdi, frequency: u32, pins: Ice40Pins) -> io::Result<Self> {
+        let mpsse = Mpsse::new(ftdi).await?;
+        Self::from_mpsse(mpsse, frequency, pins).await
+    }
+
+    pub async fn from_mpsse(mpsse: Mpsse, frequency: u32, pins: Ice40Pins) -> io::Result<Self> {
+        let creset = mpsse.gpio_pin(pins.creset)?;
+        let cdone = mpsse.gpio_pin(pins.cdone)?;
+        creset.set_level(true).await?;
+        creset.set_direction(PinDirection::Output).await?;
+        cdone.set_direction(PinDirection::Input).await?;
+        let config = SpiConfig {
+            frequency,
+            mode: SpiMode::Mode3,
+        };
+        let spi = MpsseSpi::from_mpsse(mpsse, config).await?;
+        Ok(Self { spi, creset, cdone })
+    }
+
+    pub async fn done(&self) -> io::Result<bool> {
+        self.cdone.get_level().await
+    }
+
+    /// Hold the FPGA in reset, which releases the SPI bus.
+    pub async fn reset(&self) -> io::Result<()> {
+        self.creset.set_level(false).await
+    }
+
+    pub async fn configure(&mut self, bitstream: &[u8]) -> Result<(), FpgaError> {
+        // SPI_SS must be low while CRESET_B rises to select the SPI slave mode
+        let mut cmd = MpsseCmd::new();
+        self.spi
+            .mpsse()
+            .set_protocol_pins(&mut cmd, SCK, SCK | MOSI | CS);
+        self.spi.mpsse().execute(cmd).await?;
+        self.creset.set_level(false).await?;
+        tokio::time::sleep(ICE40_RESET_PULSE).await;
+        self.creset.set_level(true).await?;
+        tokio::time::sleep(ICE40_CLEAR_DELAY).await;
+
+        // 8 clocks with SPI_SS high, then the bitstream and at least 100 clocks
+        let mut cmd = MpsseCmd::new();
+        self.spi
+            .mpsse()
+            .set_protocol_pins(&mut cmd, SCK | CS, SCK | MOSI | CS);
+        self.spi.mpsse().execute(cmd).await?;
+        self.spi
+            .run(&mut [SpiOperation::Write(&[0])], false)
+            .await?;
+        self.spi.write(bitstream).await?;
+        self.spi
+            .run(&mut [SpiOperation::Write(&[0; STARTUP_CLOCK_BYTES])], false)
+            .await?;
+        if !wait_for_level(&self.cdone, true, DONE_TIMEOUT).await? {
+            return Err(FpgaError::DoneTimeout);
+        }
+        // at least 49 more clocks to start the user I/O
+        self.spi
+            .run(&mut [SpiOperation::Write(&[0; 7])], false)
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XilinxPins {
+    /// GPIO pin number as used by [`Mpsse::gpio_pin`].
+    pub prog_b: u8,
+    pub init_b: u8,
+    pub done: u8,
+}
+
+impl Default for XilinxPins {
+    fn default() -> Self {
+        Self {
+            prog_b: 4,
+            init_b: 5,
+            done: 6,
+        }
+    }
+}
+
+pub struct XilinxSlaveSerial {
+    spi: MpsseSpi,
+    prog_b: MpsseGpioPin,
+    init_b: MpsseGpioPin,
+    done: MpsseGpioPin,
+}
+
+impl XilinxSlaveSerial {
+    pub async fn new(ftdi: Ftdi, frequency: u32, pins: XilinxPins) -> io::Result<Self> {
+        let mpsse = Mpsse::new(ftdi).await?;
+        Self::from_mpsse(mpsse, frequency, pins).await
+    }
+
+    pub async fn from_mpsse(mpsse: Mpsse, frequency: u32, pins: XilinxPins) -> io::Result<Self> {
+        let prog_b = mpsse.gpio_pin(pins.prog_b)?;
+        let init_b = mpsse.gpio_pin(pins.init_b)?;
+        let done = mpsse.gpio_pin(pins.done)?;
+        prog_b.set_level(true).await?;
+        prog_b.set_direction(PinDirection::Output).await?;
+        init_b.set_direction(PinDirection::Input).await?;
+        done.set_direction(PinDirection::Input).await?;
+        // DIN is sampled on the rising edge of CCLK, MSB first
+        let config = SpiConfig {
+            frequency,
+            mode: SpiMode::Mode0,
+        };
+        let spi = MpsseSpi::from_mpsse(mpsse, config).await?;
+        Ok(Self {
+            spi,
+            prog_b,
+            init_b,
+            done,
+        })
+    }
+
+    pub async fn done(&self) -> io::Result<bool> {
+        self.done.get_level().await
+    }
+
+    pub async fn configure(&mut self, bitstream: &[u8]) -> Result<(), FpgaError> {
+        self.prog_b.set_level(false).await?;
+        tokio::time::sleep(XILINX_PROGRAM_PULSE).await;
+        self.prog_b.set_level(true).await?;
+        if !wait_for_level(&self.init_b, true, INIT_TIMEOUT).await? {
+            return Err(FpgaError::InitTimeout);
+        }
+
+        self.spi
+            .run(&mut [SpiOperation::Write(bitstream)], false)
+            .await?;
+
+        // keep clocking CCLK to run the startup sequence until DONE rises
+        let deadline = Instant::now() + DONE_TIMEOUT;
+        while !self.done.get_level().await? {
+            if !self.init_b.get_level().await? {
+                return Err(FpgaError::CrcError);
+            }
+            if Instant::now() > deadline {
+                return Err(FpgaError::DoneTimeout);
+            }
+            self.spi
+                .run(
+                    &mut [SpiOperation::Write(&[0xFF; STARTUP_CLOCK_BYTES])],
+                    false,
+                )
+                .await?;
+        }
+        self.spi
+            .run(
+                &mut [SpiOperation::Write(&[0xFF; STARTUP_CLOCK_BYTES])],
+                false,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(file: &mut Vec<u8>, key: u8, value: &str) {
+        file.push(key);
+        file.extend_from_slice(&(value.len() as u16 + 1).to_be_bytes());
+        file.extend_from_slice(value.as_bytes());
+        file.push(0);
+    }
+
+    /// Header of a Xilinx .bit file as written by the vendor tools.
+    fn bit_file(data: &[u8]) -> Vec<u8> {
+        let mut file = vec![0x00, 0x09];
+        file.extend_from_slice(&[0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x00]);
+        file.extend_from_slice(&[0x00, 0x01]);
+        field(&mut file, b'a', "top;UserID=0XFFFFFFFF;Version=2023.2");
+        field(&mut file, b'b', "7a35tcsg324");
+        field(&mut file, b'c', "2024/03/01");
+        field(&mut file, b'd', "12:34:56");
+        file.push(b'e');
+        file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        file.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn parse_header() {
+        let data = [0xFF, 0xFF, 0xFF, 0xFF, 0xAA, 0x99, 0x55, 0x66];
+        let bit = BitFile::parse(&bit_file(&data)).unwrap();
+        assert_eq!(bit.design, "top;UserID=0XFFFFFFFF;Version=2023.2");
+        assert_eq!(bit.part, "7a35tcsg324");
+        assert_eq!(bit.date, "2024/03/01");
+        assert_eq!(bit.time, "12:34:56");
+        assert_eq!(bit.data, data);
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        let file = bit_file(&[0xAA, 0x99, 0x55, 0x66]);
+        let err = BitFile::parse(&file[..file.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = BitFile::parse(&file[..20]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut file = bit_file(&[]);
+        // replace the key of the part name
+        let pos = file.iter().position(|x| *x == b'b').unwrap();
+        file[pos] = b'x';
+        let err = BitFile::parse(&file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,173 @@
+//! Implementations of the `embedded-hal` and `embedded-hal-async` traits.
+//!
+//! This allows reusing platform-agnostic device drivers on the host. The digital pin
+//! traits are synchronous and block the calling thread until the device answered, which
+//! also stalls all other tasks of a current-thread tokio runtime meanwhile.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use embedded_hal::digital;
+use embedded_hal::i2c as hal_i2c;
+use embedded_hal::spi as hal_spi;
+
+use crate::i2c::{I2cError, I2cOperation, MpsseI2c};
+use crate::mpsse::MpsseGpioPin;
+use crate::spi::{MpsseSpi, SpiOperation};
+
+/// Error type of the SPI and digital pin implementations.
+#[derive(Debug)]
+pub struct HalError(pub io::Error);
+
+impl fmt::Display for HalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for HalError {}
+
+impl From<io::Error> for HalError {
+    fn from(x: io::Error) -> Self {
+        HalError(x)
+    }
+}
+
+impl hal_spi::Error for HalError {
+    fn kind(&self) -> hal_spi::ErrorKind {
+        hal_spi::ErrorKind::Other
+    }
+}
+
+impl digital::Error for HalError {
+    fn kind(&self) -> digital::ErrorKind {
+        digital::ErrorKind::Other
+    }
+}
+
+impl hal_i2c::Error for I2cError {
+    fn kind(&self) -> hal_i2c::ErrorKind {
+        match self {
+            I2cError::AddressNack(_) => {
+                hal_i2c::ErrorKind::NoAcknowledge(hal_i2c::NoAcknowledgeSource::Address)
+            }
+            I2cError::DataNack(_) => {
+                hal_i2c::ErrorKind::NoAcknowledge(hal_i2c::NoAcknowledgeSource::Data)
+            }
+            I2cError::Io(_) => hal_i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl hal_i2c::ErrorType for MpsseI2c {
+    type Error = I2cError;
+}
+
+impl embedded_hal_async::i2c::I2c for MpsseI2c {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [hal_i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut operations: Vec<I2cOperation<'_>> = operations
+            .iter_mut()
+            .map(|x| match x {
+                hal_i2c::Operation::Read(data) => I2cOperation::Read(data),
+                hal_i2c::Operation::Write(data) => I2cOperation::Write(data),
+            })
+            .collect();
+        MpsseI2c::transaction(self, address, &mut operations).await
+    }
+}
+
+impl hal_spi::ErrorType for MpsseSpi {
+    type Error = HalError;
+}
+
+/// Bus access without touching CS, the chip select is left to the caller.
+impl embedded_hal_async::spi::SpiBus<u8> for MpsseSpi {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.run(&mut [SpiOperation::Read(words)], false).await?;
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.run(&mut [SpiOperation::Write(words)], false).await?;
+        Ok(())
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.run(&mut [SpiOperation::Transfer(read, write)], false)
+            .await?;
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.run(&mut [SpiOperation::TransferInPlace(words)], false)
+            .await?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // every operation waits for the response of the chip
+        Ok(())
+    }
+}
+
+/// Device access, CS on ADBUS3 is asserted for the duration of the transaction.
+impl embedded_hal_async::spi::SpiDevice<u8> for MpsseSpi {
+    async fn transaction(
+        &mut self,
+        operations: &mut [hal_spi::Operation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let mut operations: Vec<SpiOperation<'_>> = operations
+            .iter_mut()
+            .map(|x| match x {
+                hal_spi::Operation::Read(data) => SpiOperation::Read(data),
+                hal_spi::Operation::Write(data) => SpiOperation::Write(data),
+                hal_spi::Operation::Transfer(read, write) => SpiOperation::Transfer(read, write),
+                hal_spi::Operation::TransferInPlace(data) => SpiOperation::TransferInPlace(data),
+                hal_spi::Operation::DelayNs(ns) => {
+                    SpiOperation::Delay(Duration::from_nanos(*ns as u64))
+                }
+            })
+            .collect();
+        self.run(&mut operations, true).await?;
+        Ok(())
+    }
+}
+
+impl digital::ErrorType for MpsseGpioPin {
+    type Error = HalError;
+}
+
+impl digital::OutputPin for MpsseGpioPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(self.set_level_blocking(false)?)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(self.set_level_blocking(true)?)
+    }
+}
+
+impl digital::InputPin for MpsseGpioPin {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.get_level_blocking()?)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.get_level_blocking()?)
+    }
+}
+
+/// Delay provider based on the tokio timer.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Delay;
+
+impl embedded_hal_async::delay::DelayNs for Delay {
+    async fn delay_ns(&mut self, ns: u32) {
+        tokio::time::sleep(Duration::from_nanos(ns as u64)).await;
+    }
+}
@@ -0,0 +1,332 @@
+//! Notification about devices being plugged in and removed.
+//!
+//! The device list of the D2XX library is compared against the previous snapshot
+//! whenever the USB subsystem reports a change. On Linux, changes are detected using
+//! kernel uevents received on a netlink socket. On other platforms, or if the socket
+//! cannot be opened, the list is polled periodically.
+//!
+//! Devices are told apart by their location as well as their identity, such that cables
+//! with blank or duplicate serial numbers are reported when they move between ports.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::selector::{list_located, LocatedDevice};
+use crate::{DeviceInfo, Ftdi};
+
+/// Time for the D2XX library to pick up a device after the kernel reported it.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+pub struct DeviceWatchConfig {
+    /// Interval of the polling fallback. With uevents the list is compared at this
+    /// interval anyway, in case an event got lost.
+    pub poll_interval: Duration,
+    /// Report all devices present when the watch is started as arrived.
+    pub report_present: bool,
+}
+
+impl Default for DeviceWatchConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(1),
+            report_present: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    Arrived(DeviceInfo),
+    Removed(DeviceInfo),
+}
+
+/// Stream of [`DeviceEvent`]s, watching stops once this is dropped.
+#[derive(Debug)]
+pub struct DeviceWatch {
+    rx: UnboundedReceiver<io::Result<DeviceEvent>>,
+}
+
+impl Stream for DeviceWatch {
+    type Item = io::Result<DeviceEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Ftdi {
+    pub fn watch_devices() -> DeviceWatch {
+        Self::watch_devices_with_config(DeviceWatchConfig::default())
+    }
+
+    pub fn watch_devices_with_config(config: DeviceWatchConfig) -> DeviceWatch {
+        let (tx, rx) = unbounded_channel();
+        thread::spawn(move || run(config, tx));
+        DeviceWatch { rx }
+    }
+}
+
+fn same_device(a: &LocatedDevice, b: &LocatedDevice) -> bool {
+    a.location_id == b.location_id
+        && a.info.serial_number == b.info.serial_number
+        && a.info.description == b.info.description
+        && a.info.vendor_id == b.info.vendor_id
+        && a.info.product_id == b.info.product_id
+}
+
+/// Compare two snapshots, devices are matched up one by one.
+fn diff(previous: &[LocatedDevice], current: &[LocatedDevice]) -> Vec<DeviceEvent> {
+    let mut ret = Vec::new();
+    let mut added: Vec<&LocatedDevice> = current.iter().collect();
+    for device in previous {
+        match added.iter().position(|x| same_device(x, device)) {
+            Some(idx) => {
+                added.remove(idx);
+            }
+            None => ret.push(DeviceEvent::Removed(device.info.clone())),
+        }
+    }
+    ret.extend(
+        added
+            .into_iter()
+            .map(|x| DeviceEvent::Arrived(x.info.clone())),
+    );
+    ret
+}
+
+fn run(config: DeviceWatchConfig, tx: UnboundedSender<io::Result<DeviceEvent>>) {
+    let mut previous = match list_located() {
+        Ok(x) => x,
+        Err(err) => {
+            let _ = tx.send(Err(err));
+            return;
+        }
+    };
+    if config.report_present {
+        for device in &previous {
+            let _ = tx.send(Ok(DeviceEvent::Arrived(device.info.clone())));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    let socket = match uevent::UeventSocket::open() {
+        Ok(x) => Some(x),
+        Err(err) => {
+            log::debug!("Cannot receive uevents, polling instead: {}", err);
+            None
+        }
+    };
+
+    while !tx.is_closed() {
+        #[cfg(target_os = "linux")]
+        let changed = match &socket {
+            Some(socket) => match socket.wait(config.poll_interval) {
+                Ok(x) => x,
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    return;
+                }
+            },
+            None => {
+                thread::sleep(config.poll_interval);
+                false
+            }
+        };
+        #[cfg(not(target_os = "linux"))]
+        let changed = {
+            thread::sleep(config.poll_interval);
+            false
+        };
+        if changed {
+            thread::sleep(SETTLE_TIME);
+        }
+
+        let current = match list_located() {
+            Ok(x) => x,
+            Err(err) => {
+                let _ = tx.send(Err(err));
+                return;
+            }
+        };
+        for event in diff(&previous, &current) {
+            if tx.send(Ok(event)).is_err() {
+                return;
+            }
+        }
+        previous = current;
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod uevent {
+    use std::io;
+    use std::mem;
+    use std::time::Duration;
+
+    use libc::{
+        bind, close, poll, pollfd, recv, sockaddr, sockaddr_nl, socket, AF_NETLINK, MSG_DONTWAIT,
+        NETLINK_KOBJECT_UEVENT, POLLIN, SOCK_CLOEXEC, SOCK_DGRAM,
+    };
+
+    /// Multicast group of the uevents sent by the kernel.
+    const KERNEL_GROUP: u32 = 1;
+
+    pub(super) struct UeventSocket(i32);
+
+    impl UeventSocket {
+        pub(super) fn open() -> io::Result<Self> {
+            let fd = unsafe {
+                socket(
+                    AF_NETLINK,
+                    SOCK_DGRAM | SOCK_CLOEXEC,
+                    NETLINK_KOBJECT_UEVENT,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let this = Self(fd);
+            let mut addr: sockaddr_nl = unsafe { mem::zeroed() };
+            addr.nl_family = AF_NETLINK as _;
+            addr.nl_groups = KERNEL_GROUP;
+            let ret = unsafe {
+                bind(
+                    fd,
+                    &addr as *const sockaddr_nl as *const sockaddr,
+                    mem::size_of::<sockaddr_nl>() as _,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(this)
+        }
+
+        /// Wait up to `timeout` for uevents, returns whether a USB device was affected.
+        pub(super) fn wait(&self, timeout: Duration) -> io::Result<bool> {
+            let mut fds = pollfd {
+                fd: self.0,
+                events: POLLIN,
+                revents: 0,
+            };
+            let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+            let ret = unsafe { poll(&mut fds, 1, timeout) };
+            if ret < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(false);
+                }
+                return Err(err);
+            }
+            let mut usb = false;
+            let mut buf = [0_u8; 8192];
+            loop {
+                let len =
+                    unsafe { recv(self.0, buf.as_mut_ptr() as *mut _, buf.len(), MSG_DONTWAIT) };
+                if len <= 0 {
+                    break;
+                }
+                // the message is a list of NUL terminated KEY=VALUE pairs
+                usb |= buf[..len as usize]
+                    .split(|x| *x == 0)
+                    .any(|x| x == b"SUBSYSTEM=usb");
+            }
+            Ok(usb)
+        }
+    }
+
+    impl Drop for UeventSocket {
+        fn drop(&mut self) {
+            unsafe {
+                close(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChipType;
+
+    fn device(serial: &str, description: &str, location_id: u32) -> LocatedDevice {
+        LocatedDevice {
+            index: 0,
+            info: DeviceInfo {
+                port_open: false,
+                chip_type: ChipType::Ft232R,
+                vendor_id: 0x0403,
+                product_id: 0x6001,
+                serial_number: serial.to_string(),
+                description: description.to_string(),
+            },
+            location_id,
+        }
+    }
+
+    /// Events as `(arrived, serial number, description)`.
+    fn summary(events: &[DeviceEvent]) -> Vec<(bool, &str, &str)> {
+        events
+            .iter()
+            .map(|x| match x {
+                DeviceEvent::Arrived(x) => (true, x.serial_number.as_str(), x.description.as_str()),
+                DeviceEvent::Removed(x) => {
+                    (false, x.serial_number.as_str(), x.description.as_str())
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arrival_and_removal() {
+        let devices = [device("FT01", "Cable", 0x11), device("FT02", "Cable", 0x12)];
+        let reversed = [devices[1].clone(), devices[0].clone()];
+        assert!(diff(&devices, &reversed).is_empty());
+
+        let events = diff(&devices[..1], &devices);
+        assert_eq!(summary(&events), [(true, "FT02", "Cable")]);
+
+        let events = diff(&devices, &devices[1..]);
+        assert_eq!(summary(&events), [(false, "FT01", "Cable")]);
+
+        let events = diff(&devices[..1], &devices[1..]);
+        assert_eq!(
+            summary(&events),
+            [(false, "FT01", "Cable"), (true, "FT02", "Cable")]
+        );
+    }
+
+    #[test]
+    fn duplicate_identities() {
+        let devices = [device("", "Cable", 0x11), device("", "Cable", 0x12)];
+        assert!(diff(&devices, &devices).is_empty());
+
+        let events = diff(&devices, &devices[1..]);
+        assert_eq!(summary(&events), [(false, "", "Cable")]);
+        let events = diff(&devices[..1], &devices);
+        assert_eq!(summary(&events), [(true, "", "Cable")]);
+    }
+
+    #[test]
+    fn swapped_blank_serials() {
+        let previous = [device("", "Cable A", 0x11), device("", "Cable B", 0x12)];
+        let current = [device("", "Cable B", 0x11), device("", "Cable A", 0x12)];
+        let events = diff(&previous, &current);
+        assert_eq!(
+            summary(&events),
+            [
+                (false, "", "Cable A"),
+                (false, "", "Cable B"),
+                (true, "", "Cable B"),
+                (true, "", "Cable A"),
+            ]
+        );
+    }
+}
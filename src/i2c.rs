@@ -0,0 +1,331 @@
+//! I2C master on top of the MPSSE engine.
+//!
+//! The bus is wired as follows:
+//!
+//! * ADBUS0: SCL
+//! * ADBUS1: SDA (out)
+//! * ADBUS2: SDA (in), connected to ADBUS1
+//! * ADBUS7: SCL, only required if clock stretching is enabled
+//!
+//! Whole transactions are encoded into a single MPSSE batch, hence the bus is not
+//! aborted early if the target does not acknowledge. The acknowledge bits are
+//! evaluated once the response has been received.
+
+use std::fmt;
+use std::io;
+
+use crate::mpsse::{clock_divisor, ClockMode, Mpsse, MpsseCmd};
+use crate::Ftdi;
+
+const SCL: u8 = 0x01;
+const SDA: u8 = 0x02;
+
+/// Pins which take part in the drive-zero open-drain emulation: SCL, SDA out and SDA in.
+const DRIVE_ZERO_MASK: u16 = 0x0007;
+
+/// Lowest and highest 7-bit address probed by [`MpsseI2c::scan`], reserved addresses are skipped.
+const SCAN_FIRST_ADDRESS: u8 = 0x08;
+const SCAN_LAST_ADDRESS: u8 = 0x77;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum I2cSpeed {
+    Standard,
+    Fast,
+    FastPlus,
+}
+
+impl I2cSpeed {
+    pub fn frequency(&self) -> u32 {
+        match self {
+            I2cSpeed::Standard => 100_000,
+            I2cSpeed::Fast => 400_000,
+            I2cSpeed::FastPlus => 1_000_000,
+        }
+    }
+
+    /// How often pin states are repeated to satisfy setup and hold times around START and STOP.
+    fn hold_repeats(&self) -> usize {
+        match self {
+            I2cSpeed::Standard => 12,
+            I2cSpeed::Fast => 4,
+            I2cSpeed::FastPlus => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct I2cConfig {
+    pub speed: I2cSpeed,
+    /// Use the drive-zero mode of the FT232H to get real open-drain outputs.
+    ///
+    /// Chips without drive-zero mode emulate open-drain by tristating SDA, but
+    /// actively drive data bits while clocking out bytes.
+    pub drive_zero: bool,
+    /// Enable adaptive clocking, which allows targets to stretch the clock.
+    /// SCL must be connected to ADBUS7 for this to work.
+    pub clock_stretching: bool,
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        Self {
+            speed: I2cSpeed::Standard,
+            drive_zero: false,
+            clock_stretching: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum I2cError {
+    /// The target did not acknowledge its address.
+    AddressNack(u8),
+    /// The target did not acknowledge the data byte at the given index of the transaction.
+    DataNack(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cError::AddressNack(address) => {
+                write!(f, "I2C address 0x{:02x} not acknowledged", address)
+            }
+            I2cError::DataNack(index) => write!(f, "I2C data byte {} not acknowledged", index),
+            I2cError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for I2cError {}
+
+impl From<io::Error> for I2cError {
+    fn from(x: io::Error) -> Self {
+        I2cError::Io(x)
+    }
+}
+
+impl From<I2cError> for io::Error {
+    fn from(x: I2cError) -> Self {
+        match x {
+            I2cError::Io(err) => err,
+            x => io::Error::other(x),
+        }
+    }
+}
+
+/// A single part of an I2C transaction, consecutive operations of the same kind are merged.
+#[derive(Debug)]
+pub enum I2cOperation<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+/// What to expect in the response of a batch, in the order the bytes arrive.
+enum Response {
+    AddressAck(u8),
+    DataAck(usize),
+    Read { operation: usize, offset: usize },
+}
+
+pub struct MpsseI2c {
+    mpsse: Mpsse,
+    config: I2cConfig,
+}
+
+impl MpsseI2c {
+    pub async fn new(ftdi: Ftdi, config: I2cConfig) -> io::Result<Self> {
+        let mpsse = Mpsse::new(ftdi).await?;
+        Self::from_mpsse(mpsse, config).await
+    }
+
+    pub async fn from_mpsse(mpsse: Mpsse, config: I2cConfig) -> io::Result<Self> {
+        let this = Self { mpsse, config };
+        let mut cmd = MpsseCmd::new();
+        cmd.set_clock_divisor(clock_divisor(this.config.speed.frequency(), true))
+            .three_phase_clocking(true)
+            .adaptive_clocking(this.config.clock_stretching);
+        if this.config.drive_zero {
+            cmd.drive_zero(DRIVE_ZERO_MASK);
+        }
+        this.pins(&mut cmd, true, true, 1);
+        this.mpsse.execute(cmd).await?;
+        Ok(this)
+    }
+
+    pub fn config(&self) -> &I2cConfig {
+        &self.config
+    }
+
+    pub async fn write(&mut self, address: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.transaction(address, &mut [I2cOperation::Write(data)])
+            .await
+    }
+
+    pub async fn read(&mut self, address: u8, data: &mut [u8]) -> Result<(), I2cError> {
+        self.transaction(address, &mut [I2cOperation::Read(data)])
+            .await
+    }
+
+    /// Write `write` and read back into `read` after a repeated start.
+    pub async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), I2cError> {
+        self.transaction(
+            address,
+            &mut [I2cOperation::Write(write), I2cOperation::Read(read)],
+        )
+        .await
+    }
+
+    /// Execute all operations in a single USB transfer.
+    ///
+    /// A repeated start is generated whenever the direction changes and the
+    /// transaction is terminated with a stop condition.
+    pub async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), I2cError> {
+        if address > 0x7F {
+            return Err(I2cError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("I2C address 0x{:02x} exceeds 7 bits", address),
+            )));
+        }
+        let mut cmd = MpsseCmd::new();
+        let mut responses = Vec::new();
+        let mut data_index = 0;
+        for idx in 0..operations.len() {
+            let is_read = matches!(operations[idx], I2cOperation::Read(_));
+            let continues =
+                idx > 0 && matches!(operations[idx - 1], I2cOperation::Read(_)) == is_read;
+            if !continues {
+                self.start(&mut cmd, idx > 0);
+                self.write_byte(&mut cmd, (address << 1) | is_read as u8);
+                responses.push(Response::AddressAck(address));
+            }
+            match &operations[idx] {
+                I2cOperation::Write(data) => {
+                    for x in data.iter() {
+                        self.write_byte(&mut cmd, *x);
+                        responses.push(Response::DataAck(data_index));
+                        data_index += 1;
+                    }
+                }
+                I2cOperation::Read(data) => {
+                    if data.is_empty() {
+                        return Err(I2cError::Io(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Cannot read zero bytes from I2C target",
+                        )));
+                    }
+                    let group_ends =
+                        !matches!(operations.get(idx + 1), Some(I2cOperation::Read(_)));
+                    for offset in 0..data.len() {
+                        let last = group_ends && offset == data.len() - 1;
+                        self.read_byte(&mut cmd, !last);
+                        responses.push(Response::Read {
+                            operation: idx,
+                            offset,
+                        });
+                    }
+                }
+            }
+        }
+        self.stop(&mut cmd);
+
+        let answer = self.mpsse.execute(cmd).await?;
+        let mut result = Ok(());
+        for (response, x) in responses.iter().zip(answer) {
+            match response {
+                Response::AddressAck(address) => {
+                    if x & 0x01 != 0 && result.is_ok() {
+                        result = Err(I2cError::AddressNack(*address));
+                    }
+                }
+                Response::DataAck(index) => {
+                    if x & 0x01 != 0 && result.is_ok() {
+                        result = Err(I2cError::DataNack(*index));
+                    }
+                }
+                Response::Read { operation, offset } => {
+                    if let I2cOperation::Read(data) = &mut operations[*operation] {
+                        data[*offset] = x;
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Probe all non-reserved 7-bit addresses and return those which acknowledged.
+    pub async fn scan(&mut self) -> io::Result<Vec<u8>> {
+        let mut cmd = MpsseCmd::new();
+        for address in SCAN_FIRST_ADDRESS..=SCAN_LAST_ADDRESS {
+            self.start(&mut cmd, false);
+            self.write_byte(&mut cmd, address << 1);
+            self.stop(&mut cmd);
+        }
+        let answer = self.mpsse.execute(cmd).await?;
+        Ok((SCAN_FIRST_ADDRESS..=SCAN_LAST_ADDRESS)
+            .zip(answer)
+            .filter(|(_, ack)| ack & 0x01 == 0)
+            .map(|(address, _)| address)
+            .collect())
+    }
+
+    /// Set SCL and SDA, a released SDA is tristated instead of driven high.
+    fn pins(&self, cmd: &mut MpsseCmd, scl: bool, sda: bool, repeat: usize) {
+        let mut value = 0;
+        let mut direction = SCL;
+        if scl {
+            value |= SCL;
+        }
+        if sda {
+            value |= SDA;
+        } else {
+            direction |= SDA;
+        }
+        for _ in 0..repeat {
+            self.mpsse.set_protocol_pins(cmd, value, direction);
+        }
+    }
+
+    fn start(&self, cmd: &mut MpsseCmd, repeated: bool) {
+        let repeat = self.config.speed.hold_repeats();
+        if repeated {
+            self.pins(cmd, false, true, 1);
+        }
+        self.pins(cmd, true, true, repeat);
+        self.pins(cmd, true, false, repeat);
+        self.pins(cmd, false, false, 1);
+    }
+
+    fn stop(&self, cmd: &mut MpsseCmd) {
+        let repeat = self.config.speed.hold_repeats();
+        self.pins(cmd, false, false, repeat);
+        self.pins(cmd, true, false, repeat);
+        self.pins(cmd, true, true, repeat);
+    }
+
+    /// Clock out a byte and capture the acknowledge bit of the target.
+    fn write_byte(&self, cmd: &mut MpsseCmd, x: u8) {
+        self.mpsse.set_protocol_pins(cmd, 0, SCL | SDA);
+        cmd.clock_bytes_out(ClockMode::MSB_FIRST, &[x]);
+        self.pins(cmd, false, true, 1);
+        cmd.clock_bits_in(ClockMode::MSB_FIRST, 1);
+    }
+
+    /// Clock in a byte and answer with ACK or NACK.
+    fn read_byte(&self, cmd: &mut MpsseCmd, ack: bool) {
+        self.pins(cmd, false, true, 1);
+        cmd.clock_bytes_in(ClockMode::MSB_FIRST, 1);
+        self.pins(cmd, false, !ack, 1);
+        cmd.clock_bits_out(ClockMode::MSB_FIRST, if ack { 0x00 } else { 0xFF }, 1);
+        self.pins(cmd, false, true, 1);
+    }
+}
@@ -0,0 +1,133 @@
+//! Introspection of an opened device: what it is and what it can do.
+
+use std::fmt;
+use std::io;
+
+use libftd2xx::{library_version, FtdiCommon};
+
+use crate::selector::{channel, list_located, OpenTarget};
+use crate::{request, status_to_io_error, ChipType, Command, DeviceInfo, Ftdi, Handler};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u8,
+}
+
+impl Version {
+    fn from_d2xx(x: libftd2xx::Version) -> Self {
+        Self {
+            major: x.major,
+            minor: x.minor,
+            build: x.build,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FtdiInfo {
+    pub chip_type: ChipType,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial_number: String,
+    pub description: String,
+    /// `None` if the device vanished from the device list.
+    pub location_id: Option<u32>,
+    /// Channel of multi-channel chips.
+    pub channel: Option<char>,
+    pub driver_version: Version,
+    /// Version of the D2XX library.
+    pub library_version: Version,
+}
+
+/// Features of the opened chip and channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub mpsse: bool,
+    /// CBUS pins usable as GPIO, if configured as `IOMODE` in the EEPROM.
+    pub cbus_gpio: bool,
+    /// Synchronous 245 FIFO mode.
+    pub sync_fifo: bool,
+    pub max_baud: u32,
+    pub rx_fifo_size: usize,
+    pub tx_fifo_size: usize,
+}
+
+impl Capabilities {
+    fn new(chip_type: ChipType, channel: Option<char>) -> Self {
+        let (max_baud, rx_fifo_size, tx_fifo_size) = match chip_type {
+            ChipType::Ft232H => (12_000_000, 1024, 1024),
+            ChipType::Ft2232H => (12_000_000, 4096, 4096),
+            ChipType::Ft4232H => (12_000_000, 2048, 2048),
+            ChipType::Ft232R => (3_000_000, 256, 128),
+            ChipType::FtXSeries => (3_000_000, 512, 512),
+            _ => (3_000_000, 128, 128),
+        };
+        let first_channel = matches!(channel, None | Some('A'));
+        Self {
+            mpsse: match chip_type {
+                ChipType::Ft232H | ChipType::Ft2232H => true,
+                ChipType::Ft4232H => matches!(channel, Some('A' | 'B')),
+                _ => false,
+            },
+            cbus_gpio: matches!(
+                chip_type,
+                ChipType::Ft232R | ChipType::Ft232H | ChipType::FtXSeries
+            ),
+            sync_fifo: match chip_type {
+                ChipType::Ft232H => true,
+                ChipType::Ft2232H => first_channel,
+                _ => false,
+            },
+            max_baud,
+            rx_fifo_size,
+            tx_fifo_size,
+        }
+    }
+}
+
+impl Ftdi {
+    /// Identity of the opened device together with the driver and library version.
+    pub async fn info(&self) -> io::Result<FtdiInfo> {
+        request(&self.command_tx, |answer| Command::Info { answer }).await
+    }
+
+    /// Features supported by the opened chip and channel.
+    pub async fn capabilities(&self) -> io::Result<Capabilities> {
+        let info = self.info().await?;
+        Ok(Capabilities::new(info.chip_type, info.channel))
+    }
+}
+
+impl Handler {
+    pub(crate) fn info(&mut self) -> io::Result<FtdiInfo> {
+        let device = DeviceInfo::from_d2xx(self.device.device_info().map_err(status_to_io_error)?);
+        let driver_version = self.device.driver_version().map_err(status_to_io_error)?;
+        let library_version = library_version().map_err(status_to_io_error)?;
+        let location_id = match &self.target {
+            OpenTarget::Location(x) => Some(*x),
+            OpenTarget::SerialNumber(serial) => list_located()?
+                .into_iter()
+                .find(|x| x.info.serial_number == *serial)
+                .map(|x| x.location_id),
+        };
+        Ok(FtdiInfo {
+            chip_type: device.chip_type,
+            vendor_id: device.vendor_id,
+            product_id: device.product_id,
+            channel: channel(&device),
+            serial_number: device.serial_number,
+            description: device.description,
+            location_id,
+            driver_version: Version::from_d2xx(driver_version),
+            library_version: Version::from_d2xx(library_version),
+        })
+    }
+}
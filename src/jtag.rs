@@ -0,0 +1,494 @@
+//! JTAG TAP controller on top of the MPSSE engine.
+//!
+//! The pins are assigned as follows:
+//!
+//! * ADBUS0: TCK
+//! * ADBUS1: TDI
+//! * ADBUS2: TDO
+//! * ADBUS3: TMS
+
+use std::collections::VecDeque;
+use std::io;
+
+use crate::mpsse::{clock_divisor, ClockMode, Mpsse, MpsseCmd};
+use crate::Ftdi;
+
+const TCK: u8 = 0x01;
+const TDI: u8 = 0x02;
+const TDO: u8 = 0x04;
+const TMS: u8 = 0x08;
+
+/// Maximum number of TMS bits which fit into a single MPSSE command.
+const MAX_TMS_BITS: usize = 7;
+
+/// Upper bound of devices detected by [`Jtag::scan_chain`].
+const MAX_CHAIN_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDr,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIr,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl TapState {
+    const ALL: [TapState; 16] = [
+        TapState::TestLogicReset,
+        TapState::RunTestIdle,
+        TapState::SelectDr,
+        TapState::CaptureDr,
+        TapState::ShiftDr,
+        TapState::Exit1Dr,
+        TapState::PauseDr,
+        TapState::Exit2Dr,
+        TapState::UpdateDr,
+        TapState::SelectIr,
+        TapState::CaptureIr,
+        TapState::ShiftIr,
+        TapState::Exit1Ir,
+        TapState::PauseIr,
+        TapState::Exit2Ir,
+        TapState::UpdateIr,
+    ];
+
+    /// The state the TAP transitions to on a rising TCK edge with the given TMS level.
+    pub fn next(self, tms: bool) -> TapState {
+        use TapState::*;
+        match (self, tms) {
+            (TestLogicReset, false) => RunTestIdle,
+            (TestLogicReset, true) => TestLogicReset,
+            (RunTestIdle, false) => RunTestIdle,
+            (RunTestIdle, true) => SelectDr,
+            (SelectDr, false) => CaptureDr,
+            (SelectDr, true) => SelectIr,
+            (CaptureDr, false) => ShiftDr,
+            (CaptureDr, true) => Exit1Dr,
+            (ShiftDr, false) => ShiftDr,
+            (ShiftDr, true) => Exit1Dr,
+            (Exit1Dr, false) => PauseDr,
+            (Exit1Dr, true) => UpdateDr,
+            (PauseDr, false) => PauseDr,
+            (PauseDr, true) => Exit2Dr,
+            (Exit2Dr, false) => ShiftDr,
+            (Exit2Dr, true) => UpdateDr,
+            (UpdateDr, false) => RunTestIdle,
+            (UpdateDr, true) => SelectDr,
+            (SelectIr, false) => CaptureIr,
+            (SelectIr, true) => TestLogicReset,
+            (CaptureIr, false) => ShiftIr,
+            (CaptureIr, true) => Exit1Ir,
+            (ShiftIr, false) => ShiftIr,
+            (ShiftIr, true) => Exit1Ir,
+            (Exit1Ir, false) => PauseIr,
+            (Exit1Ir, true) => UpdateIr,
+            (PauseIr, false) => PauseIr,
+            (PauseIr, true) => Exit2Ir,
+            (Exit2Ir, false) => ShiftIr,
+            (Exit2Ir, true) => UpdateIr,
+            (UpdateIr, false) => RunTestIdle,
+            (UpdateIr, true) => SelectDr,
+        }
+    }
+
+    /// States in which the TAP may remain for an arbitrary number of clocks.
+    pub fn is_stable(self) -> bool {
+        matches!(
+            self,
+            TapState::TestLogicReset
+                | TapState::RunTestIdle
+                | TapState::ShiftDr
+                | TapState::PauseDr
+                | TapState::ShiftIr
+                | TapState::PauseIr
+        )
+    }
+
+    /// Shortest TMS sequence leading from `self` to `target`.
+    pub fn path_to(self, target: TapState) -> Vec<bool> {
+        if self == target {
+            return Vec::new();
+        }
+        let mut previous: [Option<(TapState, bool)>; 16] = [None; 16];
+        let mut queue = VecDeque::from([self]);
+        while let Some(state) = queue.pop_front() {
+            if state == target {
+                break;
+            }
+            for tms in [false, true] {
+                let next = state.next(tms);
+                if next != self && previous[next.index()].is_none() {
+                    previous[next.index()] = Some((state, tms));
+                    queue.push_back(next);
+                }
+            }
+        }
+        let mut path = Vec::new();
+        let mut state = target;
+        while state != self {
+            let (prev, tms) = previous[state.index()].expect("TAP state graph is connected");
+            path.push(tms);
+            state = prev;
+        }
+        path.reverse();
+        path
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|x| *x == self).unwrap()
+    }
+}
+
+/// Describes how to extract the captured TDO bits of a shift from the MPSSE response.
+struct Capture {
+    bits: usize,
+}
+
+impl Capture {
+    /// Number of response bytes produced by the shift.
+    fn len(&self) -> usize {
+        let body = self.bits - 1;
+        body.div_ceil(8) + 1
+    }
+
+    fn decode(&self, response: &[u8]) -> Vec<u8> {
+        let body = self.bits - 1;
+        let full = body / 8;
+        let rem = body % 8;
+        let mut ret = vec![0_u8; self.bits.div_ceil(8)];
+        ret[..full].copy_from_slice(&response[..full]);
+        let mut pos = full;
+        if rem > 0 {
+            ret[full] = response[pos] >> (8 - rem);
+            pos += 1;
+        }
+        let last = (response[pos] >> 7) & 0x01;
+        ret[body / 8] |= last << (body % 8);
+        ret
+    }
+}
+
+pub struct Jtag {
+    mpsse: Mpsse,
+    state: TapState,
+}
+
+impl Jtag {
+    pub async fn new(ftdi: Ftdi, frequency: u32) -> io::Result<Self> {
+        let mpsse = Mpsse::new(ftdi).await?;
+        Self::from_mpsse(mpsse, frequency).await
+    }
+
+    /// Configure the pins and reset the TAP into Test-Logic-Reset.
+    pub async fn from_mpsse(mpsse: Mpsse, frequency: u32) -> io::Result<Self> {
+        let mut this = Self {
+            mpsse,
+            state: TapState::TestLogicReset,
+        };
+        let mut cmd = MpsseCmd::new();
+        cmd.three_phase_clocking(false)
+            .adaptive_clocking(false)
+            .set_clock_divisor(clock_divisor(frequency, false));
+        this.mpsse.set_protocol_pins(&mut cmd, TMS, TCK | TDI | TMS);
+        this.mpsse.execute(cmd).await?;
+        this.reset().await?;
+        Ok(this)
+    }
+
+    pub async fn set_frequency(&mut self, frequency: u32) -> io::Result<()> {
+        let mut cmd = MpsseCmd::new();
+        cmd.set_clock_divisor(clock_divisor(frequency, false));
+        self.mpsse.execute(cmd).await?;
+        Ok(())
+    }
+
+    pub fn mpsse(&self) -> &Mpsse {
+        &self.mpsse
+    }
+
+    /// The state the TAP is assumed to be in.
+    pub fn state(&self) -> TapState {
+        self.state
+    }
+
+    /// Force the TAP into Test-Logic-Reset by clocking 5 times with TMS high.
+    pub async fn reset(&mut self) -> io::Result<()> {
+        self.tms_sequence(&[true; 5]).await?;
+        self.state = TapState::TestLogicReset;
+        Ok(())
+    }
+
+    pub async fn goto(&mut self, state: TapState) -> io::Result<()> {
+        let mut cmd = MpsseCmd::new();
+        self.push_goto(&mut cmd, state);
+        self.mpsse.execute(cmd).await?;
+        Ok(())
+    }
+
+    /// Clock out an arbitrary TMS sequence with TDI held low.
+    pub async fn tms_sequence(&mut self, tms: &[bool]) -> io::Result<()> {
+        let mut cmd = MpsseCmd::new();
+        self.push_tms(&mut cmd, tms);
+        self.mpsse.execute(cmd).await?;
+        Ok(())
+    }
+
+    /// Move to Run-Test/Idle and stay there for `cycles` clocks.
+    pub async fn run_test(&mut self, cycles: usize) -> io::Result<()> {
+        self.run_in_state(TapState::RunTestIdle, cycles).await
+    }
+
+    /// Move to the stable state `state` and stay there for `cycles` clocks.
+    pub async fn run_in_state(&mut self, state: TapState, cycles: usize) -> io::Result<()> {
+        if !state.is_stable() {
+            return Err(unstable_state_error(state));
+        }
+        let mut cmd = MpsseCmd::new();
+        self.push_goto(&mut cmd, state);
+        if state == TapState::TestLogicReset {
+            self.push_tms(&mut cmd, &vec![true; cycles]);
+        } else {
+            cmd.clock_cycles(cycles);
+        }
+        self.mpsse.execute(cmd).await?;
+        Ok(())
+    }
+
+    /// Shift `bits` bits of `tdi` (LSB first) into the instruction register and return TDO.
+    pub async fn shift_ir(
+        &mut self,
+        tdi: &[u8],
+        bits: usize,
+        end: TapState,
+    ) -> io::Result<Vec<u8>> {
+        self.shift(TapState::ShiftIr, tdi, bits, end).await
+    }
+
+    /// Shift `bits` bits of `tdi` (LSB first) into the data register and return TDO.
+    pub async fn shift_dr(
+        &mut self,
+        tdi: &[u8],
+        bits: usize,
+        end: TapState,
+    ) -> io::Result<Vec<u8>> {
+        self.shift(TapState::ShiftDr, tdi, bits, end).await
+    }
+
+    /// Clock arbitrary TMS and TDI levels and return TDO as sampled on every rising edge.
+    pub async fn clock_raw(&mut self, tms: &[bool], tdi: &[bool]) -> io::Result<Vec<bool>> {
+        if tms.len() != tdi.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TMS and TDI must have the same length",
+            ));
+        }
+        if tms.is_empty() {
+            return Ok(Vec::new());
+        }
+        // TDI is constant during a TMS command, so group runs of equal TDI levels
+        let mut cmd = MpsseCmd::new();
+        let mut groups = Vec::new();
+        let mut pos = 0;
+        while pos < tms.len() {
+            let mut len = 1;
+            while len < MAX_TMS_BITS && pos + len < tms.len() && tdi[pos + len] == tdi[pos] {
+                len += 1;
+            }
+            let chunk = &tms[pos..pos + len];
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0_u8, |acc, (idx, x)| acc | ((*x as u8) << idx));
+            cmd.clock_tms(bits, len as u8, tdi[pos]);
+            for x in chunk {
+                self.state = self.state.next(*x);
+            }
+            groups.push(len);
+            pos += len;
+        }
+        let response = self.mpsse.execute(cmd).await?;
+        let mut tdo = Vec::with_capacity(tms.len());
+        for (byte, len) in response.iter().zip(groups) {
+            let value = byte >> (8 - len);
+            tdo.extend((0..len).map(|x| value & (1 << x) != 0));
+        }
+        Ok(tdo)
+    }
+
+    /// Current level of TDO, which is valid while TCK is low.
+    pub async fn tdo(&self) -> io::Result<bool> {
+        Ok(self.mpsse.read_pins().await? & TDO as u16 != 0)
+    }
+
+    /// Read the IDCODEs of all devices in the chain, in the order closest to TDO first.
+    ///
+    /// Devices without an IDCODE register select BYPASS after reset and are reported as `None`.
+    pub async fn scan_chain(&mut self) -> io::Result<Vec<Option<u32>>> {
+        self.reset().await?;
+        let bits = 32 * MAX_CHAIN_LENGTH;
+        let tdo = self
+            .shift_dr(&vec![0xFF; bits / 8], bits, TapState::RunTestIdle)
+            .await?;
+        if tdo.iter().all(|x| *x == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "TDO is stuck low, check the JTAG connection",
+            ));
+        }
+        let bit = |idx: usize| (tdo[idx / 8] >> (idx % 8)) & 0x01 != 0;
+        let mut devices = Vec::new();
+        let mut pos = 0;
+        while pos < bits {
+            if !bit(pos) {
+                devices.push(None);
+                pos += 1;
+                continue;
+            }
+            if pos + 32 > bits {
+                break;
+            }
+            let idcode = (0..32).fold(0_u32, |acc, x| acc | ((bit(pos + x) as u32) << x));
+            if idcode == 0xFFFF_FFFF {
+                break;
+            }
+            devices.push(Some(idcode));
+            pos += 32;
+        }
+        Ok(devices)
+    }
+
+    async fn shift(
+        &mut self,
+        shift_state: TapState,
+        tdi: &[u8],
+        bits: usize,
+        end: TapState,
+    ) -> io::Result<Vec<u8>> {
+        if bits == 0 || tdi.len() * 8 < bits {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot shift {} bits out of {} bytes", bits, tdi.len()),
+            ));
+        }
+        if !end.is_stable() {
+            return Err(unstable_state_error(end));
+        }
+        let mut cmd = MpsseCmd::new();
+        self.push_goto(&mut cmd, shift_state);
+        let capture = self.push_shift(&mut cmd, tdi, bits);
+        self.push_goto(&mut cmd, end);
+        let response = self.mpsse.execute(cmd).await?;
+        Ok(capture.decode(&response[..capture.len()]))
+    }
+
+    /// Shift data while in Shift-DR or Shift-IR, leaving the state with the last bit.
+    fn push_shift(&mut self, cmd: &mut MpsseCmd, tdi: &[u8], bits: usize) -> Capture {
+        let body = bits - 1;
+        let full = body / 8;
+        let rem = body % 8;
+        if full > 0 {
+            cmd.clock_bytes(ClockMode::LSB_FIRST, &tdi[..full]);
+        }
+        if rem > 0 {
+            cmd.clock_bits(ClockMode::LSB_FIRST, tdi[full], rem as u8);
+        }
+        let last = (tdi[body / 8] >> (body % 8)) & 0x01 != 0;
+        cmd.clock_tms(0x01, 1, last);
+        self.state = self.state.next(true);
+        Capture { bits }
+    }
+
+    fn push_goto(&mut self, cmd: &mut MpsseCmd, state: TapState) {
+        let path = self.state.path_to(state);
+        self.push_tms(cmd, &path);
+    }
+
+    fn push_tms(&mut self, cmd: &mut MpsseCmd, tms: &[bool]) {
+        for chunk in tms.chunks(MAX_TMS_BITS) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0_u8, |acc, (idx, x)| acc | ((*x as u8) << idx));
+            cmd.clock_tms_out(bits, chunk.len() as u8, false);
+            for x in chunk {
+                self.state = self.state.next(*x);
+            }
+        }
+    }
+}
+
+fn unstable_state_error(state: TapState) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{:?} is not a stable TAP state", state),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walk(from: TapState, path: &[bool]) -> TapState {
+        path.iter().fold(from, |state, tms| state.next(*tms))
+    }
+
+    #[test]
+    fn path_reaches_every_state() {
+        for from in TapState::ALL {
+            for to in TapState::ALL {
+                let path = from.path_to(to);
+                assert_eq!(walk(from, &path), to, "{:?} -> {:?}", from, to);
+                // no shorter TMS sequence leads to the target
+                for len in 0..path.len() {
+                    for bits in 0..1_u32 << len {
+                        let shorter: Vec<bool> = (0..len).map(|x| bits & (1 << x) != 0).collect();
+                        assert_ne!(walk(from, &shorter), to, "{:?} -> {:?}", from, to);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_paths() {
+        use TapState::*;
+        assert!(RunTestIdle.path_to(RunTestIdle).is_empty());
+        assert_eq!(RunTestIdle.path_to(ShiftDr), vec![true, false, false]);
+        assert_eq!(RunTestIdle.path_to(ShiftIr), vec![true, true, false, false]);
+        assert_eq!(ShiftDr.path_to(PauseDr), vec![true, false]);
+        assert_eq!(PauseIr.path_to(ShiftIr), vec![true, false]);
+        assert_eq!(ShiftIr.path_to(RunTestIdle), vec![true, true, false]);
+        assert_eq!(ShiftDr.path_to(TestLogicReset), vec![true; 5]);
+    }
+
+    #[test]
+    fn five_tms_high_reset_from_anywhere() {
+        for from in TapState::ALL {
+            assert_eq!(walk(from, &[true; 5]), TapState::TestLogicReset);
+        }
+    }
+
+    #[test]
+    fn capture_decodes_shifted_bits() {
+        // 10 bits: one full byte, one bit clocked as bits and the last bit with TMS
+        let capture = Capture { bits: 10 };
+        assert_eq!(capture.len(), 3);
+        let response = [0xA5, 0x80, 0x80];
+        assert_eq!(capture.decode(&response), vec![0xA5, 0x03]);
+        let capture = Capture { bits: 1 };
+        assert_eq!(capture.len(), 1);
+        assert_eq!(capture.decode(&[0x80]), vec![0x01]);
+    }
+}
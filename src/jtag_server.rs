@@ -0,0 +1,455 @@
+//! Serve a JTAG adapter over TCP using OpenOCD's `remote_bitbang` protocol or the
+//! Xilinx Virtual Cable (XVC) protocol.
+//!
+//! Both servers work on any [`JtagBackend`], which is implemented for the MPSSE based
+//! [`Jtag`] and for [`SimulatedTap`], a single TAP with an IDCODE register which can be
+//! used to test clients without hardware.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::jtag::{Jtag, TapState};
+
+/// Largest shift vector in bytes accepted by the XVC server.
+pub const XVC_MAX_VECTOR_BYTES: usize = 2048;
+
+/// Longest command name of the XVC protocol including the colon.
+const XVC_MAX_COMMAND: usize = 8;
+
+const READ_BUFFER: usize = 4096;
+
+#[allow(async_fn_in_trait)]
+pub trait JtagBackend {
+    /// Clock the given TMS and TDI levels and return TDO as sampled on every rising edge.
+    async fn clock(&mut self, tms: &[bool], tdi: &[bool]) -> io::Result<Vec<bool>>;
+
+    /// Current level of TDO while TCK is low.
+    async fn tdo(&mut self) -> io::Result<bool>;
+
+    async fn set_frequency(&mut self, frequency: u32) -> io::Result<()>;
+
+    /// Drive the TRST and SRST reset lines, both are active high here.
+    async fn set_reset(&mut self, trst: bool, srst: bool) -> io::Result<()> {
+        let _ = (trst, srst);
+        Ok(())
+    }
+}
+
+impl JtagBackend for Jtag {
+    async fn clock(&mut self, tms: &[bool], tdi: &[bool]) -> io::Result<Vec<bool>> {
+        self.clock_raw(tms, tdi).await
+    }
+
+    async fn tdo(&mut self) -> io::Result<bool> {
+        Jtag::tdo(self).await
+    }
+
+    async fn set_frequency(&mut self, frequency: u32) -> io::Result<()> {
+        Jtag::set_frequency(self, frequency).await
+    }
+}
+
+const SIM_IR_LENGTH: u32 = 4;
+const SIM_IDCODE: u8 = 0x1;
+
+/// A single simulated TAP with a 4 bit instruction register.
+///
+/// The IDCODE instruction (0x1) is selected after reset, all other instructions select
+/// BYPASS.
+#[derive(Debug, Clone)]
+pub struct SimulatedTap {
+    idcode: u32,
+    state: TapState,
+    ir: u8,
+    ir_shift: u8,
+    dr_shift: u32,
+    dr_length: u32,
+}
+
+impl SimulatedTap {
+    pub fn new(idcode: u32) -> Self {
+        Self {
+            idcode,
+            state: TapState::TestLogicReset,
+            ir: SIM_IDCODE,
+            ir_shift: 0,
+            dr_shift: 0,
+            dr_length: 32,
+        }
+    }
+
+    pub fn state(&self) -> TapState {
+        self.state
+    }
+
+    pub fn instruction(&self) -> u8 {
+        self.ir
+    }
+
+    fn output(&self) -> bool {
+        match self.state {
+            TapState::ShiftIr => self.ir_shift & 0x01 != 0,
+            TapState::ShiftDr => self.dr_shift & 0x01 != 0,
+            _ => false,
+        }
+    }
+
+    fn step(&mut self, tms: bool, tdi: bool) -> bool {
+        let tdo = self.output();
+        match self.state {
+            TapState::TestLogicReset => self.ir = SIM_IDCODE,
+            TapState::CaptureIr => self.ir_shift = 0b0001,
+            TapState::ShiftIr => {
+                self.ir_shift = (self.ir_shift >> 1) | ((tdi as u8) << (SIM_IR_LENGTH - 1));
+            }
+            TapState::UpdateIr => self.ir = self.ir_shift,
+            TapState::CaptureDr => {
+                if self.ir == SIM_IDCODE {
+                    self.dr_shift = self.idcode;
+                    self.dr_length = 32;
+                } else {
+                    self.dr_shift = 0;
+                    self.dr_length = 1;
+                }
+            }
+            TapState::ShiftDr => {
+                self.dr_shift = (self.dr_shift >> 1) | ((tdi as u32) << (self.dr_length - 1));
+            }
+            _ => {}
+        }
+        self.state = self.state.next(tms);
+        tdo
+    }
+}
+
+impl JtagBackend for SimulatedTap {
+    async fn clock(&mut self, tms: &[bool], tdi: &[bool]) -> io::Result<Vec<bool>> {
+        Ok(tms
+            .iter()
+            .zip(tdi)
+            .map(|(tms, tdi)| self.step(*tms, *tdi))
+            .collect())
+    }
+
+    async fn tdo(&mut self) -> io::Result<bool> {
+        Ok(self.output())
+    }
+
+    async fn set_frequency(&mut self, _frequency: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    async fn set_reset(&mut self, trst: bool, _srst: bool) -> io::Result<()> {
+        if trst {
+            self.state = TapState::TestLogicReset;
+            self.ir = SIM_IDCODE;
+        }
+        Ok(())
+    }
+}
+
+/// Pending clocks and TDO reads of a `remote_bitbang` connection.
+#[derive(Default)]
+struct Bitbang {
+    tck: bool,
+    tms: Vec<bool>,
+    tdi: Vec<bool>,
+    /// For every `R`, the index of the clock whose TDO sample answers it.
+    reads: Vec<usize>,
+}
+
+impl Bitbang {
+    /// Execute the pending clocks and return the answers to the pending reads.
+    async fn flush(&mut self, backend: &mut impl JtagBackend) -> io::Result<Vec<u8>> {
+        let tdo = backend.clock(&self.tms, &self.tdi).await?;
+        let mut ret = Vec::with_capacity(self.reads.len());
+        for idx in self.reads.drain(..) {
+            // reads after the last clock are answered with the current level
+            let level = match tdo.get(idx) {
+                Some(x) => *x,
+                None => backend.tdo().await?,
+            };
+            ret.push(if level { b'1' } else { b'0' });
+        }
+        self.tms.clear();
+        self.tdi.clear();
+        Ok(ret)
+    }
+}
+
+/// Serve a single `remote_bitbang` client until it quits or disconnects.
+///
+/// Clocks are collected until the client stops sending, such that a whole scan is
+/// executed in a single round trip to the adapter.
+pub async fn serve_remote_bitbang(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    backend: &mut impl JtagBackend,
+) -> io::Result<()> {
+    let mut state = Bitbang::default();
+    let mut buf = vec![0; READ_BUFFER];
+    loop {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            state.flush(backend).await?;
+            return Ok(());
+        }
+        for x in &buf[..len] {
+            match *x {
+                b'0'..=b'7' => {
+                    let value = x - b'0';
+                    let tck = value & 0x04 != 0;
+                    if tck && !state.tck {
+                        state.tms.push(value & 0x02 != 0);
+                        state.tdi.push(value & 0x01 != 0);
+                    }
+                    state.tck = tck;
+                }
+                b'R' => {
+                    // TDO only changes on the falling edge
+                    let idx = if state.tck && !state.tms.is_empty() {
+                        state.tms.len() - 1
+                    } else {
+                        state.tms.len()
+                    };
+                    state.reads.push(idx);
+                }
+                b'r' | b's' | b't' | b'u' => {
+                    let answer = state.flush(backend).await?;
+                    stream.write_all(&answer).await?;
+                    let trst = matches!(*x, b't' | b'u');
+                    let srst = matches!(*x, b's' | b'u');
+                    backend.set_reset(trst, srst).await?;
+                }
+                b'Q' => {
+                    let answer = state.flush(backend).await?;
+                    stream.write_all(&answer).await?;
+                    return Ok(());
+                }
+                // blink and sleep commands
+                _ => {}
+            }
+        }
+        let answer = state.flush(backend).await?;
+        stream.write_all(&answer).await?;
+    }
+}
+
+fn unpack_bits(data: &[u8], bits: usize) -> Vec<bool> {
+    (0..bits)
+        .map(|x| data[x / 8] & (1 << (x % 8)) != 0)
+        .collect()
+}
+
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    let mut ret = vec![0; bits.len().div_ceil(8)];
+    for (idx, x) in bits.iter().enumerate() {
+        ret[idx / 8] |= (*x as u8) << (idx % 8);
+    }
+    ret
+}
+
+/// Serve a single XVC 1.0 client until it disconnects.
+pub async fn serve_xvc(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    backend: &mut impl JtagBackend,
+) -> io::Result<()> {
+    loop {
+        let mut command = Vec::new();
+        loop {
+            let x = match stream.read_u8().await {
+                Ok(x) => x,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && command.is_empty() => {
+                    return Ok(())
+                }
+                Err(err) => return Err(err),
+            };
+            command.push(x);
+            if x == b':' || command.len() >= XVC_MAX_COMMAND {
+                break;
+            }
+        }
+        match command.as_slice() {
+            b"getinfo:" => {
+                let info = format!("xvcServer_v1.0:{}\n", XVC_MAX_VECTOR_BYTES);
+                stream.write_all(info.as_bytes()).await?;
+            }
+            b"settck:" => {
+                let period = stream.read_u32_le().await?;
+                if let Some(frequency) = 1_000_000_000_u32.checked_div(period) {
+                    backend.set_frequency(frequency).await?;
+                }
+                stream.write_all(&period.to_le_bytes()).await?;
+            }
+            b"shift:" => {
+                let bits = stream.read_u32_le().await? as usize;
+                let len = bits.div_ceil(8);
+                if len > XVC_MAX_VECTOR_BYTES {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("XVC shift of {} bits exceeds the vector size", bits),
+                    ));
+                }
+                let mut tms = vec![0; len];
+                let mut tdi = vec![0; len];
+                stream.read_exact(&mut tms).await?;
+                stream.read_exact(&mut tdi).await?;
+                let tdo = backend
+                    .clock(&unpack_bits(&tms, bits), &unpack_bits(&tdi, bits))
+                    .await?;
+                stream.write_all(&pack_bits(&tdo)).await?;
+            }
+            x => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown XVC command {:?}", String::from_utf8_lossy(x)),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+
+    const IDCODE: u32 = 0x4BA0_0477;
+
+    /// TMS and TDI levels which read the 32 bit IDCODE from a TAP in Test-Logic-Reset.
+    fn read_idcode_sequence() -> (Vec<bool>, Vec<bool>) {
+        let mut tms = TapState::TestLogicReset.path_to(TapState::ShiftDr);
+        tms.extend((0..32).map(|x| x == 31));
+        tms.extend(TapState::Exit1Dr.path_to(TapState::RunTestIdle));
+        let tdi = vec![false; tms.len()];
+        (tms, tdi)
+    }
+
+    fn idcode_from_tdo(tdo: &[bool]) -> u32 {
+        let start = TapState::TestLogicReset.path_to(TapState::ShiftDr).len();
+        tdo[start..start + 32]
+            .iter()
+            .rev()
+            .fold(0, |acc, x| (acc << 1) | *x as u32)
+    }
+
+    async fn xvc_session(
+        client: impl std::future::Future<Output = io::Result<()>>,
+        server: DuplexStream,
+        tap: &mut SimulatedTap,
+    ) -> io::Result<()> {
+        let (served, client) = tokio::join!(serve_xvc(server, tap), client);
+        client?;
+        served
+    }
+
+    #[test]
+    fn simulated_tap_reads_idcode() {
+        let mut tap = SimulatedTap::new(IDCODE);
+        let (tms, tdi) = read_idcode_sequence();
+        let tdo: Vec<bool> = tms
+            .iter()
+            .zip(&tdi)
+            .map(|(a, b)| tap.step(*a, *b))
+            .collect();
+        assert_eq!(idcode_from_tdo(&tdo), IDCODE);
+        assert_eq!(tap.state(), TapState::RunTestIdle);
+    }
+
+    #[tokio::test]
+    async fn xvc_getinfo_and_settck() {
+        let (mut client, server) = duplex(READ_BUFFER);
+        let mut tap = SimulatedTap::new(IDCODE);
+        let session = async move {
+            client.write_all(b"getinfo:").await?;
+            let mut info = vec![0; b"xvcServer_v1.0:2048\n".len()];
+            client.read_exact(&mut info).await?;
+            assert_eq!(info, b"xvcServer_v1.0:2048\n");
+            for period in [100_u32, 0] {
+                client.write_all(b"settck:").await?;
+                client.write_all(&period.to_le_bytes()).await?;
+                assert_eq!(client.read_u32_le().await?, period);
+            }
+            Ok(())
+        };
+        xvc_session(session, server, &mut tap).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn xvc_shift_reads_idcode() {
+        let (mut client, server) = duplex(READ_BUFFER);
+        let mut tap = SimulatedTap::new(IDCODE);
+        let (tms, tdi) = read_idcode_sequence();
+        let bits = tms.len();
+        let session = async move {
+            client.write_all(b"shift:").await?;
+            client.write_all(&(bits as u32).to_le_bytes()).await?;
+            client.write_all(&pack_bits(&tms)).await?;
+            client.write_all(&pack_bits(&tdi)).await?;
+            let mut tdo = vec![0; bits.div_ceil(8)];
+            client.read_exact(&mut tdo).await?;
+            assert_eq!(idcode_from_tdo(&unpack_bits(&tdo, bits)), IDCODE);
+            Ok(())
+        };
+        xvc_session(session, server, &mut tap).await.unwrap();
+        assert_eq!(tap.state(), TapState::RunTestIdle);
+    }
+
+    #[tokio::test]
+    async fn xvc_rejects_oversized_shift_and_unknown_commands() {
+        for request in [
+            [b"shift:".as_slice(), &u32::MAX.to_le_bytes()].concat(),
+            b"bogus:".to_vec(),
+        ] {
+            let (mut client, server) = duplex(READ_BUFFER);
+            let mut tap = SimulatedTap::new(IDCODE);
+            let session = async move { client.write_all(&request).await };
+            let err = xvc_session(session, server, &mut tap).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn remote_bitbang_reads_idcode() {
+        let (mut client, server) = duplex(READ_BUFFER);
+        let mut tap = SimulatedTap::new(IDCODE);
+        let (tms, tdi) = read_idcode_sequence();
+        let bits = tms.len();
+        let session = async move {
+            let mut request = Vec::new();
+            for (tms, tdi) in tms.iter().zip(&tdi) {
+                let value = b'0' + ((*tms as u8) << 1) + *tdi as u8;
+                // sample TDO with TCK low, before the rising edge
+                request.extend([value, b'R', value + 4]);
+            }
+            request.push(b'Q');
+            client.write_all(&request).await?;
+            let mut answer = vec![0; bits];
+            client.read_exact(&mut answer).await?;
+            let tdo: Vec<bool> = answer.iter().map(|x| *x == b'1').collect();
+            assert_eq!(idcode_from_tdo(&tdo), IDCODE);
+            io::Result::Ok(())
+        };
+        let (served, client) = tokio::join!(serve_remote_bitbang(server, &mut tap), session);
+        client.unwrap();
+        served.unwrap();
+        assert_eq!(tap.state(), TapState::RunTestIdle);
+    }
+
+    #[tokio::test]
+    async fn remote_bitbang_reset() {
+        let (mut client, server) = duplex(READ_BUFFER);
+        let mut tap = SimulatedTap::new(IDCODE);
+        let session = async move {
+            // move to Run-Test/Idle, then assert TRST
+            client.write_all(b"04").await?;
+            client.write_all(b"tr").await?;
+            client.write_all(b"Q").await
+        };
+        let (served, client) = tokio::join!(serve_remote_bitbang(server, &mut tap), session);
+        client.unwrap();
+        served.unwrap();
+        assert_eq!(tap.state(), TapState::TestLogicReset);
+    }
+}
@@ -2,13 +2,14 @@ use std::collections::VecDeque;
 use std::future::Future;
 use std::io;
 use std::io::ErrorKind;
+use std::mem::ManuallyDrop;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
-use libftd2xx::list_devices;
 use libftd2xx::BitsPerWord;
 use libftd2xx::FtStatus;
 use libftd2xx::Ftdi as FtdiBase;
@@ -19,6 +20,7 @@ use tokio::io::AsyncWrite;
 use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::{
+    mpsc,
     mpsc::{UnboundedReceiver, UnboundedSender},
     oneshot,
 };
@@ -26,9 +28,16 @@ use tokio::sync::{
 #[cfg(target_os = "linux")]
 mod waker_linux;
 
+use eeprom::EepromConfig;
+use info::FtdiInfo;
+use logic::{Capture, CaptureConfig};
+use selector::OpenTarget;
+use sync_fifo::{SyncFifoConfig, SyncFifoEvent};
 use tokio::task::spawn_blocking;
+use user_area::UserValue;
 #[cfg(target_os = "linux")]
 use waker_linux::{Waker, WakerHandle};
+use watch::{PinReceiver, WatchConfig, Watchers};
 
 #[cfg(target_os = "windows")]
 mod waker_windows;
@@ -36,6 +45,37 @@ mod waker_windows;
 #[cfg(target_os = "windows")]
 use waker_windows::{Waker, WakerHandle};
 
+pub mod boundary_scan;
+pub mod bsdl;
+pub mod cbus;
+pub mod chip;
+mod device;
+pub mod eeprom;
+#[cfg(feature = "embedded-io")]
+pub mod embedded_stream;
+pub mod fpga;
+#[cfg(feature = "embedded-hal")]
+pub mod hal;
+pub mod hotplug;
+pub mod i2c;
+pub mod info;
+pub mod jtag;
+pub mod jtag_server;
+pub mod logic;
+pub mod mpsse;
+pub mod provision;
+pub mod reconnect;
+mod reset;
+pub mod selector;
+pub mod spi;
+pub mod spi_flash;
+pub mod svf;
+pub mod swd;
+pub mod sync_fifo;
+pub mod user_area;
+mod vid_pid;
+pub mod watch;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum StopBits {
     One,
@@ -55,6 +95,38 @@ pub enum DataBits {
     Eight,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PinDirection {
+    Input,
+    Output,
+}
+
+/// Operating mode of the FTDI chip, see `FT_SetBitMode`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BitMode {
+    Reset,
+    AsyncBitbang,
+    Mpsse,
+    SyncBitbang,
+    McuHost,
+    FastSerial,
+    CbusBitbang,
+    SyncFifo,
+}
+
+impl BitMode {
+    /// Whether received bytes still belong to the UART byte stream in this mode.
+    ///
+    /// In all other modes the device only answers to explicit transfers, so the
+    /// handler must not drain the receive queue in the background.
+    fn is_streaming(self) -> bool {
+        matches!(
+            self,
+            BitMode::Reset | BitMode::AsyncBitbang | BitMode::CbusBitbang
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SerialParams {
     pub baud: u32,
@@ -63,6 +135,18 @@ pub struct SerialParams {
     pub parity: Parity,
 }
 
+/// 115200 baud, 8N1.
+impl Default for SerialParams {
+    fn default() -> Self {
+        Self {
+            baud: 115200,
+            data_bits: DataBits::Eight,
+            stop_bits: StopBits::One,
+            parity: Parity::None,
+        }
+    }
+}
+
 impl From<StopBits> for libftd2xx::StopBits {
     fn from(x: StopBits) -> Self {
         match x {
@@ -91,12 +175,66 @@ impl From<Parity> for libftd2xx::Parity {
     }
 }
 
-pub fn status_to_io_error(status: FtStatus) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, status.to_string())
+impl From<BitMode> for libftd2xx::BitMode {
+    fn from(x: BitMode) -> Self {
+        match x {
+            BitMode::Reset => libftd2xx::BitMode::Reset,
+            BitMode::AsyncBitbang => libftd2xx::BitMode::AsyncBitbang,
+            BitMode::Mpsse => libftd2xx::BitMode::Mpsse,
+            BitMode::SyncBitbang => libftd2xx::BitMode::SyncBitbang,
+            BitMode::McuHost => libftd2xx::BitMode::McuHost,
+            BitMode::FastSerial => libftd2xx::BitMode::FastSerial,
+            BitMode::CbusBitbang => libftd2xx::BitMode::CbusBitbang,
+            BitMode::SyncFifo => libftd2xx::BitMode::SyncFifo,
+        }
+    }
+}
+
+/// Convert a D2XX status, the [`Status`] is kept as the inner error.
+pub(crate) fn status_to_io_error(status: FtStatus) -> io::Error {
+    let status = Status::from_d2xx(status);
+    let kind = if status.is_disconnect() {
+        ErrorKind::NotConnected
+    } else {
+        ErrorKind::Other
+    };
+    io::Error::new(kind, status)
 }
 
 fn disconnected_error() -> io::Error {
-    io::Error::new(ErrorKind::Other, "Channel Disconnected")
+    io::Error::new(ErrorKind::NotConnected, "Channel Disconnected")
+}
+
+/// Send a command carrying a oneshot answer channel to the handler thread and wait for the reply.
+async fn request<T>(
+    command_tx: &UnboundedSender<Command>,
+    command: impl FnOnce(oneshot::Sender<io::Result<T>>) -> Command,
+) -> io::Result<T> {
+    let (tx, rx) = oneshot::channel();
+    if command_tx.send(command(tx)).is_err() {
+        return Err(disconnected_error());
+    }
+    rx.await.unwrap_or_else(|_| Err(disconnected_error()))
+}
+
+/// Like [`request`] but blocks the calling thread, for use in synchronous trait implementations.
+///
+/// Within a tokio runtime the answer is awaited on a separate thread, since blocking a
+/// runtime thread panics and `block_in_place` is not available on the current-thread runtime.
+#[cfg(feature = "embedded-hal")]
+fn blocking_request<T: Send>(
+    command_tx: &UnboundedSender<Command>,
+    command: impl FnOnce(oneshot::Sender<io::Result<T>>) -> Command,
+) -> io::Result<T> {
+    let (tx, rx) = oneshot::channel();
+    if command_tx.send(command(tx)).is_err() {
+        return Err(disconnected_error());
+    }
+    let answer = match tokio::runtime::Handle::try_current() {
+        Ok(_) => std::thread::scope(|s| s.spawn(|| rx.blocking_recv()).join().unwrap()),
+        Err(_) => rx.blocking_recv(),
+    };
+    answer.unwrap_or_else(|_| Err(disconnected_error()))
 }
 
 #[derive(Debug)]
@@ -108,16 +246,37 @@ pub struct Ftdi {
     shutdown_rx: Option<oneshot::Receiver<()>>,
 }
 
-pub use libftd2xx::DeviceInfo;
+pub use device::{ChipType, DeviceInfo, Status};
 
 impl Ftdi {
     pub async fn list_devices() -> io::Result<Vec<DeviceInfo>> {
-        spawn_blocking(|| list_devices().map_err(status_to_io_error))
-            .await
-            .unwrap()
+        spawn_blocking(vid_pid::list_all).await.unwrap()
     }
 
     pub async fn open(serial_number: &str, params: &SerialParams) -> io::Result<Ftdi> {
+        Self::open_target(OpenTarget::SerialNumber(serial_number.to_owned()), params).await
+    }
+
+    /// Open the device with `serial_number`, or the first listed device if it is `None`.
+    pub async fn open_or_first(
+        serial_number: Option<&str>,
+        params: &SerialParams,
+    ) -> io::Result<Ftdi> {
+        let serial_number = match serial_number {
+            Some(x) => x.to_string(),
+            None => {
+                Self::list_devices()
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "No FTDI device found"))?
+                    .serial_number
+            }
+        };
+        Self::open(&serial_number, params).await
+    }
+
+    async fn open_target(target: OpenTarget, params: &SerialParams) -> io::Result<Ftdi> {
         let (open_tx, open_rx) = oneshot::channel();
         let (command_tx, command_rx) = unbounded_channel();
         let (event_tx, event_rx) = unbounded_channel();
@@ -126,10 +285,9 @@ impl Ftdi {
             let event_tx = event_tx.clone();
             let params = params.clone();
             let command_tx = command_tx.clone();
-            let serial_number = serial_number.to_owned();
             move || {
                 Handler::run(
-                    serial_number,
+                    target,
                     params.clone(),
                     open_tx,
                     command_tx,
@@ -178,6 +336,50 @@ impl Ftdi {
         }
     }
 
+    pub async fn set_dtr(&self, active: bool) -> io::Result<()> {
+        request(&self.command_tx, |answer| Command::SetDtr {
+            active,
+            answer,
+        })
+        .await
+    }
+
+    pub async fn set_rts(&self, active: bool) -> io::Result<()> {
+        request(&self.command_tx, |answer| Command::SetRts {
+            active,
+            answer,
+        })
+        .await
+    }
+
+    /// Switch the chip into a different bit mode.
+    ///
+    /// `mask` selects which pins are outputs, its meaning depends on `mode`.
+    /// While the chip is in a mode other than [`BitMode::Reset`], [`BitMode::AsyncBitbang`]
+    /// or [`BitMode::CbusBitbang`], received data is no longer forwarded to `AsyncRead`
+    /// and must be fetched using explicit transfers.
+    pub async fn set_bit_mode(&self, mask: u8, mode: BitMode) -> io::Result<()> {
+        request(&self.command_tx, |answer| Command::SetBitMode {
+            mask,
+            mode,
+            answer,
+        })
+        .await
+    }
+
+    /// Write `data` to the device and then read exactly `read_len` bytes back.
+    ///
+    /// Both happen back-to-back on the handler thread, so no other command can
+    /// interleave with the transfer.
+    pub(crate) async fn transfer(&self, data: Vec<u8>, read_len: usize) -> io::Result<Vec<u8>> {
+        request(&self.command_tx, |answer| Command::Transfer {
+            data,
+            read_len,
+            answer,
+        })
+        .await
+    }
+
     fn push_to_output_buffer(&mut self, buf: &mut tokio::io::ReadBuf<'_>) -> bool {
         if self.buffer.is_empty() || buf.remaining() == 0 {
             return false;
@@ -219,25 +421,158 @@ enum Command {
         params: SerialParams,
         answer: oneshot::Sender<io::Result<()>>,
     },
+    SetDtr {
+        active: bool,
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    SetRts {
+        active: bool,
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    SetBitMode {
+        mask: u8,
+        mode: BitMode,
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    Transfer {
+        data: Vec<u8>,
+        read_len: usize,
+        answer: oneshot::Sender<io::Result<Vec<u8>>>,
+    },
+    ReadPins {
+        answer: oneshot::Sender<io::Result<u8>>,
+    },
+    CbusIoModePins {
+        answer: oneshot::Sender<io::Result<[bool; 4]>>,
+    },
+    MpsseSync {
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    EepromRead {
+        answer: oneshot::Sender<io::Result<EepromConfig>>,
+    },
+    EepromProgram {
+        config: EepromConfig,
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    ResetDevice {
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    CyclePort {
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    SetDeadmanTimeout {
+        timeout: Duration,
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    Info {
+        answer: oneshot::Sender<io::Result<FtdiInfo>>,
+    },
+    UserAreaRead {
+        answer: oneshot::Sender<io::Result<Vec<u8>>>,
+    },
+    UserAreaWrite {
+        data: Vec<u8>,
+        answer: oneshot::Sender<io::Result<()>>,
+    },
+    /// Set `key` to `value` or remove it if `None`, answered with the previous value.
+    UserAreaUpdate {
+        key: String,
+        value: Option<UserValue>,
+        answer: oneshot::Sender<io::Result<Option<UserValue>>>,
+    },
+    WatchPins {
+        mask: u16,
+        config: WatchConfig,
+        answer: oneshot::Sender<io::Result<(u16, PinReceiver)>>,
+    },
+    Capture {
+        config: CaptureConfig,
+        answer: oneshot::Sender<io::Result<Capture>>,
+    },
+    StartSyncFifo {
+        config: SyncFifoConfig,
+        answer: oneshot::Sender<io::Result<mpsc::Receiver<io::Result<SyncFifoEvent>>>>,
+    },
+}
+
+/// Send an error to the answer channel of each listed variant, ignoring those without answer.
+macro_rules! reject_answers {
+    ($command:expr, $err:expr, [$($variant:ident),* $(,)?]) => {
+        match $command {
+            Command::Cancel | Command::PollRead | Command::Send(_) => {}
+            $(Command::$variant { answer, .. } => {
+                let _ = answer.send(Err($err));
+            })*
+        }
+    };
+}
+
+impl Command {
+    /// Answer a command which cannot be executed in the current mode of the handler.
+    fn reject(self, msg: &str) {
+        reject_answers!(
+            self,
+            io::Error::new(ErrorKind::Unsupported, msg),
+            [
+                SetParams,
+                SetDtr,
+                SetRts,
+                SetBitMode,
+                Transfer,
+                ReadPins,
+                CbusIoModePins,
+                MpsseSync,
+                EepromRead,
+                EepromProgram,
+                ResetDevice,
+                CyclePort,
+                SetDeadmanTimeout,
+                Info,
+                UserAreaRead,
+                UserAreaWrite,
+                UserAreaUpdate,
+                WatchPins,
+                Capture,
+                StartSyncFifo,
+            ]
+        )
+    }
 }
 
 struct Event(io::Result<Vec<u8>>);
 
 struct Handler {
+    command_tx: UnboundedSender<Command>,
     command_rx: UnboundedReceiver<Command>,
     event_tx: UnboundedSender<Event>,
-    _waker: WakerHandle,
-    device: FtdiBase,
+    waker: Option<WakerHandle>,
+    /// Closed explicitly with [`Handler::close_device`], which also tracks if it is open.
+    device: ManuallyDrop<FtdiBase>,
+    device_open: bool,
+    target: OpenTarget,
+    params: SerialParams,
     close_sender: oneshot::Sender<()>,
+    mode: BitMode,
+    watchers: Watchers,
 }
 
+/// Upper bound for the device to deliver the next part of a response.
+///
+/// This limits the time without progress rather than the whole transfer, such that long
+/// responses at slow clock rates do not time out while data keeps arriving.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Upper bound for the device to re-enumerate after its port was cycled.
+const REENUMERATION_TIMEOUT: Duration = Duration::from_secs(10);
+
 fn clone_io_error(err: &io::Error) -> io::Error {
     io::Error::new(err.kind(), format!("{}", err))
 }
 
 impl Handler {
     fn run(
-        serial_number: String,
+        target: OpenTarget,
         params: SerialParams,
         open_channel: oneshot::Sender<io::Result<()>>,
         command_tx: UnboundedSender<Command>,
@@ -245,36 +580,21 @@ impl Handler {
         event_tx: UnboundedSender<Event>,
         shutdown_tx: oneshot::Sender<()>,
     ) {
-        let mut device = match FtdiBase::with_serial_number(&serial_number) {
+        let mut device = match Self::open_device(&target, &params) {
             Ok(device) => device,
-            Err(status) => {
-                let _ = open_channel.send(Err(status_to_io_error(status)));
+            Err(err) => {
+                let _ = open_channel.send(Err(err));
                 return;
             }
         };
-        if let Err(status) =
-            device.set_timeouts(Duration::from_millis(100), Duration::from_millis(100))
-        {
-            let _ = open_channel.send(Err(status_to_io_error(status)));
-            return;
-        }
-
-        if let Err(status) = device.set_latency_timer(Duration::from_millis(2)) {
-            let _ = open_channel.send(Err(status_to_io_error(status)));
-            return;
-        }
-
-        if let Err(x) = Self::apply_params(&mut device, &params) {
-            let _ = open_channel.send(Err(x));
-            return;
-        }
 
         log::debug!("Device configuration succeeded, spawning waker.");
         let waker = match Waker::spawn(&mut device, command_tx.clone()) {
             Ok(x) => x,
             Err(err) => {
                 let _ = open_channel.send(Err(err));
-                let _ = device.close();
+                // closed when dropped
+                drop(device);
                 return;
             }
         };
@@ -282,22 +602,46 @@ impl Handler {
         let _ = open_channel.send(Ok(()));
 
         let mut this = Handler {
+            command_tx,
             command_rx,
             event_tx,
-            device,
-            _waker: waker,
+            device: ManuallyDrop::new(device),
+            device_open: true,
+            target,
+            params,
+            waker: Some(waker),
             close_sender: shutdown_tx,
+            mode: BitMode::Reset,
+            watchers: Default::default(),
         };
 
         if let Err(err) = this.run_loop() {
             let _ = this.event_tx.send(Event(Err(err)));
         }
-        let _ = this.device.close();
+        this.close_device();
         let _ = this.close_sender.send(());
 
         log::debug!("Device handler thread dropped.");
     }
 
+    fn close_device(&mut self) {
+        if std::mem::take(&mut self.device_open) {
+            let _ = self.device.close();
+        }
+    }
+
+    fn open_device(target: &OpenTarget, params: &SerialParams) -> io::Result<FtdiBase> {
+        let mut device = target.open()?;
+        device
+            .set_timeouts(Duration::from_millis(100), Duration::from_millis(100))
+            .map_err(status_to_io_error)?;
+        device
+            .set_latency_timer(Duration::from_millis(2))
+            .map_err(status_to_io_error)?;
+        Self::apply_params(&mut device, params)?;
+        Ok(device)
+    }
+
     fn apply_params(device: &mut FtdiBase, params: &SerialParams) -> io::Result<()> {
         device
             .set_baud_rate(params.baud)
@@ -353,14 +697,58 @@ impl Handler {
         ret
     }
 
+    fn read_exact(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0_u8; len];
+        let mut received = 0;
+        let mut deadline = Instant::now() + TRANSFER_TIMEOUT;
+        while received < len {
+            let num_bytes = self
+                .device
+                .read(&mut buf[received..])
+                .map_err(status_to_io_error)?;
+            received += num_bytes;
+            if num_bytes > 0 {
+                deadline = Instant::now() + TRANSFER_TIMEOUT;
+            } else if Instant::now() > deadline {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("Expected {} bytes but only received {}", len, received),
+                ));
+            }
+        }
+        Ok(buf)
+    }
+
+    fn transfer(&mut self, data: Vec<u8>, read_len: usize) -> io::Result<Vec<u8>> {
+        if !data.is_empty() {
+            self.send_data(data)?;
+        }
+        let response = self.read_exact(read_len)?;
+        if self.mode == BitMode::Mpsse {
+            self.mpsse_check_response()?;
+        }
+        Ok(response)
+    }
+
+    fn set_bit_mode(&mut self, mask: u8, mode: BitMode) -> io::Result<()> {
+        self.device
+            .set_bit_mode(mask, mode.into())
+            .map_err(status_to_io_error)?;
+        self.mode = mode;
+        Ok(())
+    }
+
     fn run_loop(&mut self) -> io::Result<()> {
-        while let Some(msg) = self.command_rx.blocking_recv() {
+        while let Some(msg) = self.next_command() {
             match msg {
                 Command::Cancel => {
                     log::debug!("Canceling run loop.");
                     break;
                 }
                 Command::PollRead => {
+                    if !self.mode.is_streaming() {
+                        continue;
+                    }
                     let num_bytes = self.device.queue_status().map_err(status_to_io_error)?;
                     if num_bytes != 0 {
                         let data = self.poll_read(num_bytes)?;
@@ -375,9 +763,109 @@ impl Handler {
                     let result = Self::apply_params(&mut self.device, &params);
                     if let Err(x) = result.as_ref() {
                         log::debug!("Applying params failed: {:?}", x);
+                    } else {
+                        self.params = params;
                     }
                     let _ = answer.send(result);
                 }
+                Command::SetDtr { active, answer } => {
+                    let result = if active {
+                        self.device.set_dtr()
+                    } else {
+                        self.device.clear_dtr()
+                    };
+                    let _ = answer.send(result.map_err(status_to_io_error));
+                }
+                Command::SetRts { active, answer } => {
+                    let result = if active {
+                        self.device.set_rts()
+                    } else {
+                        self.device.clear_rts()
+                    };
+                    let _ = answer.send(result.map_err(status_to_io_error));
+                }
+                Command::SetBitMode { mask, mode, answer } => {
+                    log::debug!("Switching to bit mode {:?} with mask 0x{:x}", mode, mask);
+                    let _ = answer.send(self.set_bit_mode(mask, mode));
+                }
+                Command::Transfer {
+                    data,
+                    read_len,
+                    answer,
+                } => {
+                    let _ = answer.send(self.transfer(data, read_len));
+                }
+                Command::ReadPins { answer } => {
+                    let _ = answer.send(self.device.bit_mode().map_err(status_to_io_error));
+                }
+                Command::CbusIoModePins { answer } => {
+                    let _ = answer.send(cbus::iomode_pins(&mut self.device));
+                }
+                Command::MpsseSync { answer } => {
+                    let _ = answer.send(self.mpsse_sync());
+                }
+                Command::EepromRead { answer } => {
+                    let _ = answer.send(self.eeprom_read());
+                }
+                Command::EepromProgram { config, answer } => {
+                    log::debug!("Programming EEPROM: {:?}", config);
+                    let _ = answer.send(self.eeprom_program(&config));
+                }
+                Command::ResetDevice { answer } => {
+                    log::debug!("Resetting device");
+                    let _ = answer.send(self.reset_device());
+                }
+                Command::CyclePort { answer } => {
+                    log::debug!("Cycling port");
+                    match self.cycle_port() {
+                        Err(err) if !self.device_open => {
+                            // the device did not come back, stop serving the closed handle
+                            let _ = answer.send(Err(clone_io_error(&err)));
+                            return Err(err);
+                        }
+                        result => {
+                            let _ = answer.send(result);
+                        }
+                    }
+                }
+                Command::SetDeadmanTimeout { timeout, answer } => {
+                    let _ = answer.send(self.set_deadman_timeout(timeout));
+                }
+                Command::Info { answer } => {
+                    let _ = answer.send(self.info());
+                }
+                Command::UserAreaRead { answer } => {
+                    let _ = answer.send(self.user_area_read());
+                }
+                Command::UserAreaWrite { data, answer } => {
+                    let _ = answer.send(self.user_area_write(data));
+                }
+                Command::UserAreaUpdate { key, value, answer } => {
+                    let _ = answer.send(self.user_area_update(key, value));
+                }
+                Command::WatchPins {
+                    mask,
+                    config,
+                    answer,
+                } => {
+                    let _ = answer.send(self.watch_pins(mask, config));
+                }
+                Command::Capture { config, answer } => {
+                    log::debug!("Starting logic capture: {:?}", config);
+                    let _ = answer.send(self.capture(&config));
+                }
+                Command::StartSyncFifo { config, answer } => {
+                    log::debug!("Entering synchronous FIFO mode: {:?}", config);
+                    match self.start_sync_fifo(&config) {
+                        Ok((tx, rx)) => {
+                            let _ = answer.send(Ok(rx));
+                            return self.run_sync_fifo(tx, config.chunk_size);
+                        }
+                        Err(err) => {
+                            let _ = answer.send(Err(err));
+                        }
+                    }
+                }
             }
         }
         Ok(())
@@ -414,7 +902,10 @@ impl AsyncRead for Ftdi {
                     return Poll::Ready(Err(ret));
                 }
                 Poll::Ready(None) => {
-                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "Disconnected")));
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::NotConnected,
+                        "Disconnected",
+                    )));
                 }
                 Poll::Pending => return Poll::Pending,
             }
@@ -436,7 +927,10 @@ impl AsyncWrite for Ftdi {
             return Poll::Ready(Err(clone_io_error(err)));
         }
         if self.command_tx.send(Command::Send(buf.to_vec())).is_err() {
-            return Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "Disconnected")));
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Disconnected",
+            )));
         }
         Poll::Ready(Ok(buf.len()))
     }
@@ -0,0 +1,367 @@
+//! Logic analyzer on the 8 ADBUS pins using synchronous bit-bang mode.
+//!
+//! In synchronous bit-bang mode the chip samples the pins once for every byte written to
+//! it. The handler thread keeps writing dummy bytes, one chunk ahead of the samples it
+//! reads back, until the trigger condition was met and enough samples were collected. If
+//! the host cannot keep up, the chip pauses sampling. These pauses are estimated from the
+//! write timing and reported in [`Capture::gaps`].
+//!
+//! The handler thread is busy for the whole capture, other requests to the device wait
+//! until it finished. Afterwards the device stays in synchronous bit-bang mode with all
+//! pins as inputs.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use libftd2xx::FtdiCommon;
+
+use crate::mpsse::Edge;
+use crate::{request, status_to_io_error, BitMode, Command, Ftdi, Handler};
+
+/// Upper bound for the number of samples requested in one round trip.
+const MAX_CHUNK: usize = 4096;
+const MIN_CHUNK: usize = 64;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Trigger {
+    /// Start capturing right away.
+    Immediate,
+    /// Trigger once the pins selected by `mask` equal `value`.
+    Pattern {
+        mask: u8,
+        value: u8,
+    },
+    Edge {
+        pin: u8,
+        edge: Edge,
+    },
+}
+
+impl Trigger {
+    fn matches(&self, previous: Option<u8>, sample: u8) -> bool {
+        match *self {
+            Trigger::Immediate => true,
+            Trigger::Pattern { mask, value } => sample & mask == value & mask,
+            Trigger::Edge { pin, edge } => {
+                let previous = match previous {
+                    Some(x) => x & (1 << pin) != 0,
+                    None => return false,
+                };
+                let current = sample & (1 << pin) != 0;
+                match edge {
+                    Edge::Rising => !previous && current,
+                    Edge::Falling => previous && !current,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// Samples per second, the bit-bang clock runs at 16 times the configured baud rate.
+    pub sample_rate: u32,
+    pub trigger: Trigger,
+    /// Number of samples kept from before the trigger.
+    pub pre_trigger: usize,
+    /// Number of samples captured starting with the trigger sample.
+    pub post_trigger: usize,
+    /// Give up if the trigger condition is not met within this time.
+    pub timeout: Duration,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 1_000_000,
+            trigger: Trigger::Immediate,
+            pre_trigger: 1000,
+            post_trigger: 100_000,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub sample_rate: u32,
+    /// One byte per sample, ADBUS0 in bit 0.
+    pub samples: Vec<u8>,
+    /// Index of the sample which met the trigger condition.
+    pub trigger_index: usize,
+    /// Indices of samples before which sampling paused, because the host did not write
+    /// fast enough. The time elapsed between these samples and their predecessors is unknown.
+    pub gaps: Vec<usize>,
+}
+
+impl Capture {
+    /// Export the capture as Value Change Dump, `names` optionally labels the pins.
+    pub fn write_vcd(&self, mut w: impl Write, names: &[&str]) -> io::Result<()> {
+        let id = |pin: usize| (b'!' + pin as u8) as char;
+        writeln!(
+            w,
+            "$comment async-ftdi capture, trigger at sample {} $end",
+            self.trigger_index
+        )?;
+        if !self.gaps.is_empty() {
+            let gaps: Vec<String> = self.gaps.iter().map(|x| x.to_string()).collect();
+            writeln!(
+                w,
+                "$comment sampling paused before samples {}, timestamps are not continuous $end",
+                gaps.join(", ")
+            )?;
+        }
+        writeln!(w, "$timescale 1 ns $end")?;
+        writeln!(w, "$scope module ftdi $end")?;
+        for pin in 0..8 {
+            let name = match names.get(pin) {
+                Some(x) => x.to_string(),
+                None => format!("D{}", pin),
+            };
+            writeln!(w, "$var wire 1 {} {} $end", id(pin), name)?;
+        }
+        writeln!(w, "$upscope $end")?;
+        writeln!(w, "$enddefinitions $end")?;
+
+        let mut last: Option<u8> = None;
+        for (idx, sample) in self.samples.iter().enumerate() {
+            let changed = match last {
+                Some(x) => x ^ sample,
+                None => 0xFF,
+            };
+            if changed == 0 {
+                continue;
+            }
+            let time = idx as u64 * 1_000_000_000 / self.sample_rate.max(1) as u64;
+            writeln!(w, "#{}", time)?;
+            for pin in 0..8 {
+                if changed & (1 << pin) != 0 {
+                    let level = if sample & (1 << pin) != 0 { '1' } else { '0' };
+                    writeln!(w, "{}{}", level, id(pin))?;
+                }
+            }
+            last = Some(*sample);
+        }
+        let end = self.samples.len() as u64 * 1_000_000_000 / self.sample_rate.max(1) as u64;
+        writeln!(w, "#{}", end)?;
+        Ok(())
+    }
+
+    pub fn to_vcd(&self, names: &[&str]) -> String {
+        let mut ret = Vec::new();
+        self.write_vcd(&mut ret, names).unwrap();
+        String::from_utf8(ret).unwrap()
+    }
+}
+
+impl Ftdi {
+    /// Switch into synchronous bit-bang mode and capture the ADBUS pins.
+    pub async fn capture(&self, config: CaptureConfig) -> io::Result<Capture> {
+        request(&self.command_tx, |answer| Command::Capture {
+            config,
+            answer,
+        })
+        .await
+    }
+}
+
+impl Handler {
+    pub(crate) fn capture(&mut self, config: &CaptureConfig) -> io::Result<Capture> {
+        if config.post_trigger == 0 || config.sample_rate == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Sample rate and post-trigger depth must not be zero",
+            ));
+        }
+        if let Trigger::Edge { pin, .. } = config.trigger {
+            if pin > 7 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Pin {} cannot be captured", pin),
+                ));
+            }
+        }
+        self.set_bit_mode(0, BitMode::Reset)?;
+        self.set_bit_mode(0, BitMode::SyncBitbang)?;
+        self.device
+            .set_baud_rate((config.sample_rate / 16).max(1))
+            .map_err(status_to_io_error)?;
+        self.device.purge_all().map_err(status_to_io_error)?;
+
+        // request about 10 ms worth of samples per round trip
+        let chunk = (config.sample_rate as usize / 100).clamp(MIN_CHUNK, MAX_CHUNK);
+        let result = self.capture_chunks(config, chunk);
+        // discard the samples of the chunk written ahead
+        self.device.purge_all().map_err(status_to_io_error)?;
+        result
+    }
+
+    fn capture_chunks(&mut self, config: &CaptureConfig, chunk: usize) -> io::Result<Capture> {
+        let deadline = Instant::now() + config.timeout;
+        let chunk_duration = Duration::from_secs_f64(chunk as f64 / config.sample_rate as f64);
+        // the estimated time at which the chip runs out of written bytes
+        let mut sampled_until = Instant::now();
+        let mut written = 0;
+        let mut gaps = Vec::new();
+        let mut write_chunk = |this: &mut Self| {
+            let now = Instant::now();
+            if written > 0 && now > sampled_until {
+                gaps.push(written);
+            }
+            sampled_until = sampled_until.max(now) + chunk_duration;
+            written += chunk;
+            this.send_data(vec![0; chunk])
+        };
+
+        let mut pre = VecDeque::with_capacity(config.pre_trigger);
+        let mut captured: Option<(Vec<u8>, usize)> = None;
+        // index of the first sample in `pre` or `captured`, counted from the start
+        let mut first = 0;
+        let mut last = None;
+        write_chunk(self)?;
+        loop {
+            write_chunk(self)?;
+            for sample in self.read_exact(chunk)? {
+                match &mut captured {
+                    Some((samples, _)) => samples.push(sample),
+                    None if config.trigger.matches(last, sample) => {
+                        let mut samples: Vec<u8> = pre.drain(..).collect();
+                        let trigger_index = samples.len();
+                        samples.push(sample);
+                        captured = Some((samples, trigger_index));
+                    }
+                    None => {
+                        if pre.len() == config.pre_trigger {
+                            pre.pop_front();
+                            first += 1;
+                        }
+                        if config.pre_trigger > 0 {
+                            pre.push_back(sample);
+                        }
+                        last = Some(sample);
+                    }
+                }
+                if let Some((samples, trigger_index)) = &captured {
+                    if samples.len() == trigger_index + config.post_trigger {
+                        let (samples, trigger_index) = captured.take().unwrap();
+                        let end = first + samples.len();
+                        return Ok(Capture {
+                            sample_rate: config.sample_rate,
+                            samples,
+                            trigger_index,
+                            gaps: gaps
+                                .iter()
+                                .filter(|x| **x > first && **x < end)
+                                .map(|x| x - first)
+                                .collect(),
+                        });
+                    }
+                }
+            }
+            if captured.is_none() && Instant::now() > deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Trigger condition was not met",
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_trigger() {
+        let trigger = Trigger::Pattern {
+            mask: 0x0F,
+            value: 0x05,
+        };
+        assert!(trigger.matches(None, 0x05));
+        assert!(trigger.matches(Some(0x05), 0xF5));
+        assert!(!trigger.matches(None, 0x04));
+        assert!(!trigger.matches(Some(0x05), 0x0D));
+        assert!(Trigger::Immediate.matches(None, 0));
+    }
+
+    #[test]
+    fn edge_trigger() {
+        let rising = Trigger::Edge {
+            pin: 3,
+            edge: Edge::Rising,
+        };
+        assert!(rising.matches(Some(0x00), 0x08));
+        assert!(rising.matches(Some(0xF7), 0xFF));
+        assert!(!rising.matches(Some(0x08), 0x08));
+        assert!(!rising.matches(Some(0x08), 0x00));
+        assert!(!rising.matches(Some(0x00), 0x04));
+        // the first sample has no predecessor, so it cannot be an edge
+        assert!(!rising.matches(None, 0x08));
+
+        let falling = Trigger::Edge {
+            pin: 0,
+            edge: Edge::Falling,
+        };
+        assert!(falling.matches(Some(0x01), 0x00));
+        assert!(!falling.matches(Some(0x00), 0x01));
+        assert!(!falling.matches(None, 0x00));
+    }
+
+    #[test]
+    fn vcd_export() {
+        let capture = Capture {
+            sample_rate: 1_000_000,
+            samples: vec![0x00, 0x00, 0x01, 0x03, 0x03],
+            trigger_index: 2,
+            gaps: vec![3],
+        };
+        let expected = "\
+$comment async-ftdi capture, trigger at sample 2 $end
+$comment sampling paused before samples 3, timestamps are not continuous $end
+$timescale 1 ns $end
+$scope module ftdi $end
+$var wire 1 ! CLK $end
+$var wire 1 \" DATA $end
+$var wire 1 # D2 $end
+$var wire 1 $ D3 $end
+$var wire 1 % D4 $end
+$var wire 1 & D5 $end
+$var wire 1 ' D6 $end
+$var wire 1 ( D7 $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+0\"
+0#
+0$
+0%
+0&
+0'
+0(
+#2000
+1!
+#3000
+1\"
+#5000
+";
+        assert_eq!(capture.to_vcd(&["CLK", "DATA"]), expected);
+    }
+
+    #[test]
+    fn vcd_without_gaps() {
+        let capture = Capture {
+            sample_rate: 2_000_000,
+            samples: vec![0xFF; 4],
+            trigger_index: 0,
+            gaps: Vec::new(),
+        };
+        let vcd = capture.to_vcd(&[]);
+        assert!(!vcd.contains("sampling paused"));
+        assert!(vcd.contains("$var wire 1 ! D0 $end"));
+        // a single record for the first sample, followed by the end of the capture
+        assert!(vcd.ends_with("$enddefinitions $end\n#0\n1!\n1\"\n1#\n1$\n1%\n1&\n1'\n1(\n#2000\n"));
+    }
+}
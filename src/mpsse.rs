@@ -0,0 +1,568 @@
+//! Building blocks for talking to the MPSSE engine of FT232H, FT2232H and FT4232H chips.
+//!
+//! Commands are collected in an [`MpsseCmd`] and sent to the chip in a single USB transfer.
+//! The builder keeps track of how many bytes the chip will answer with, such that the
+//! response can be read back in the same round trip. Several independent batches can be
+//! combined with [`Mpsse::execute_batch`], which splits the response up again.
+//!
+//! All transfers are executed by the handler thread of the device, hence a batch is
+//! never interleaved with the commands of other users of the same [`Mpsse`].
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use libftd2xx::FtdiCommon;
+
+use crate::{request, status_to_io_error, BitMode, Command, Ftdi, Handler, PinDirection};
+
+const CLOCK_DATA_OUT: u8 = 0x10;
+const CLOCK_DATA_IN: u8 = 0x20;
+const CLOCK_BITS: u8 = 0x02;
+const CLOCK_TMS_OUT: u8 = 0x4B;
+const SET_BITS_LOW: u8 = 0x80;
+const GET_BITS_LOW: u8 = 0x81;
+const SET_BITS_HIGH: u8 = 0x82;
+const GET_BITS_HIGH: u8 = 0x83;
+const ENABLE_LOOPBACK: u8 = 0x84;
+const DISABLE_LOOPBACK: u8 = 0x85;
+const SET_CLOCK_DIVISOR: u8 = 0x86;
+const SEND_IMMEDIATE: u8 = 0x87;
+const DISABLE_CLOCK_DIVIDE_BY_5: u8 = 0x8A;
+const ENABLE_3_PHASE_CLOCKING: u8 = 0x8C;
+const DISABLE_3_PHASE_CLOCKING: u8 = 0x8D;
+const CLOCK_BITS_NO_DATA: u8 = 0x8E;
+const CLOCK_BYTES_NO_DATA: u8 = 0x8F;
+const ENABLE_ADAPTIVE_CLOCKING: u8 = 0x96;
+const DISABLE_ADAPTIVE_CLOCKING: u8 = 0x97;
+const DRIVE_ZERO: u8 = 0x9E;
+
+/// The chip answers unknown opcodes with this byte followed by the opcode.
+const BAD_COMMAND: u8 = 0xFA;
+/// Invalid opcodes used to synchronize with the command parser.
+const SYNC_OPCODES: [u8; 2] = [0xAA, 0xAB];
+/// Upper bound of stale bytes skipped while waiting for the echo of a sync opcode.
+const SYNC_MAX_BYTES: usize = 1024;
+
+/// Time for the chip to settle after switching into MPSSE mode.
+const MODE_SWITCH_DELAY: Duration = Duration::from_millis(50);
+
+/// ADBUS0 to ADBUS3 are driven by the serial protocol engines, all other pins are GPIOs.
+const PROTOCOL_PINS: u8 = 0x0F;
+
+/// Clock of the MPSSE engine with the divide-by-5 prescaler disabled.
+pub const MPSSE_BASE_CLOCK: u32 = 60_000_000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+/// Selects on which clock edges data is shifted and in which order bits are sent.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ClockMode {
+    pub write_edge: Edge,
+    pub read_edge: Edge,
+    pub bit_order: BitOrder,
+}
+
+impl ClockMode {
+    /// Output on the falling edge, sample on the rising edge, MSB first (SPI mode 0, I2C).
+    pub const MSB_FIRST: ClockMode = ClockMode {
+        write_edge: Edge::Falling,
+        read_edge: Edge::Rising,
+        bit_order: BitOrder::MsbFirst,
+    };
+
+    /// Output on the falling edge, sample on the rising edge, LSB first (JTAG).
+    pub const LSB_FIRST: ClockMode = ClockMode {
+        write_edge: Edge::Falling,
+        read_edge: Edge::Rising,
+        bit_order: BitOrder::LsbFirst,
+    };
+
+    fn opcode(&self, base: u8) -> u8 {
+        let mut ret = base;
+        if base & CLOCK_DATA_OUT != 0 && self.write_edge == Edge::Falling {
+            ret |= 0x01;
+        }
+        if base & CLOCK_DATA_IN != 0 && self.read_edge == Edge::Falling {
+            ret |= 0x04;
+        }
+        if self.bit_order == BitOrder::LsbFirst {
+            ret |= 0x08;
+        }
+        ret
+    }
+}
+
+/// Compute the clock divisor for the requested SCK frequency.
+///
+/// With 3-phase clocking each clock period is stretched to three half-periods,
+/// which lowers the resulting frequency by a factor of 2/3.
+pub fn clock_divisor(frequency: u32, three_phase: bool) -> u16 {
+    let half_periods = if three_phase { 3 } else { 2 };
+    let divisor = MPSSE_BASE_CLOCK / (frequency.max(1) * half_periods);
+    divisor.saturating_sub(1).min(u16::MAX as u32) as u16
+}
+
+/// A batch of MPSSE commands which is sent to the chip in a single USB write.
+#[derive(Debug, Default, Clone)]
+pub struct MpsseCmd {
+    data: Vec<u8>,
+    read_len: usize,
+}
+
+impl MpsseCmd {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of bytes the chip answers with once this batch has been executed.
+    pub fn read_len(&self) -> usize {
+        self.read_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Append all commands of `other` to this batch.
+    pub fn append(&mut self, other: MpsseCmd) -> &mut Self {
+        self.data.extend(other.data);
+        self.read_len += other.read_len;
+        self
+    }
+
+    pub fn set_gpio_lower(&mut self, value: u8, direction: u8) -> &mut Self {
+        self.data.extend([SET_BITS_LOW, value, direction]);
+        self
+    }
+
+    pub fn set_gpio_upper(&mut self, value: u8, direction: u8) -> &mut Self {
+        self.data.extend([SET_BITS_HIGH, value, direction]);
+        self
+    }
+
+    /// Read the level of the lower GPIO byte (ADBUS), answered with a single byte.
+    pub fn gpio_lower(&mut self) -> &mut Self {
+        self.data.push(GET_BITS_LOW);
+        self.read_len += 1;
+        self
+    }
+
+    /// Read the level of the upper GPIO byte (ACBUS), answered with a single byte.
+    pub fn gpio_upper(&mut self) -> &mut Self {
+        self.data.push(GET_BITS_HIGH);
+        self.read_len += 1;
+        self
+    }
+
+    pub fn set_clock_divisor(&mut self, divisor: u16) -> &mut Self {
+        self.data.push(DISABLE_CLOCK_DIVIDE_BY_5);
+        self.data
+            .extend([SET_CLOCK_DIVISOR, divisor as u8, (divisor >> 8) as u8]);
+        self
+    }
+
+    pub fn three_phase_clocking(&mut self, enable: bool) -> &mut Self {
+        self.data.push(if enable {
+            ENABLE_3_PHASE_CLOCKING
+        } else {
+            DISABLE_3_PHASE_CLOCKING
+        });
+        self
+    }
+
+    /// Adaptive clocking waits for the target to echo the clock on GPIOL3 (ADBUS7).
+    pub fn adaptive_clocking(&mut self, enable: bool) -> &mut Self {
+        self.data.push(if enable {
+            ENABLE_ADAPTIVE_CLOCKING
+        } else {
+            DISABLE_ADAPTIVE_CLOCKING
+        });
+        self
+    }
+
+    /// Only drive pins in `mask` low and tristate them when set high (FT232H only).
+    ///
+    /// The lower 8 bits of `mask` refer to ADBUS, the upper 8 bits to ACBUS.
+    pub fn drive_zero(&mut self, mask: u16) -> &mut Self {
+        self.data
+            .extend([DRIVE_ZERO, mask as u8, (mask >> 8) as u8]);
+        self
+    }
+
+    /// Connect TDI/DO internally to TDO/DI, the pins are left untouched.
+    pub fn loopback(&mut self, enable: bool) -> &mut Self {
+        self.data.push(if enable {
+            ENABLE_LOOPBACK
+        } else {
+            DISABLE_LOOPBACK
+        });
+        self
+    }
+
+    /// Ask the chip to flush its response buffer to the host right away.
+    pub fn send_immediate(&mut self) -> &mut Self {
+        self.data.push(SEND_IMMEDIATE);
+        self
+    }
+
+    pub fn clock_bytes_out(&mut self, mode: ClockMode, data: &[u8]) -> &mut Self {
+        for chunk in data.chunks(0x10000) {
+            self.push_length_opcode(mode.opcode(CLOCK_DATA_OUT), chunk.len());
+            self.data.extend(chunk);
+        }
+        self
+    }
+
+    pub fn clock_bytes_in(&mut self, mode: ClockMode, len: usize) -> &mut Self {
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = remaining.min(0x10000);
+            self.push_length_opcode(mode.opcode(CLOCK_DATA_IN), chunk);
+            remaining -= chunk;
+        }
+        self.read_len += len;
+        self
+    }
+
+    /// Clock out `data` while capturing the same number of bytes.
+    pub fn clock_bytes(&mut self, mode: ClockMode, data: &[u8]) -> &mut Self {
+        for chunk in data.chunks(0x10000) {
+            self.push_length_opcode(mode.opcode(CLOCK_DATA_OUT | CLOCK_DATA_IN), chunk.len());
+            self.data.extend(chunk);
+        }
+        self.read_len += data.len();
+        self
+    }
+
+    /// Clock out the first `bits` bits (1 to 8) of `data`.
+    pub fn clock_bits_out(&mut self, mode: ClockMode, data: u8, bits: u8) -> &mut Self {
+        assert!((1..=8).contains(&bits));
+        self.data
+            .extend([mode.opcode(CLOCK_DATA_OUT | CLOCK_BITS), bits - 1, data]);
+        self
+    }
+
+    /// Capture `bits` bits (1 to 8), answered with a single byte.
+    ///
+    /// With [`BitOrder::MsbFirst`] the bits are shifted in at the LSB, with
+    /// [`BitOrder::LsbFirst`] they end up in the upper bits of the byte.
+    pub fn clock_bits_in(&mut self, mode: ClockMode, bits: u8) -> &mut Self {
+        assert!((1..=8).contains(&bits));
+        self.data
+            .extend([mode.opcode(CLOCK_DATA_IN | CLOCK_BITS), bits - 1]);
+        self.read_len += 1;
+        self
+    }
+
+    /// Clock out and capture `bits` bits (1 to 8) at the same time.
+    pub fn clock_bits(&mut self, mode: ClockMode, data: u8, bits: u8) -> &mut Self {
+        assert!((1..=8).contains(&bits));
+        self.data.extend([
+            mode.opcode(CLOCK_DATA_OUT | CLOCK_DATA_IN | CLOCK_BITS),
+            bits - 1,
+            data,
+        ]);
+        self.read_len += 1;
+        self
+    }
+
+    /// Clock `bits` bits (1 to 7) of `tms` out on TMS, LSB first, while holding TDI at `tdi`.
+    pub fn clock_tms_out(&mut self, tms: u8, bits: u8, tdi: bool) -> &mut Self {
+        assert!((1..=7).contains(&bits));
+        let data = (tms & 0x7F) | if tdi { 0x80 } else { 0x00 };
+        self.data.extend([CLOCK_TMS_OUT, bits - 1, data]);
+        self
+    }
+
+    /// Clock `bits` bits (1 to 7) out on TMS and capture TDO for every bit.
+    pub fn clock_tms(&mut self, tms: u8, bits: u8, tdi: bool) -> &mut Self {
+        assert!((1..=7).contains(&bits));
+        let data = (tms & 0x7F) | if tdi { 0x80 } else { 0x00 };
+        self.data
+            .extend([CLOCK_TMS_OUT | CLOCK_DATA_IN, bits - 1, data]);
+        self.read_len += 1;
+        self
+    }
+
+    /// Generate `cycles` clock pulses without transferring any data.
+    pub fn clock_cycles(&mut self, cycles: usize) -> &mut Self {
+        let mut bytes = cycles / 8;
+        while bytes > 0 {
+            let chunk = bytes.min(0x10000);
+            self.push_length_opcode(CLOCK_BYTES_NO_DATA, chunk);
+            bytes -= chunk;
+        }
+        let bits = cycles % 8;
+        if bits > 0 {
+            self.data.extend([CLOCK_BITS_NO_DATA, bits as u8 - 1]);
+        }
+        self
+    }
+
+    fn push_length_opcode(&mut self, opcode: u8, len: usize) {
+        let len = len - 1;
+        self.data.extend([opcode, len as u8, (len >> 8) as u8]);
+    }
+}
+
+/// A device which has been switched into MPSSE mode.
+///
+/// `Mpsse` is cheap to clone, all clones share the same underlying device. The device is
+/// closed once the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Mpsse {
+    ftdi: Arc<Ftdi>,
+    pins: Arc<Mutex<PinState>>,
+}
+
+/// Last value and direction written to the 16 pins, ADBUS0 in bit 0 and ACBUS7 in bit 15.
+#[derive(Debug, Default)]
+struct PinState {
+    value: u16,
+    direction: u16,
+}
+
+impl Mpsse {
+    /// Reset the chip, switch it into MPSSE mode and synchronize with its command parser.
+    pub async fn new(ftdi: Ftdi) -> io::Result<Self> {
+        ftdi.set_bit_mode(0, BitMode::Reset).await?;
+        ftdi.set_bit_mode(0, BitMode::Mpsse).await?;
+        tokio::time::sleep(MODE_SWITCH_DELAY).await;
+        let this = Mpsse {
+            ftdi: Arc::new(ftdi),
+            pins: Default::default(),
+        };
+        this.sync().await?;
+        let mut cmd = MpsseCmd::new();
+        cmd.loopback(false);
+        this.execute(cmd).await?;
+        Ok(this)
+    }
+
+    pub(crate) fn ftdi(&self) -> &Ftdi {
+        &self.ftdi
+    }
+
+    /// Discard pending data and send invalid opcodes until the chip echoes them back.
+    ///
+    /// Afterwards every response byte is known to belong to the next executed command.
+    pub async fn sync(&self) -> io::Result<()> {
+        request(&self.ftdi.command_tx, |answer| Command::MpsseSync {
+            answer,
+        })
+        .await
+    }
+
+    /// Check the data path to the chip using the internal loopback.
+    ///
+    /// The outputs keep their state, but the clock pin toggles while the test runs.
+    pub async fn self_test(&self) -> io::Result<()> {
+        let pattern: Vec<u8> = (0..=255).collect();
+        let mut cmd = MpsseCmd::new();
+        cmd.loopback(true)
+            .clock_bytes(ClockMode::MSB_FIRST, &pattern)
+            .loopback(false);
+        let response = self.execute(cmd).await?;
+        if response != pattern {
+            let pos = response.iter().zip(&pattern).position(|(a, b)| a != b);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("MPSSE loopback test failed at byte {:?}", pos),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Set the protocol pins ADBUS0 to ADBUS3 while keeping the GPIOs on ADBUS4 to ADBUS7.
+    pub fn set_protocol_pins(&self, cmd: &mut MpsseCmd, value: u8, direction: u8) {
+        let mut pins = self.pins.lock().unwrap();
+        let keep = !(PROTOCOL_PINS as u16);
+        pins.value = (pins.value & keep) | (value & PROTOCOL_PINS) as u16;
+        pins.direction = (pins.direction & keep) | (direction & PROTOCOL_PINS) as u16;
+        cmd.set_gpio_lower(pins.value as u8, pins.direction as u8);
+    }
+
+    /// Use one of the pins ADBUS4 to ADBUS7 (4 to 7) or ACBUS0 to ACBUS7 (8 to 15) as GPIO.
+    pub fn gpio_pin(&self, pin: u8) -> io::Result<MpsseGpioPin> {
+        if !(4..=15).contains(&pin) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Pin {} cannot be used as GPIO", pin),
+            ));
+        }
+        Ok(MpsseGpioPin {
+            mpsse: self.clone(),
+            pin,
+        })
+    }
+
+    /// Read the level of all 16 pins, ADBUS0 in bit 0 and ACBUS7 in bit 15.
+    pub async fn read_pins(&self) -> io::Result<u16> {
+        let mut cmd = MpsseCmd::new();
+        cmd.gpio_lower().gpio_upper();
+        let response = self.execute(cmd).await?;
+        Ok(response[0] as u16 | (response[1] as u16) << 8)
+    }
+
+    fn update_pin(&self, pin: u8, direction: Option<PinDirection>, high: Option<bool>) -> MpsseCmd {
+        let mut pins = self.pins.lock().unwrap();
+        let mask = 1_u16 << pin;
+        match direction {
+            Some(PinDirection::Input) => pins.direction &= !mask,
+            Some(PinDirection::Output) => pins.direction |= mask,
+            None => {}
+        }
+        match high {
+            Some(true) => pins.value |= mask,
+            Some(false) => pins.value &= !mask,
+            None => {}
+        }
+        let mut cmd = MpsseCmd::new();
+        if pin < 8 {
+            cmd.set_gpio_lower(pins.value as u8, pins.direction as u8);
+        } else {
+            cmd.set_gpio_upper((pins.value >> 8) as u8, (pins.direction >> 8) as u8);
+        }
+        cmd
+    }
+
+    /// Send `cmd` to the chip and return its response.
+    pub async fn execute(&self, mut cmd: MpsseCmd) -> io::Result<Vec<u8>> {
+        if cmd.read_len > 0 {
+            cmd.send_immediate();
+        }
+        self.ftdi.transfer(cmd.data, cmd.read_len).await
+    }
+
+    /// Send several batches in a single USB write and return the response of each batch.
+    pub async fn execute_batch(&self, cmds: Vec<MpsseCmd>) -> io::Result<Vec<Vec<u8>>> {
+        let mut combined = MpsseCmd::new();
+        let lengths: Vec<usize> = cmds.iter().map(|x| x.read_len).collect();
+        for cmd in cmds {
+            combined.append(cmd);
+        }
+        let mut response = self.execute(combined).await?.into_iter();
+        Ok(lengths
+            .into_iter()
+            .map(|len| response.by_ref().take(len).collect())
+            .collect())
+    }
+
+    /// Like [`Mpsse::execute`] but blocks the calling thread.
+    #[cfg(feature = "embedded-hal")]
+    pub(crate) fn execute_blocking(&self, mut cmd: MpsseCmd) -> io::Result<Vec<u8>> {
+        if cmd.read_len > 0 {
+            cmd.send_immediate();
+        }
+        crate::blocking_request(&self.ftdi.command_tx, |answer| crate::Command::Transfer {
+            data: cmd.data,
+            read_len: cmd.read_len,
+            answer,
+        })
+    }
+}
+
+/// A single GPIO pin of a device in MPSSE mode, obtained from [`Mpsse::gpio_pin`].
+#[derive(Debug, Clone)]
+pub struct MpsseGpioPin {
+    mpsse: Mpsse,
+    pin: u8,
+}
+
+impl MpsseGpioPin {
+    pub async fn set_direction(&self, direction: PinDirection) -> io::Result<()> {
+        let cmd = self.mpsse.update_pin(self.pin, Some(direction), None);
+        self.mpsse.execute(cmd).await?;
+        Ok(())
+    }
+
+    /// Set the output level, which only takes effect while the pin is an output.
+    pub async fn set_level(&self, high: bool) -> io::Result<()> {
+        let cmd = self.mpsse.update_pin(self.pin, None, Some(high));
+        self.mpsse.execute(cmd).await?;
+        Ok(())
+    }
+
+    pub async fn get_level(&self) -> io::Result<bool> {
+        Ok(self.mpsse.read_pins().await? & (1 << self.pin) != 0)
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    pub(crate) fn set_level_blocking(&self, high: bool) -> io::Result<()> {
+        let cmd = self.mpsse.update_pin(self.pin, None, Some(high));
+        self.mpsse.execute_blocking(cmd)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    pub(crate) fn get_level_blocking(&self) -> io::Result<bool> {
+        let mut cmd = MpsseCmd::new();
+        if self.pin < 8 {
+            cmd.gpio_lower();
+        } else {
+            cmd.gpio_upper();
+        }
+        let response = self.mpsse.execute_blocking(cmd)?;
+        Ok(response[0] & (1 << (self.pin % 8)) != 0)
+    }
+}
+
+impl Handler {
+    pub(crate) fn mpsse_sync(&mut self) -> io::Result<()> {
+        self.device.purge_rx().map_err(status_to_io_error)?;
+        for opcode in SYNC_OPCODES {
+            self.send_data(vec![opcode, SEND_IMMEDIATE])?;
+            let mut last = 0;
+            let mut skipped = 0;
+            loop {
+                let x = self.read_exact(1)?[0];
+                if last == BAD_COMMAND && x == opcode {
+                    break;
+                }
+                last = x;
+                skipped += 1;
+                if skipped > SYNC_MAX_BYTES {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "MPSSE did not echo the synchronization command",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail if the chip sent more bytes than expected, e.g. because it rejected an opcode.
+    pub(crate) fn mpsse_check_response(&mut self) -> io::Result<()> {
+        let extra = self.device.queue_status().map_err(status_to_io_error)?;
+        if extra == 0 {
+            return Ok(());
+        }
+        let data = self.read_exact(extra)?;
+        match data.windows(2).find(|x| x[0] == BAD_COMMAND) {
+            Some(x) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("MPSSE rejected opcode 0x{:02x}", x[1]),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("MPSSE sent {} unexpected bytes", extra),
+            )),
+        }
+    }
+}
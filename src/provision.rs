@@ -0,0 +1,324 @@
+//! Provisioning of many adapters with a common EEPROM template.
+//!
+//! Every device of the template's chip type which matches the [`ProvisionConfig`] gets
+//! the template with a unique serial number. Serial numbers are allocated from a pattern
+//! like `FIX-####`, where the run of `#` is replaced by a zero-padded counter persisted
+//! in a counter file, so numbers are never handed out twice across runs.
+//!
+//! After programming and verifying the EEPROM, the port is cycled such that the device
+//! re-enumerates, and the new identity is expected to show up in [`Ftdi::list_devices`].
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tokio::task::spawn_blocking;
+
+use crate::eeprom::{ChipEeprom, EepromConfig};
+use crate::reset;
+use crate::selector::{base_identity, channel, list_located, LocatedDevice, OpenTarget};
+use crate::{ChipType, DeviceInfo, Ftdi, SerialParams};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone)]
+pub struct ProvisionConfig {
+    /// Configuration programmed into every device, except for the serial number.
+    pub template: EepromConfig,
+    /// Serial number pattern, the first run of `#` is replaced by the counter.
+    pub serial_pattern: String,
+    /// File holding the next counter value, created if missing.
+    pub counter_file: PathBuf,
+    /// Only provision devices with this VID/PID, defaults to any.
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    /// Also provision devices whose serial number already matches the pattern.
+    pub reprovision: bool,
+    /// How long to wait for the device to re-enumerate with its new identity.
+    pub enumeration_timeout: Duration,
+}
+
+impl ProvisionConfig {
+    pub fn new(template: EepromConfig, serial_pattern: &str, counter_file: PathBuf) -> Self {
+        Self {
+            template,
+            serial_pattern: serial_pattern.to_string(),
+            counter_file,
+            vendor_id: None,
+            product_id: None,
+            reprovision: false,
+            enumeration_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ProvisionResult {
+    pub previous: DeviceInfo,
+    /// Location ID of the device before it was provisioned.
+    pub location_id: u32,
+    /// Allocated serial number, `None` if provisioning failed before allocation.
+    pub serial_number: Option<String>,
+    pub result: io::Result<()>,
+}
+
+/// Split the pattern into prefix, counter width and suffix.
+fn parse_pattern(pattern: &str) -> io::Result<(&str, usize, &str)> {
+    let start = pattern.find('#').ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Serial number pattern must contain a run of '#'",
+        )
+    })?;
+    let width = pattern[start..]
+        .find(|x| x != '#')
+        .unwrap_or(pattern.len() - start);
+    Ok((&pattern[..start], width, &pattern[start + width..]))
+}
+
+/// Whether `serial` could have been allocated from `pattern`.
+pub fn matches_pattern(pattern: &str, serial: &str) -> bool {
+    let (prefix, width, suffix) = match parse_pattern(pattern) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    match serial
+        .strip_prefix(prefix)
+        .and_then(|x| x.strip_suffix(suffix))
+    {
+        // allocate_serial never produces counters wider than the run of `#`
+        Some(counter) => counter.len() == width && counter.chars().all(|x| x.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Allocate the next serial number and persist the incremented counter.
+pub fn allocate_serial(pattern: &str, counter_file: &Path) -> io::Result<String> {
+    let (prefix, width, suffix) = parse_pattern(pattern)?;
+    let counter: u64 = match fs::read_to_string(counter_file) {
+        Ok(x) => x.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid counter in {}", counter_file.display()),
+            )
+        })?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => 1,
+        Err(err) => return Err(err),
+    };
+    let counter_text = format!("{:0width$}", counter, width = width);
+    if counter_text.len() > width {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Counter {} exceeds the pattern {}", counter, pattern),
+        ));
+    }
+    fs::write(counter_file, format!("{}\n", counter + 1))?;
+    Ok(format!("{}{}{}", prefix, counter_text, suffix))
+}
+
+fn chip_type(chip: &ChipEeprom) -> ChipType {
+    match chip {
+        ChipEeprom::Ft232r(_) => ChipType::Ft232R,
+        ChipEeprom::Ft232h(_) => ChipType::Ft232H,
+        ChipEeprom::Ft2232h(_) => ChipType::Ft2232H,
+        ChipEeprom::Ft4232h(_) => ChipType::Ft4232H,
+        ChipEeprom::FtX(_) => ChipType::FtXSeries,
+    }
+}
+
+/// Devices which would be provisioned with `config`.
+///
+/// Multi-channel chips share one EEPROM, so only their first channel is listed.
+pub async fn matching_devices(config: &ProvisionConfig) -> io::Result<Vec<DeviceInfo>> {
+    Ok(matching_located(config)
+        .await?
+        .into_iter()
+        .map(|x| x.info)
+        .collect())
+}
+
+/// Like [`matching_devices`], but with the locations which identify devices with blank or
+/// duplicate serial numbers.
+async fn matching_located(config: &ProvisionConfig) -> io::Result<Vec<LocatedDevice>> {
+    let chip_type = chip_type(&config.template.chip);
+    let multi_channel = chip_type.is_multi_channel();
+    let devices = spawn_blocking(list_located).await.unwrap()?;
+    Ok(devices
+        .into_iter()
+        .filter(|x| {
+            let (serial, _) = base_identity(&x.info);
+            x.info.chip_type == chip_type
+                && !x.info.port_open
+                && (!multi_channel || channel(&x.info) == Some('A'))
+                && config.vendor_id.is_none_or(|id| x.info.vendor_id == id)
+                && config.product_id.is_none_or(|id| x.info.product_id == id)
+                && (config.reprovision || !matches_pattern(&config.serial_pattern, serial))
+        })
+        .collect())
+}
+
+/// Provision all matching devices, one after another.
+///
+/// A failure of one device does not stop the others, the outcome of every device is
+/// reported in the returned list.
+pub async fn provision_all(config: &ProvisionConfig) -> io::Result<Vec<ProvisionResult>> {
+    let mut ret = Vec::new();
+    for device in matching_located(config).await? {
+        let serial = match allocate_serial(&config.serial_pattern, &config.counter_file) {
+            Ok(x) => x,
+            Err(err) => {
+                ret.push(ProvisionResult {
+                    previous: device.info,
+                    location_id: device.location_id,
+                    serial_number: None,
+                    result: Err(err),
+                });
+                continue;
+            }
+        };
+        let result = provision_device(config, device.location_id, &serial).await;
+        ret.push(ProvisionResult {
+            previous: device.info,
+            location_id: device.location_id,
+            serial_number: Some(serial),
+            result,
+        });
+    }
+    Ok(ret)
+}
+
+/// Program the template with the serial number `new_serial` into the device at the
+/// location `location_id`.
+///
+/// The device is addressed by its location, since factory serial numbers may be blank or
+/// shared by several devices.
+pub async fn provision_device(
+    config: &ProvisionConfig,
+    location_id: u32,
+    new_serial: &str,
+) -> io::Result<()> {
+    let mut eeprom = config.template.clone();
+    eeprom.serial_number = new_serial.to_string();
+    eeprom.serial_number_enable = true;
+
+    let target = OpenTarget::Location(location_id);
+    let ftdi = Ftdi::open_target(target.clone(), &SerialParams::default()).await?;
+    let result = ftdi.eeprom_program(eeprom.clone()).await;
+    ftdi.close().await;
+    result?;
+
+    let cycled = spawn_blocking(move || {
+        let mut device = target.open()?;
+        reset::cycle(&mut device)
+    })
+    .await
+    .unwrap();
+    if let Err(err) = cycled {
+        log::warn!(
+            "Cycling the port failed, the device must be replugged: {}",
+            err
+        );
+    }
+    wait_for_identity(&eeprom, config.enumeration_timeout).await
+}
+
+async fn wait_for_identity(eeprom: &EepromConfig, timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let found = Ftdi::list_devices().await?.iter().any(|x| {
+            base_identity(x) == (eeprom.serial_number.as_str(), eeprom.description.as_str())
+                && x.vendor_id == eeprom.vendor_id
+                && x.product_id == eeprom.product_id
+        });
+        if found {
+            return Ok(());
+        }
+        if Instant::now() > deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "Device {} did not re-enumerate with its new identity",
+                    eeprom.serial_number
+                ),
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counter file path unique to a test, removed if it exists.
+    fn counter_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "async-ftdi-{}-{}.counter",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn parse_patterns() {
+        assert_eq!(parse_pattern("FIX-####").unwrap(), ("FIX-", 4, ""));
+        assert_eq!(parse_pattern("A##B#").unwrap(), ("A", 2, "B#"));
+        assert_eq!(parse_pattern("###").unwrap(), ("", 3, ""));
+        assert!(parse_pattern("FIX").is_err());
+    }
+
+    #[test]
+    fn match_patterns() {
+        assert!(matches_pattern("FIX-####", "FIX-0042"));
+        assert!(matches_pattern("FIX-###-R", "FIX-007-R"));
+        assert!(!matches_pattern("FIX-###-R", "FIX-007"));
+        assert!(!matches_pattern("FIX-####", "FIX-42"));
+        assert!(!matches_pattern("FIX-####", "FIX-12345"));
+        assert!(!matches_pattern("FIX-####", "FIX-00a1"));
+        assert!(!matches_pattern("FIX-####", "FT123456"));
+        assert!(!matches_pattern("FIX", "FIX"));
+    }
+
+    #[test]
+    fn allocate_from_missing_file() {
+        let path = counter_file("missing");
+        assert_eq!(allocate_serial("FIX-####", &path).unwrap(), "FIX-0001");
+        assert_eq!(allocate_serial("FIX-####", &path).unwrap(), "FIX-0002");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn allocate_with_suffix() {
+        let path = counter_file("suffix");
+        fs::write(&path, "41\n").unwrap();
+        let serial = allocate_serial("X##-REV1", &path).unwrap();
+        assert_eq!(serial, "X41-REV1");
+        assert!(matches_pattern("X##-REV1", &serial));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn allocate_rejects_width_overflow() {
+        let path = counter_file("overflow");
+        fs::write(&path, "99").unwrap();
+        assert_eq!(allocate_serial("S##", &path).unwrap(), "S99");
+        let err = allocate_serial("S##", &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // the counter is not advanced by a failed allocation
+        assert_eq!(fs::read_to_string(&path).unwrap(), "100\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn allocate_rejects_invalid_counter() {
+        let path = counter_file("invalid");
+        fs::write(&path, "twelve").unwrap();
+        let err = allocate_serial("S##", &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+//! A [`Ftdi`] which reopens the device after it was unplugged.
+//!
+//! Once the device fails with a disconnect-class error, [`ReconnectingFtdi`] waits for a
+//! device with the same serial number to reappear, reopens it with the last
+//! [`SerialParams`] and DTR/RTS state and resumes reading and writing. Data in flight
+//! while the device was gone is lost.
+
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
+
+use crate::{Ftdi, SerialParams};
+
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// Delay before the first attempt to find the device again.
+    pub initial_backoff: Duration,
+    /// The delay doubles after every failed attempt up to this limit.
+    pub max_backoff: Duration,
+    /// Give up if the device did not reappear within this time, `None` waits forever.
+    pub give_up_after: Option<Duration>,
+    /// Fail the first read after a reconnect with a [`Reconnected`] error, such that the
+    /// application can resynchronize its protocol.
+    pub report_reconnect: bool,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            give_up_after: None,
+            report_reconnect: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The device was lost with the given error and is being searched.
+    Reconnecting {
+        error: String,
+        attempts: u32,
+    },
+    /// The device did not reappear in time, the handle is unusable.
+    GaveUp,
+}
+
+/// Marker error returned by a read after the device was reopened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reconnected;
+
+impl fmt::Display for Reconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Device was reconnected")
+    }
+}
+
+impl Error for Reconnected {}
+
+/// The error carrying the [`Reconnected`] marker.
+///
+/// Its kind is neither retried like `Interrupted` by `read_exact` and friends, nor a
+/// disconnect which would start another reconnect.
+fn reconnected_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, Reconnected)
+}
+
+/// Whether `err` is the marker returned after a reconnect.
+pub fn is_reconnected(err: &io::Error) -> bool {
+    err.get_ref()
+        .is_some_and(|x| x.downcast_ref::<Reconnected>().is_some())
+}
+
+/// Whether `err` indicates that the device is gone.
+pub fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
+    )
+}
+
+type ReconnectFuture = Pin<Box<dyn Future<Output = io::Result<Ftdi>> + Send>>;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ControlLines {
+    dtr: Option<bool>,
+    rts: Option<bool>,
+}
+
+pub struct ReconnectingFtdi {
+    serial_number: String,
+    params: SerialParams,
+    lines: ControlLines,
+    config: ReconnectConfig,
+    inner: Option<Ftdi>,
+    reconnect: Option<ReconnectFuture>,
+    report_pending: bool,
+    state_tx: Arc<watch::Sender<ConnectionState>>,
+}
+
+impl ReconnectingFtdi {
+    pub async fn open(
+        serial_number: &str,
+        params: &SerialParams,
+        config: ReconnectConfig,
+    ) -> io::Result<Self> {
+        let ftdi = Ftdi::open(serial_number, params).await?;
+        let (state_tx, _) = watch::channel(ConnectionState::Connected);
+        Ok(Self {
+            serial_number: serial_number.to_string(),
+            params: params.clone(),
+            lines: ControlLines::default(),
+            config,
+            inner: Some(ftdi),
+            reconnect: None,
+            report_pending: false,
+            state_tx: Arc::new(state_tx),
+        })
+    }
+
+    /// Subscribe to changes of the connection state.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state_tx.subscribe()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// The currently open device, `None` while reconnecting.
+    pub fn get_ref(&self) -> Option<&Ftdi> {
+        self.inner.as_ref()
+    }
+
+    fn connected(&mut self) -> io::Result<&mut Ftdi> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Device is reconnecting"))
+    }
+
+    /// Apply new serial parameters, they are also used when reopening the device.
+    pub async fn set_params(&mut self, params: SerialParams) -> io::Result<()> {
+        self.params = params.clone();
+        self.connected()?.set_params(params).await
+    }
+
+    pub async fn set_dtr(&mut self, active: bool) -> io::Result<()> {
+        self.lines.dtr = Some(active);
+        self.connected()?.set_dtr(active).await
+    }
+
+    pub async fn set_rts(&mut self, active: bool) -> io::Result<()> {
+        self.lines.rts = Some(active);
+        self.connected()?.set_rts(active).await
+    }
+
+    /// Drop the device and start searching for it again.
+    fn start_reconnect(&mut self, err: &io::Error) {
+        log::debug!("Device {} lost: {}", self.serial_number, err);
+        self.inner = None;
+        self.state_tx.send_replace(ConnectionState::Reconnecting {
+            error: err.to_string(),
+            attempts: 0,
+        });
+        self.reconnect = Some(Box::pin(reconnect(
+            self.serial_number.clone(),
+            self.params.clone(),
+            self.lines,
+            self.config.clone(),
+            self.state_tx.clone(),
+            err.to_string(),
+        )));
+    }
+
+    /// Drive an ongoing reconnect, returns `Ready(Ok)` once a device is available.
+    fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.inner.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        let future = match self.reconnect.as_mut() {
+            Some(x) => x,
+            None => {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Gave up reconnecting the device",
+                )))
+            }
+        };
+        match future.as_mut().poll(cx) {
+            Poll::Ready(Ok(ftdi)) => {
+                log::debug!("Device {} reconnected", self.serial_number);
+                self.reconnect = None;
+                self.inner = Some(ftdi);
+                self.report_pending = self.config.report_reconnect;
+                self.state_tx.send_replace(ConnectionState::Connected);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(err)) => {
+                self.reconnect = None;
+                self.state_tx.send_replace(ConnectionState::GaveUp);
+                Poll::Ready(Err(err))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+async fn reconnect(
+    serial_number: String,
+    params: SerialParams,
+    lines: ControlLines,
+    config: ReconnectConfig,
+    state_tx: Arc<watch::Sender<ConnectionState>>,
+    error: String,
+) -> io::Result<Ftdi> {
+    let start = Instant::now();
+    let mut backoff = config.initial_backoff;
+    let mut attempts = 0;
+    loop {
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(config.max_backoff);
+        attempts += 1;
+        state_tx.send_replace(ConnectionState::Reconnecting {
+            error: error.clone(),
+            attempts,
+        });
+
+        let present = Ftdi::list_devices()
+            .await
+            .is_ok_and(|x| x.iter().any(|x| x.serial_number == serial_number));
+        if present {
+            match open(&serial_number, &params, lines).await {
+                Ok(x) => return Ok(x),
+                Err(err) => log::debug!("Reopening {} failed: {}", serial_number, err),
+            }
+        }
+        if let Some(limit) = config.give_up_after {
+            if start.elapsed() > limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Device {} did not reappear", serial_number),
+                ));
+            }
+        }
+    }
+}
+
+async fn open(serial_number: &str, params: &SerialParams, lines: ControlLines) -> io::Result<Ftdi> {
+    let ftdi = Ftdi::open(serial_number, params).await?;
+    if let Some(x) = lines.dtr {
+        ftdi.set_dtr(x).await?;
+    }
+    if let Some(x) = lines.rts {
+        ftdi.set_rts(x).await?;
+    }
+    Ok(ftdi)
+}
+
+impl AsyncRead for ReconnectingFtdi {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            match self.poll_connected(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+            if self.report_pending {
+                self.report_pending = false;
+                return Poll::Ready(Err(reconnected_error()));
+            }
+            let inner = self.inner.as_mut().unwrap();
+            match Pin::new(inner).poll_read(cx, buf) {
+                Poll::Ready(Err(err)) if is_disconnect(&err) => self.start_reconnect(&err),
+                x => return x,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ReconnectingFtdi {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match self.poll_connected(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+            let inner = self.inner.as_mut().unwrap();
+            match Pin::new(inner).poll_write(cx, buf) {
+                Poll::Ready(Err(err)) if is_disconnect(&err) => self.start_reconnect(&err),
+                x => return x,
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.reconnect = None;
+        match self.inner.as_mut() {
+            Some(inner) => Pin::new(inner).poll_shutdown(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{status_to_io_error, Status};
+    use libftd2xx::FtStatus;
+
+    #[test]
+    fn reconnected_marker() {
+        let err = reconnected_error();
+        assert!(is_reconnected(&err));
+        assert!(!is_disconnect(&err));
+        assert_ne!(err.kind(), io::ErrorKind::Interrupted);
+
+        assert!(!is_reconnected(&io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "Device was reconnected"
+        )));
+        assert!(!is_reconnected(&io::Error::from(
+            io::ErrorKind::ConnectionReset
+        )));
+        assert!(!is_reconnected(&io::Error::other(Status::IoError)));
+    }
+
+    #[test]
+    fn disconnect_errors() {
+        assert!(is_disconnect(&crate::disconnected_error()));
+        assert!(is_disconnect(&status_to_io_error(FtStatus::IO_ERROR)));
+        assert!(is_disconnect(&status_to_io_error(FtStatus::INVALID_HANDLE)));
+        assert!(is_disconnect(&io::Error::from(io::ErrorKind::BrokenPipe)));
+        assert!(is_disconnect(&io::Error::from(
+            io::ErrorKind::UnexpectedEof
+        )));
+
+        assert!(!is_disconnect(&status_to_io_error(
+            FtStatus::INVALID_PARAMETER
+        )));
+        assert!(!is_disconnect(&io::Error::from(io::ErrorKind::TimedOut)));
+        assert!(!is_disconnect(&io::Error::from(io::ErrorKind::Interrupted)));
+    }
+}
@@ -0,0 +1,108 @@
+//! Recovery of unresponsive devices: device reset, port cycling and the deadman timeout.
+
+use std::io;
+use std::mem::ManuallyDrop;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libftd2xx::{FtStatus, Ftdi as FtdiBase, FtdiCommon};
+use libftd2xx_ffi::{FT_CyclePort, FT_ResetDevice, FT_ResetPort, FT_SetDeadmanTimeout, FT_STATUS};
+
+use crate::{
+    request, status_to_io_error, BitMode, Command, Ftdi, Handler, Waker, REENUMERATION_TIMEOUT,
+};
+
+const REOPEN_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Let the device re-enumerate, falling back to a port reset if cycling is not supported.
+///
+/// The handle is invalid afterwards and must be closed.
+pub(crate) fn cycle(device: &mut FtdiBase) -> io::Result<()> {
+    let handle = device.handle();
+    if let Err(err) = check(unsafe { FT_CyclePort(handle) }) {
+        log::debug!("Cycling the port failed, resetting it instead: {}", err);
+        check(unsafe { FT_ResetPort(handle) })?;
+    }
+    Ok(())
+}
+
+fn check(status: FT_STATUS) -> io::Result<()> {
+    if status != 0 {
+        return Err(status_to_io_error(FtStatus::from(status)));
+    }
+    Ok(())
+}
+
+impl Ftdi {
+    /// Reset the chip with `FT_ResetDevice`, the serial parameters are applied again.
+    ///
+    /// The chip is back in [`BitMode::Reset`] afterwards.
+    pub async fn reset_device(&self) -> io::Result<()> {
+        request(&self.command_tx, |answer| Command::ResetDevice { answer }).await
+    }
+
+    /// Let the device re-enumerate on the USB and reopen it with the last [`SerialParams`].
+    ///
+    /// The handle stays usable, data received before the cycle is discarded and the chip
+    /// is back in [`BitMode::Reset`] afterwards.
+    ///
+    /// [`SerialParams`]: crate::SerialParams
+    pub async fn cycle_port(&self) -> io::Result<()> {
+        request(&self.command_tx, |answer| Command::CyclePort { answer }).await
+    }
+
+    /// Time after which the driver gives up on a USB request which the device does not
+    /// answer, the D2XX default is 5 seconds.
+    pub async fn set_deadman_timeout(&self, timeout: Duration) -> io::Result<()> {
+        request(&self.command_tx, |answer| Command::SetDeadmanTimeout {
+            timeout,
+            answer,
+        })
+        .await
+    }
+}
+
+impl Handler {
+    pub(crate) fn reset_device(&mut self) -> io::Result<()> {
+        check(unsafe { FT_ResetDevice(self.device.handle()) })?;
+        self.mode = BitMode::Reset;
+        Self::apply_params(&mut self.device, &self.params)
+    }
+
+    /// Cycle the port and reopen the device.
+    ///
+    /// If the device cannot be reopened or watched, it stays closed and the handler must stop.
+    pub(crate) fn cycle_port(&mut self) -> io::Result<()> {
+        cycle(&mut self.device)?;
+        // stop the waker before its event handle becomes invalid
+        self.waker.take();
+        self.close_device();
+        self.mode = BitMode::Reset;
+
+        let deadline = Instant::now() + REENUMERATION_TIMEOUT;
+        let device = loop {
+            thread::sleep(REOPEN_INTERVAL);
+            match Self::open_device(&self.target, &self.params) {
+                Ok(x) => break x,
+                Err(err) if Instant::now() > deadline => return Err(err),
+                Err(err) => log::debug!("Reopening after port cycle failed: {}", err),
+            }
+        };
+        // the closed handle must not be closed again by dropping it
+        self.device = ManuallyDrop::new(device);
+        self.device_open = true;
+        match Waker::spawn(&mut self.device, self.command_tx.clone()) {
+            Ok(x) => self.waker = Some(x),
+            Err(err) => {
+                self.close_device();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_deadman_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        let ms = timeout.as_millis().min(u32::MAX as u128);
+        check(unsafe { FT_SetDeadmanTimeout(self.device.handle(), ms as _) })
+    }
+}
@@ -0,0 +1,398 @@
+//! Selecting devices by other properties than the serial number.
+//!
+//! Cheap clones often have blank or duplicate serial numbers, so a [`DeviceSelector`]
+//! can also match on the USB location and the channel of multi-channel chips. Devices
+//! opened with [`Ftdi::open_selected`] are opened by their location ID.
+
+use std::ffi::c_void;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::raw::c_char;
+
+use libftd2xx::{list_devices, FtStatus, Ftdi as FtdiBase};
+use libftd2xx_ffi::{FT_GetDeviceInfoDetail, DWORD, FT_HANDLE};
+use tokio::task::spawn_blocking;
+
+use crate::{status_to_io_error, vid_pid, ChipType, DeviceInfo, Ftdi, SerialParams};
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceSelector {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    /// Glob pattern for the description, `*` matches any text and `?` a single character.
+    pub description: Option<String>,
+    /// Glob pattern for the serial number.
+    pub serial_number: Option<String>,
+    /// Location ID as reported by the D2XX library.
+    pub location_id: Option<u32>,
+    /// USB port path like `1-2.3`, only available on Linux.
+    pub port_path: Option<String>,
+    pub chip_type: Option<ChipType>,
+    /// Channel of multi-channel chips, `'A'` to `'D'`.
+    pub channel: Option<char>,
+    /// Select the n-th device of the ones matching all other criteria.
+    pub index: Option<usize>,
+}
+
+/// A device together with its position in the device list and its location.
+#[derive(Debug, Clone)]
+pub(crate) struct LocatedDevice {
+    pub(crate) index: usize,
+    pub(crate) info: DeviceInfo,
+    pub(crate) location_id: u32,
+}
+
+/// How the handler thread finds the device, also when reopening it.
+#[derive(Debug, Clone)]
+pub(crate) enum OpenTarget {
+    SerialNumber(String),
+    Location(u32),
+}
+
+impl OpenTarget {
+    pub(crate) fn open(&self) -> io::Result<FtdiBase> {
+        match self {
+            OpenTarget::SerialNumber(x) => vid_pid::try_each_pair(|| {
+                FtdiBase::with_serial_number(x).map_err(status_to_io_error)
+            }),
+            // the index is only valid for the list of the currently active VID/PID
+            OpenTarget::Location(location) => vid_pid::try_each_pair(|| {
+                let device = list_with_locations()?
+                    .into_iter()
+                    .find(|x| x.location_id == *location)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("No device at location 0x{:x}", location),
+                        )
+                    })?;
+                FtdiBase::with_index(device.index as i32).map_err(status_to_io_error)
+            }),
+        }
+    }
+}
+
+/// List all devices including their location IDs.
+pub(crate) fn list_located() -> io::Result<Vec<LocatedDevice>> {
+    let lists = vid_pid::for_each_pair(list_with_locations)?;
+    let mut ret: Vec<LocatedDevice> = Vec::new();
+    for device in lists.into_iter().flatten() {
+        if !ret.iter().any(|x| x.location_id == device.location_id) {
+            ret.push(device);
+        }
+    }
+    Ok(ret)
+}
+
+/// List the devices visible with the currently active VID/PID.
+fn list_with_locations() -> io::Result<Vec<LocatedDevice>> {
+    let devices = list_devices().map_err(status_to_io_error)?;
+    let mut ret = Vec::with_capacity(devices.len());
+    for (index, info) in devices.into_iter().enumerate() {
+        let mut flags: DWORD = 0;
+        let mut device_type: DWORD = 0;
+        let mut id: DWORD = 0;
+        let mut location_id: DWORD = 0;
+        let mut serial_number = [0 as c_char; 16];
+        let mut description = [0 as c_char; 64];
+        let mut handle = MaybeUninit::<FT_HANDLE>::uninit();
+        // refers to the device list created by `list_devices`
+        let status = unsafe {
+            FT_GetDeviceInfoDetail(
+                index as _,
+                &mut flags,
+                &mut device_type,
+                &mut id,
+                &mut location_id,
+                serial_number.as_mut_ptr() as *mut c_void,
+                description.as_mut_ptr() as *mut c_void,
+                handle.as_mut_ptr(),
+            )
+        };
+        if status != 0 {
+            return Err(status_to_io_error(FtStatus::from(status)));
+        }
+        ret.push(LocatedDevice {
+            index,
+            info: DeviceInfo::from_d2xx(info),
+            location_id: location_id as u32,
+        });
+    }
+    Ok(ret)
+}
+
+/// Match `text` against a glob pattern with `*` and `?` wildcards.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Channel letter of a multi-channel chip, taken from the description suffix.
+pub(crate) fn channel(info: &DeviceInfo) -> Option<char> {
+    if !info.chip_type.is_multi_channel() {
+        return None;
+    }
+    let mut chars = info.description.chars().rev();
+    match (chars.next(), chars.next()) {
+        (Some(x @ 'A'..='D'), Some(' ')) => Some(x),
+        _ => None,
+    }
+}
+
+/// Serial number and description of `info` without the channel suffix of multi-channel chips.
+pub(crate) fn base_identity(info: &DeviceInfo) -> (&str, &str) {
+    match channel(info) {
+        Some(x) => (
+            info.serial_number
+                .strip_suffix(x)
+                .unwrap_or(&info.serial_number),
+            // `channel` only returns a letter for descriptions ending in " A" to " D"
+            &info.description[..info.description.len() - 2],
+        ),
+        None => (&info.serial_number, &info.description),
+    }
+}
+
+/// USB port path of the device from sysfs, matched by serial number.
+#[cfg(target_os = "linux")]
+fn port_path(info: &DeviceInfo) -> Option<String> {
+    // all channels of a chip share the USB serial number without the channel letter
+    let serial = match channel(info) {
+        Some(x) => info
+            .serial_number
+            .strip_suffix(x)
+            .unwrap_or(&info.serial_number),
+        None => &info.serial_number,
+    };
+    if serial.is_empty() {
+        return None;
+    }
+    std::fs::read_dir("/sys/bus/usb/devices")
+        .ok()?
+        .filter_map(|x| x.ok())
+        .find(|x| {
+            std::fs::read_to_string(x.path().join("serial")).is_ok_and(|x| x.trim() == serial)
+        })
+        .map(|x| x.file_name().to_string_lossy().into_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn port_path(_info: &DeviceInfo) -> Option<String> {
+    None
+}
+
+impl DeviceSelector {
+    fn matches(&self, device: &LocatedDevice) -> bool {
+        let info = &device.info;
+        self.vendor_id.is_none_or(|x| info.vendor_id == x)
+            && self.product_id.is_none_or(|x| info.product_id == x)
+            && self
+                .description
+                .as_ref()
+                .is_none_or(|x| glob_match(x, &info.description))
+            && self
+                .serial_number
+                .as_ref()
+                .is_none_or(|x| glob_match(x, &info.serial_number))
+            && self.location_id.is_none_or(|x| device.location_id == x)
+            && self.chip_type.is_none_or(|x| info.chip_type == x)
+            && self
+                .channel
+                .is_none_or(|x| channel(info) == Some(x.to_ascii_uppercase()))
+            && self
+                .port_path
+                .as_ref()
+                .is_none_or(|x| port_path(info).as_ref() == Some(x))
+    }
+
+    fn select(&self, devices: Vec<LocatedDevice>) -> Vec<LocatedDevice> {
+        let matching = devices.into_iter().filter(|x| self.matches(x));
+        match self.index {
+            Some(index) => matching.skip(index).take(1).collect(),
+            None => matching.collect(),
+        }
+    }
+}
+
+impl Ftdi {
+    /// List all devices matching `selector`.
+    pub async fn find(selector: &DeviceSelector) -> io::Result<Vec<DeviceInfo>> {
+        let selector = selector.clone();
+        spawn_blocking(move || {
+            let devices = list_located()?;
+            Ok(selector
+                .select(devices)
+                .into_iter()
+                .map(|x| x.info)
+                .collect())
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Open the single device matching `selector`.
+    ///
+    /// Fails if no device or more than one device matches.
+    pub async fn open_selected(
+        selector: &DeviceSelector,
+        params: &SerialParams,
+    ) -> io::Result<Ftdi> {
+        let selector = selector.clone();
+        let mut devices = spawn_blocking(move || list_located().map(|x| selector.select(x)))
+            .await
+            .unwrap()?;
+        let device = match devices.len() {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No device matches the selector",
+                ))
+            }
+            1 => devices.remove(0),
+            n => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} devices match the selector", n),
+                ))
+            }
+        };
+        Self::open_target(OpenTarget::Location(device.location_id), params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(index: usize, chip_type: ChipType, serial: &str, description: &str) -> LocatedDevice {
+        LocatedDevice {
+            index,
+            info: DeviceInfo {
+                port_open: false,
+                chip_type,
+                vendor_id: 0x0403,
+                product_id: 0x6010,
+                serial_number: serial.to_string(),
+                description: description.to_string(),
+            },
+            location_id: 0x1010 + index as u32,
+        }
+    }
+
+    fn devices() -> Vec<LocatedDevice> {
+        vec![
+            device(0, ChipType::Ft2232H, "FT1234A", "Dual RS232-HS A"),
+            device(1, ChipType::Ft2232H, "FT1234B", "Dual RS232-HS B"),
+            device(2, ChipType::Ft232H, "FT5678", "Single RS232-HS"),
+            device(3, ChipType::Ft2232H, "", "Dual RS232-HS A"),
+        ]
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("FT1234", "FT1234"));
+        assert!(!glob_match("FT1234", "FT12345"));
+        assert!(glob_match("FT*", "FT1234"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*HS A", "Dual RS232-HS A"));
+        assert!(!glob_match("*HS A", "Dual RS232-HS B"));
+        assert!(glob_match("FT12?4", "FT1234"));
+        assert!(!glob_match("FT12?4", "FT124"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn glob_backtracking() {
+        assert!(glob_match("*ab", "aab"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("*A*A", "xAyAzA"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("**?", "x"));
+    }
+
+    #[test]
+    fn channel_from_description() {
+        let devices = devices();
+        assert_eq!(channel(&devices[0].info), Some('A'));
+        assert_eq!(channel(&devices[1].info), Some('B'));
+        assert_eq!(base_identity(&devices[1].info), ("FT1234", "Dual RS232-HS"));
+        assert_eq!(base_identity(&devices[3].info), ("", "Dual RS232-HS"));
+
+        // single channel chips and descriptions without a separated suffix have no channel
+        let single = device(0, ChipType::Ft232H, "FT1", "Adapter A");
+        assert_eq!(channel(&single.info), None);
+        assert_eq!(base_identity(&single.info), ("FT1", "Adapter A"));
+        let joined = device(0, ChipType::Ft4232H, "FT1", "AdapterA");
+        assert_eq!(channel(&joined.info), None);
+        let beyond = device(0, ChipType::Ft4232H, "FT1", "Adapter E");
+        assert_eq!(channel(&beyond.info), None);
+    }
+
+    #[test]
+    fn select_by_properties() {
+        let indices = |selector: &DeviceSelector| -> Vec<usize> {
+            selector.select(devices()).iter().map(|x| x.index).collect()
+        };
+        assert_eq!(indices(&DeviceSelector::default()), [0, 1, 2, 3]);
+
+        let selector = DeviceSelector {
+            description: Some("Dual*".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(indices(&selector), [0, 1, 3]);
+
+        let selector = DeviceSelector {
+            channel: Some('a'),
+            ..Default::default()
+        };
+        assert_eq!(indices(&selector), [0, 3]);
+
+        let selector = DeviceSelector {
+            serial_number: Some("FT????".to_string()),
+            chip_type: Some(ChipType::Ft232H),
+            ..Default::default()
+        };
+        assert_eq!(indices(&selector), [2]);
+
+        let selector = DeviceSelector {
+            location_id: Some(0x1011),
+            ..Default::default()
+        };
+        assert!(selector.matches(&devices()[1]));
+        assert!(!selector.matches(&devices()[0]));
+    }
+
+    #[test]
+    fn select_by_index() {
+        let mut selector = DeviceSelector {
+            channel: Some('A'),
+            index: Some(1),
+            ..Default::default()
+        };
+        let selected = selector.select(devices());
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].index, 3);
+
+        // the index counts matching devices only
+        selector.index = Some(2);
+        assert!(selector.select(devices()).is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+//! SPI master on top of the MPSSE engine.
+//!
+//! The pins are assigned as follows:
+//!
+//! * ADBUS0: SCK
+//! * ADBUS1: MOSI
+//! * ADBUS2: MISO
+//! * ADBUS3: CS (active low)
+//!
+//! All operations of a transaction are sent to the chip in a single USB transfer,
+//! unless the transaction contains a delay.
+
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+use crate::mpsse::{clock_divisor, ClockMode, Edge, Mpsse, MpsseCmd};
+use crate::Ftdi;
+
+const SCK: u8 = 0x01;
+const MOSI: u8 = 0x02;
+const CS: u8 = 0x08;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SpiMode {
+    /// Clock idles low, data is sampled on the rising edge.
+    Mode0,
+    /// Clock idles low, data is sampled on the falling edge.
+    Mode1,
+    /// Clock idles high, data is sampled on the falling edge.
+    Mode2,
+    /// Clock idles high, data is sampled on the rising edge.
+    Mode3,
+}
+
+impl SpiMode {
+    fn clock_idle_high(self) -> bool {
+        matches!(self, SpiMode::Mode2 | SpiMode::Mode3)
+    }
+
+    fn clock_mode(self) -> ClockMode {
+        let (write_edge, read_edge) = match self {
+            SpiMode::Mode0 | SpiMode::Mode3 => (Edge::Falling, Edge::Rising),
+            SpiMode::Mode1 | SpiMode::Mode2 => (Edge::Rising, Edge::Falling),
+        };
+        ClockMode {
+            write_edge,
+            read_edge,
+            ..ClockMode::MSB_FIRST
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpiConfig {
+    pub frequency: u32,
+    pub mode: SpiMode,
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 1_000_000,
+            mode: SpiMode::Mode0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SpiOperation<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+    /// Clock out the second buffer while reading into the first one.
+    ///
+    /// If the buffers differ in length, the shorter write buffer is padded with zeros
+    /// and surplus read data is discarded.
+    Transfer(&'a mut [u8], &'a [u8]),
+    TransferInPlace(&'a mut [u8]),
+    Delay(Duration),
+}
+
+pub struct MpsseSpi {
+    mpsse: Mpsse,
+    config: SpiConfig,
+}
+
+impl MpsseSpi {
+    pub async fn new(ftdi: Ftdi, config: SpiConfig) -> io::Result<Self> {
+        let mpsse = Mpsse::new(ftdi).await?;
+        Self::from_mpsse(mpsse, config).await
+    }
+
+    pub async fn from_mpsse(mpsse: Mpsse, config: SpiConfig) -> io::Result<Self> {
+        let this = Self { mpsse, config };
+        let mut cmd = MpsseCmd::new();
+        cmd.three_phase_clocking(false)
+            .adaptive_clocking(false)
+            .set_clock_divisor(clock_divisor(this.config.frequency, false));
+        this.pins(&mut cmd, false);
+        this.mpsse.execute(cmd).await?;
+        Ok(this)
+    }
+
+    pub fn config(&self) -> &SpiConfig {
+        &self.config
+    }
+
+    pub fn mpsse(&self) -> &Mpsse {
+        &self.mpsse
+    }
+
+    /// Execute all operations with CS asserted.
+    pub async fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> io::Result<()> {
+        self.run(operations, true).await
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.transaction(&mut [SpiOperation::Write(data)]).await
+    }
+
+    pub async fn read(&mut self, data: &mut [u8]) -> io::Result<()> {
+        self.transaction(&mut [SpiOperation::Read(data)]).await
+    }
+
+    pub async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> io::Result<()> {
+        self.transaction(&mut [SpiOperation::Transfer(read, write)])
+            .await
+    }
+
+    pub async fn transfer_in_place(&mut self, data: &mut [u8]) -> io::Result<()> {
+        self.transaction(&mut [SpiOperation::TransferInPlace(data)])
+            .await
+    }
+
+    /// Execute all operations, asserting CS around them if `chip_select` is set.
+    pub(crate) async fn run(
+        &mut self,
+        operations: &mut [SpiOperation<'_>],
+        chip_select: bool,
+    ) -> io::Result<()> {
+        let mode = self.config.mode.clock_mode();
+        let mut cmd = MpsseCmd::new();
+        let mut pending = Vec::new();
+        if chip_select {
+            self.pins(&mut cmd, true);
+        }
+        for idx in 0..operations.len() {
+            match &operations[idx] {
+                SpiOperation::Read(data) => {
+                    cmd.clock_bytes_in(mode, data.len());
+                    pending.push(idx);
+                }
+                SpiOperation::Write(data) => {
+                    cmd.clock_bytes_out(mode, data);
+                }
+                SpiOperation::Transfer(read, write) => {
+                    let mut data = write.to_vec();
+                    data.resize(read.len().max(write.len()), 0);
+                    cmd.clock_bytes(mode, &data);
+                    pending.push(idx);
+                }
+                SpiOperation::TransferInPlace(data) => {
+                    cmd.clock_bytes(mode, data);
+                    pending.push(idx);
+                }
+                SpiOperation::Delay(delay) => {
+                    let delay = *delay;
+                    self.flush(&mut cmd, operations, &mut pending).await?;
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+        if chip_select {
+            self.pins(&mut cmd, false);
+        }
+        self.flush(&mut cmd, operations, &mut pending).await
+    }
+
+    /// Execute the commands collected so far and copy the response to the pending operations.
+    async fn flush(
+        &self,
+        cmd: &mut MpsseCmd,
+        operations: &mut [SpiOperation<'_>],
+        pending: &mut Vec<usize>,
+    ) -> io::Result<()> {
+        if cmd.is_empty() {
+            return Ok(());
+        }
+        let response = self.mpsse.execute(mem::take(cmd)).await?;
+        let mut pos = 0;
+        for idx in pending.drain(..) {
+            match &mut operations[idx] {
+                SpiOperation::Read(data) | SpiOperation::TransferInPlace(data) => {
+                    data.copy_from_slice(&response[pos..pos + data.len()]);
+                    pos += data.len();
+                }
+                SpiOperation::Transfer(read, write) => {
+                    read.copy_from_slice(&response[pos..pos + read.len()]);
+                    pos += read.len().max(write.len());
+                }
+                SpiOperation::Write(_) | SpiOperation::Delay(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn pins(&self, cmd: &mut MpsseCmd, cs_active: bool) {
+        let mut value = 0;
+        if self.config.mode.clock_idle_high() {
+            value |= SCK;
+        }
+        if !cs_active {
+            value |= CS;
+        }
+        self.mpsse.set_protocol_pins(cmd, value, SCK | MOSI | CS);
+    }
+}
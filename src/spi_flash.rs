@@ -0,0 +1,578 @@
+//! Programmer for SPI NOR flash chips on top of [`MpsseSpi`].
+//!
+//! The geometry of the flash is discovered from its SFDP tables. Chips without SFDP
+//! support can be used by passing a [`FlashGeometry`] to [`SpiFlash::with_geometry`],
+//! optionally followed by [`SpiFlash::probe`] to check that a flash responds.
+//! Long running operations report their progress to the [`FlashProgressStream`]
+//! returned by [`SpiFlash::progress`].
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::spi::{MpsseSpi, SpiOperation};
+
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_READ: u8 = 0x03;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_PAGE_PROGRAM_4B: u8 = 0x12;
+const CMD_READ_4B: u8 = 0x13;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_READ_SFDP: u8 = 0x5A;
+const CMD_READ_JEDEC_ID: u8 = 0x9F;
+const CMD_CHIP_ERASE: u8 = 0xC7;
+
+const STATUS_WIP: u8 = 0x01;
+const STATUS_WEL: u8 = 0x02;
+
+const SFDP_SIGNATURE: &[u8; 4] = b"SFDP";
+const SFDP_BASIC_PARAMETERS: u16 = 0xFF00;
+
+/// Erase opcodes with 3-byte addresses and their 4-byte address counterparts.
+const ERASE_OPCODES_4B: [(u8, u8); 3] = [(0x20, 0x21), (0x52, 0x5C), (0xD8, 0xDC)];
+
+/// Size of a single read transfer, also the granularity of the verify progress.
+const READ_CHUNK: usize = 0x10000;
+
+const PROGRAM_TIMEOUT: Duration = Duration::from_millis(50);
+const ERASE_TIMEOUT: Duration = Duration::from_secs(5);
+const CHIP_ERASE_TIMEOUT: Duration = Duration::from_secs(600);
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SfdpParameter {
+    pub id: u16,
+    pub major: u8,
+    pub minor: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Sfdp {
+    pub major: u8,
+    pub minor: u8,
+    pub parameters: Vec<SfdpParameter>,
+}
+
+impl Sfdp {
+    pub fn parameter(&self, id: u16) -> Option<&SfdpParameter> {
+        self.parameters.iter().find(|x| x.id == id)
+    }
+
+    /// Derive the flash geometry from the basic flash parameter table.
+    pub fn geometry(&self) -> io::Result<FlashGeometry> {
+        let table = self
+            .parameter(SFDP_BASIC_PARAMETERS)
+            .ok_or_else(|| invalid_sfdp("Basic flash parameter table missing"))?;
+        let dword = |idx: usize| {
+            table
+                .data
+                .get(idx * 4..idx * 4 + 4)
+                .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        };
+        let density =
+            dword(1).ok_or_else(|| invalid_sfdp("Basic flash parameter table too short"))?;
+        let size_bits = if density & 0x8000_0000 == 0 {
+            density as u64 + 1
+        } else {
+            1_u64
+                .checked_shl(density & 0x7FFF_FFFF)
+                .ok_or_else(|| invalid_sfdp("Invalid flash density"))?
+        };
+        let size = size_bits / 8;
+        if size == 0 || size > u32::MAX as u64 {
+            return Err(invalid_sfdp("Invalid flash density"));
+        }
+
+        // smallest erase type from DWORD 8 and 9, falling back to the 4 KiB erase of DWORD 1
+        let mut erase = None;
+        for idx in [7, 8] {
+            if let Some(x) = dword(idx) {
+                for half in [x & 0xFFFF, x >> 16] {
+                    let exponent = half & 0xFF;
+                    let opcode = (half >> 8) as u8;
+                    if exponent == 0 || exponent >= 32 {
+                        continue;
+                    }
+                    let sector = 1_u32 << exponent;
+                    if erase.is_none_or(|(size, _)| sector < size) {
+                        erase = Some((sector, opcode));
+                    }
+                }
+            }
+        }
+        let first = dword(0).unwrap_or(0);
+        let (sector_size, sector_erase) = match erase {
+            Some(x) => x,
+            None if first & 0x03 == 0x01 => (4096, (first >> 8) as u8),
+            None => return Err(invalid_sfdp("No erase type available")),
+        };
+
+        let page_size = match dword(10) {
+            Some(x) if self.minor >= 5 || self.major > 1 => 1 << ((x >> 4) & 0x0F),
+            _ => 256,
+        };
+
+        Ok(FlashGeometry {
+            size: size as u32,
+            page_size,
+            sector_size,
+            sector_erase,
+            four_byte_address: size > 0x100_0000,
+        })
+    }
+}
+
+fn invalid_sfdp(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("SFDP: {}", msg))
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FlashGeometry {
+    /// Size of the flash in bytes.
+    pub size: u32,
+    pub page_size: u32,
+    /// Size of the smallest erasable unit.
+    pub sector_size: u32,
+    /// Opcode erasing a single sector, given for 3-byte addressing.
+    pub sector_erase: u8,
+    /// Use the dedicated 4-byte address opcodes, required for flashes larger than 16 MiB.
+    pub four_byte_address: bool,
+}
+
+impl Default for FlashGeometry {
+    fn default() -> Self {
+        Self {
+            size: 0x100_0000,
+            page_size: 256,
+            sector_size: 4096,
+            sector_erase: CMD_SECTOR_ERASE,
+            four_byte_address: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FlashStage {
+    Erase,
+    Program,
+    Read,
+    Verify,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FlashProgress {
+    pub stage: FlashStage,
+    /// Number of bytes processed so far in this stage.
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Progress reports of a [`SpiFlash`], the stream ends once the flash is dropped.
+#[derive(Debug)]
+pub struct FlashProgressStream {
+    rx: UnboundedReceiver<FlashProgress>,
+}
+
+impl Stream for FlashProgressStream {
+    type Item = FlashProgress;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+pub struct SpiFlash {
+    spi: MpsseSpi,
+    geometry: FlashGeometry,
+    progress: Option<UnboundedSender<FlashProgress>>,
+}
+
+impl SpiFlash {
+    /// Probe the flash connected to `spi` and read its geometry from the SFDP tables.
+    pub async fn new(spi: MpsseSpi) -> io::Result<Self> {
+        let mut this = Self::with_geometry(spi, FlashGeometry::default());
+        this.probe().await?;
+        this.detect_geometry().await?;
+        Ok(this)
+    }
+
+    pub fn with_geometry(spi: MpsseSpi, geometry: FlashGeometry) -> Self {
+        Self {
+            spi,
+            geometry,
+            progress: None,
+        }
+    }
+
+    pub fn geometry(&self) -> &FlashGeometry {
+        &self.geometry
+    }
+
+    pub fn spi(&mut self) -> &mut MpsseSpi {
+        &mut self.spi
+    }
+
+    /// Subscribe to the progress of all following operations.
+    ///
+    /// Only the most recently returned stream receives progress reports.
+    pub fn progress(&mut self) -> FlashProgressStream {
+        let (tx, rx) = unbounded_channel();
+        self.progress = Some(tx);
+        FlashProgressStream { rx }
+    }
+
+    /// Read the JEDEC ID, failing if no flash responds.
+    pub async fn probe(&mut self) -> io::Result<JedecId> {
+        let id = self.jedec_id().await?;
+        if matches!(id.manufacturer, 0x00 | 0xFF) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No SPI flash detected",
+            ));
+        }
+        Ok(id)
+    }
+
+    /// Replace the geometry by the one read from the SFDP tables.
+    ///
+    /// The geometry is left unchanged if the flash does not support SFDP or its tables
+    /// are invalid.
+    pub async fn detect_geometry(&mut self) -> io::Result<()> {
+        self.geometry = self.read_sfdp().await?.geometry()?;
+        Ok(())
+    }
+
+    pub async fn jedec_id(&mut self) -> io::Result<JedecId> {
+        let mut id = [0; 3];
+        self.spi
+            .transaction(&mut [
+                SpiOperation::Write(&[CMD_READ_JEDEC_ID]),
+                SpiOperation::Read(&mut id),
+            ])
+            .await?;
+        Ok(JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        })
+    }
+
+    /// Read the SFDP header and all parameter tables.
+    pub async fn read_sfdp(&mut self) -> io::Result<Sfdp> {
+        let mut header = [0; 8];
+        self.read_sfdp_raw(0, &mut header).await?;
+        if &header[0..4] != SFDP_SIGNATURE {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Flash does not support SFDP",
+            ));
+        }
+        let count = header[6] as usize + 1;
+        let mut headers = vec![0; count * 8];
+        self.read_sfdp_raw(8, &mut headers).await?;
+        let mut parameters = Vec::with_capacity(count);
+        for x in headers.chunks_exact(8) {
+            let len = x[3] as usize * 4;
+            let pointer = u32::from_le_bytes([x[4], x[5], x[6], 0]);
+            let mut data = vec![0; len];
+            self.read_sfdp_raw(pointer, &mut data).await?;
+            parameters.push(SfdpParameter {
+                id: (x[7] as u16) << 8 | x[0] as u16,
+                major: x[2],
+                minor: x[1],
+                data,
+            });
+        }
+        Ok(Sfdp {
+            major: header[5],
+            minor: header[4],
+            parameters,
+        })
+    }
+
+    async fn read_sfdp_raw(&mut self, addr: u32, data: &mut [u8]) -> io::Result<()> {
+        let [_, a2, a1, a0] = addr.to_be_bytes();
+        self.spi
+            .transaction(&mut [
+                SpiOperation::Write(&[CMD_READ_SFDP, a2, a1, a0, 0]),
+                SpiOperation::Read(data),
+            ])
+            .await
+    }
+
+    pub async fn read_status(&mut self) -> io::Result<u8> {
+        let mut status = [0];
+        self.spi
+            .transaction(&mut [
+                SpiOperation::Write(&[CMD_READ_STATUS]),
+                SpiOperation::Read(&mut status),
+            ])
+            .await?;
+        Ok(status[0])
+    }
+
+    pub async fn read(&mut self, addr: u32, data: &mut [u8]) -> io::Result<()> {
+        self.check_range(addr, data.len())?;
+        let total = data.len();
+        for (idx, chunk) in data.chunks_mut(READ_CHUNK).enumerate() {
+            let offset = idx * READ_CHUNK;
+            let header = self.command(CMD_READ, CMD_READ_4B, addr + offset as u32);
+            self.spi
+                .transaction(&mut [SpiOperation::Write(&header), SpiOperation::Read(chunk)])
+                .await?;
+            self.report(FlashStage::Read, offset + chunk.len(), total);
+        }
+        Ok(())
+    }
+
+    /// Erase all sectors overlapping the given range.
+    pub async fn erase(&mut self, addr: u32, len: usize) -> io::Result<()> {
+        self.check_range(addr, len)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let sector = self.geometry.sector_size;
+        let start = addr - addr % sector;
+        let end = addr as u64 + len as u64;
+        let total = (end - start as u64) as usize;
+        let mut current = start as u64;
+        while current < end {
+            self.erase_sector(current as u32).await?;
+            current += sector as u64;
+            self.report(
+                FlashStage::Erase,
+                (current.min(end) - start as u64) as usize,
+                total,
+            );
+        }
+        Ok(())
+    }
+
+    /// Erase the sector containing `addr`.
+    pub async fn erase_sector(&mut self, addr: u32) -> io::Result<()> {
+        self.check_range(addr, 1)?;
+        let opcode = self.geometry.sector_erase;
+        let opcode_4b = ERASE_OPCODES_4B
+            .iter()
+            .find(|(x, _)| *x == opcode)
+            .map_or(opcode, |(_, x)| *x);
+        let header = self.command(opcode, opcode_4b, addr);
+        self.write_enable().await?;
+        self.spi.write(&header).await?;
+        self.wait_ready(ERASE_TIMEOUT).await
+    }
+
+    pub async fn erase_chip(&mut self) -> io::Result<()> {
+        let total = self.geometry.size as usize;
+        self.report(FlashStage::Erase, 0, total);
+        self.write_enable().await?;
+        self.spi.write(&[CMD_CHIP_ERASE]).await?;
+        self.wait_ready(CHIP_ERASE_TIMEOUT).await?;
+        self.report(FlashStage::Erase, total, total);
+        Ok(())
+    }
+
+    /// Program `data` into already erased flash, splitting it at page boundaries.
+    pub async fn program(&mut self, addr: u32, data: &[u8]) -> io::Result<()> {
+        self.check_range(addr, data.len())?;
+        let page = self.geometry.page_size as usize;
+        let mut done = 0;
+        while done < data.len() {
+            let current = addr + done as u32;
+            let len = (page - current as usize % page).min(data.len() - done);
+            let header = self.command(CMD_PAGE_PROGRAM, CMD_PAGE_PROGRAM_4B, current);
+            self.write_enable().await?;
+            self.spi
+                .transaction(&mut [
+                    SpiOperation::Write(&header),
+                    SpiOperation::Write(&data[done..done + len]),
+                ])
+                .await?;
+            self.wait_ready(PROGRAM_TIMEOUT).await?;
+            done += len;
+            self.report(FlashStage::Program, done, data.len());
+        }
+        Ok(())
+    }
+
+    /// Read back the flash and compare it against `data`.
+    pub async fn verify(&mut self, addr: u32, data: &[u8]) -> io::Result<()> {
+        self.check_range(addr, data.len())?;
+        let mut buf = vec![0; READ_CHUNK];
+        for (idx, expected) in data.chunks(READ_CHUNK).enumerate() {
+            let offset = idx * READ_CHUNK;
+            let actual = &mut buf[..expected.len()];
+            let header = self.command(CMD_READ, CMD_READ_4B, addr + offset as u32);
+            self.spi
+                .transaction(&mut [SpiOperation::Write(&header), SpiOperation::Read(actual)])
+                .await?;
+            if let Some(pos) = actual.iter().zip(expected).position(|(a, b)| a != b) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Verify failed at 0x{:08x}: read 0x{:02x}, expected 0x{:02x}",
+                        addr as usize + offset + pos,
+                        actual[pos],
+                        expected[pos]
+                    ),
+                ));
+            }
+            self.report(FlashStage::Verify, offset + expected.len(), data.len());
+        }
+        Ok(())
+    }
+
+    /// Erase, program and verify `data` at `addr`.
+    ///
+    /// Data in the erased sectors outside of the given range is lost.
+    pub async fn flash(&mut self, addr: u32, data: &[u8]) -> io::Result<()> {
+        self.erase(addr, data.len()).await?;
+        self.program(addr, data).await?;
+        self.verify(addr, data).await
+    }
+
+    async fn write_enable(&mut self) -> io::Result<()> {
+        self.spi.write(&[CMD_WRITE_ENABLE]).await?;
+        if self.read_status().await? & STATUS_WEL == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Flash did not set the write enable latch, is it write protected?",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn wait_ready(&mut self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.read_status().await? & STATUS_WIP == 0 {
+                return Ok(());
+            }
+            if Instant::now() > deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Flash did not finish the write operation",
+                ));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn command(&self, opcode: u8, opcode_4b: u8, addr: u32) -> Vec<u8> {
+        let addr = addr.to_be_bytes();
+        if self.geometry.four_byte_address {
+            vec![opcode_4b, addr[0], addr[1], addr[2], addr[3]]
+        } else {
+            vec![opcode, addr[1], addr[2], addr[3]]
+        }
+    }
+
+    fn check_range(&self, addr: u32, len: usize) -> io::Result<()> {
+        if addr as u64 + len as u64 > self.geometry.size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Range 0x{:08x}+0x{:x} exceeds the flash size of 0x{:x} bytes",
+                    addr, len, self.geometry.size
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, stage: FlashStage, done: usize, total: usize) {
+        if let Some(tx) = &self.progress {
+            if tx.send(FlashProgress { stage, done, total }).is_err() {
+                self.progress = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sfdp(minor: u8, dwords: &[u32]) -> Sfdp {
+        Sfdp {
+            major: 1,
+            minor,
+            parameters: vec![SfdpParameter {
+                id: SFDP_BASIC_PARAMETERS,
+                major: 1,
+                minor,
+                data: dwords.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            }],
+        }
+    }
+
+    #[test]
+    fn geometry_from_erase_types() {
+        let mut dwords = [0; 11];
+        // 4 KiB erase with 0x20, 16 Mbit
+        dwords[0] = 0xFFF1_20E5;
+        dwords[1] = 0x00FF_FFFF;
+        // erase types: 32 KiB with 0x52, 4 KiB with 0x20, 64 KiB with 0xD8, unused
+        dwords[7] = 0x520F_200C;
+        dwords[8] = 0x00FF_D810;
+        // 512 byte pages
+        dwords[10] = 0x0000_0090;
+        let expected = FlashGeometry {
+            size: 0x20_0000,
+            page_size: 512,
+            sector_size: 4096,
+            sector_erase: 0x20,
+            four_byte_address: false,
+        };
+        assert_eq!(sfdp(6, &dwords).geometry().unwrap(), expected);
+
+        // the page size is only reported since JESD216B
+        let geometry = sfdp(0, &dwords).geometry().unwrap();
+        assert_eq!(geometry.page_size, 256);
+    }
+
+    #[test]
+    fn geometry_of_short_table() {
+        // 1 Gbit given as power of two, only the 4 KiB erase of DWORD 1
+        let geometry = sfdp(0, &[0xFFF1_20E5, 0x8000_001E]).geometry().unwrap();
+        let expected = FlashGeometry {
+            size: 0x800_0000,
+            page_size: 256,
+            sector_size: 4096,
+            sector_erase: 0x20,
+            four_byte_address: true,
+        };
+        assert_eq!(geometry, expected);
+    }
+
+    #[test]
+    fn reject_invalid_geometry() {
+        let kind = |x: &Sfdp| x.geometry().unwrap_err().kind();
+        let missing = Sfdp {
+            major: 1,
+            minor: 0,
+            parameters: Vec::new(),
+        };
+        assert_eq!(kind(&missing), io::ErrorKind::InvalidData);
+        assert_eq!(kind(&sfdp(0, &[0xFFF1_20E5])), io::ErrorKind::InvalidData);
+        // 32 Gbit does not fit the 32-bit addresses
+        let too_large = sfdp(0, &[0xFFF1_20E5, 0x8000_0023]);
+        assert_eq!(kind(&too_large), io::ErrorKind::InvalidData);
+        // neither erase types nor the 4 KiB erase
+        let no_erase = sfdp(0, &[0xFFFF_FFFF, 0x00FF_FFFF]);
+        assert_eq!(kind(&no_erase), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,690 @@
+//! Player for Serial Vector Format (SVF) files, as exported by most CPLD and FPGA toolchains.
+//!
+//! The file is parsed into a list of [`SvfCommand`]s first, such that syntax errors are
+//! reported before anything is sent to the target. `PIOMAP` and `PIO` are not supported.
+
+use std::io;
+use std::time::Duration;
+
+use crate::jtag::{Jtag, TapState};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanPattern {
+    pub length: usize,
+    pub tdi: Option<Vec<u8>>,
+    pub tdo: Option<Vec<u8>>,
+    pub mask: Option<Vec<u8>>,
+    pub smask: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunTest {
+    pub run_state: Option<TapState>,
+    pub run_count: usize,
+    pub min_time: Option<Duration>,
+    pub end_state: Option<TapState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrstMode {
+    On,
+    Off,
+    Z,
+    Absent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SvfCommand {
+    EndDr(TapState),
+    EndIr(TapState),
+    Frequency(Option<f64>),
+    Hdr(ScanPattern),
+    Hir(ScanPattern),
+    Tdr(ScanPattern),
+    Tir(ScanPattern),
+    Sdr(ScanPattern),
+    Sir(ScanPattern),
+    RunTest(RunTest),
+    State(Vec<TapState>),
+    Trst(TrstMode),
+}
+
+fn syntax_error(line: usize, msg: impl AsRef<str>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("SVF syntax error in line {}: {}", line, msg.as_ref()),
+    )
+}
+
+fn parse_state(line: usize, name: &str) -> io::Result<TapState> {
+    let state = match name {
+        "RESET" => TapState::TestLogicReset,
+        "IDLE" => TapState::RunTestIdle,
+        "DRSELECT" => TapState::SelectDr,
+        "DRCAPTURE" => TapState::CaptureDr,
+        "DRSHIFT" => TapState::ShiftDr,
+        "DREXIT1" => TapState::Exit1Dr,
+        "DRPAUSE" => TapState::PauseDr,
+        "DREXIT2" => TapState::Exit2Dr,
+        "DRUPDATE" => TapState::UpdateDr,
+        "IRSELECT" => TapState::SelectIr,
+        "IRCAPTURE" => TapState::CaptureIr,
+        "IRSHIFT" => TapState::ShiftIr,
+        "IREXIT1" => TapState::Exit1Ir,
+        "IRPAUSE" => TapState::PauseIr,
+        "IREXIT2" => TapState::Exit2Ir,
+        "IRUPDATE" => TapState::UpdateIr,
+        _ => return Err(syntax_error(line, format!("unknown state `{}`", name))),
+    };
+    Ok(state)
+}
+
+fn parse_stable_state(line: usize, name: &str) -> io::Result<TapState> {
+    let state = parse_state(line, name)?;
+    if !state.is_stable() || matches!(state, TapState::ShiftDr | TapState::ShiftIr) {
+        return Err(syntax_error(
+            line,
+            format!("`{}` is not a valid end state", name),
+        ));
+    }
+    Ok(state)
+}
+
+fn parse_number(line: usize, token: &str) -> io::Result<f64> {
+    token
+        .parse::<f64>()
+        .map_err(|_| syntax_error(line, format!("invalid number `{}`", token)))
+}
+
+/// Convert a hex string (MSB first) into a little endian byte vector holding `length` bits.
+fn parse_hex(line: usize, hex: &str, length: usize) -> io::Result<Vec<u8>> {
+    let mut ret = vec![0_u8; length.div_ceil(8)];
+    for (idx, c) in hex.chars().rev().enumerate() {
+        let nibble = c
+            .to_digit(16)
+            .ok_or_else(|| syntax_error(line, format!("invalid hex digit `{}`", c)))?
+            as u8;
+        if idx / 2 >= ret.len() {
+            if nibble != 0 {
+                return Err(syntax_error(line, "hex value exceeds scan length"));
+            }
+            continue;
+        }
+        ret[idx / 2] |= nibble << ((idx % 2) * 4);
+    }
+    if !length.is_multiple_of(8) && ret[ret.len() - 1] >> (length % 8) != 0 {
+        return Err(syntax_error(line, "hex value exceeds scan length"));
+    }
+    Ok(ret)
+}
+
+/// Split the input into statements, stripping comments. Each statement carries its line number.
+fn statements(text: &str) -> Vec<(usize, String)> {
+    let mut ret = Vec::new();
+    let mut current = String::new();
+    let mut start_line = 1;
+    for (idx, line) in text.lines().enumerate() {
+        let line = match (line.find('!'), line.find("//")) {
+            (Some(a), Some(b)) => &line[..a.min(b)],
+            (Some(a), None) | (None, Some(a)) => &line[..a],
+            (None, None) => line,
+        };
+        for part in line.split_inclusive(';') {
+            if current.trim().is_empty() {
+                start_line = idx + 1;
+            }
+            if let Some(stmt) = part.strip_suffix(';') {
+                current.push(' ');
+                current.push_str(stmt);
+                ret.push((start_line, std::mem::take(&mut current)));
+            } else {
+                current.push(' ');
+                current.push_str(part);
+            }
+        }
+    }
+    ret
+}
+
+/// Split a statement into tokens, a parenthesized hex value is returned as one token.
+fn tokenize(line: usize, stmt: &str) -> io::Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = stmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c == '(' {
+            let mut value = String::from("(");
+            loop {
+                match chars.next() {
+                    Some(')') => break,
+                    Some(x) if x.is_whitespace() => {}
+                    Some(x) => value.push(x),
+                    None => return Err(syntax_error(line, "missing `)`")),
+                }
+            }
+            tokens.push(value);
+            continue;
+        }
+        let mut token = String::from(c);
+        while let Some(x) = chars.peek() {
+            if x.is_whitespace() || *x == '(' {
+                break;
+            }
+            token.push(*x);
+            chars.next();
+        }
+        tokens.push(token.to_ascii_uppercase());
+    }
+    Ok(tokens)
+}
+
+fn parse_scan(line: usize, args: &[String]) -> io::Result<ScanPattern> {
+    let length = args
+        .first()
+        .ok_or_else(|| syntax_error(line, "missing scan length"))?;
+    let length = length
+        .parse::<usize>()
+        .map_err(|_| syntax_error(line, format!("invalid scan length `{}`", length)))?;
+    let mut ret = ScanPattern {
+        length,
+        ..Default::default()
+    };
+    for pair in args[1..].chunks(2) {
+        let value = pair
+            .get(1)
+            .and_then(|x| x.strip_prefix('('))
+            .ok_or_else(|| syntax_error(line, format!("missing value for `{}`", pair[0])))?;
+        let value = Some(parse_hex(line, value, length)?);
+        match pair[0].as_str() {
+            "TDI" => ret.tdi = value,
+            "TDO" => ret.tdo = value,
+            "MASK" => ret.mask = value,
+            "SMASK" => ret.smask = value,
+            x => {
+                return Err(syntax_error(
+                    line,
+                    format!("unknown scan parameter `{}`", x),
+                ))
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_run_test(line: usize, args: &[String]) -> io::Result<RunTest> {
+    let mut ret = RunTest {
+        run_state: None,
+        run_count: 0,
+        min_time: None,
+        end_state: None,
+    };
+    let mut idx = 0;
+    if let Some(x) = args.get(idx) {
+        if x.parse::<f64>().is_err() {
+            ret.run_state = Some(parse_stable_state(line, x)?);
+            idx += 1;
+        }
+    }
+    while idx < args.len() {
+        match args.get(idx + 1).map(|x| x.as_str()) {
+            Some("TCK") | Some("SCK") => {
+                ret.run_count = parse_number(line, &args[idx])? as usize;
+                idx += 2;
+            }
+            Some("SEC") => {
+                let secs = parse_number(line, &args[idx])?;
+                if ret.min_time.is_none() {
+                    ret.min_time = Some(Duration::from_secs_f64(secs));
+                }
+                idx += 2;
+            }
+            _ => match args[idx].as_str() {
+                // the maximum time is only an upper bound, we never exceed the minimum time
+                "MAXIMUM" => idx += 1,
+                "ENDSTATE" => {
+                    let state = args
+                        .get(idx + 1)
+                        .ok_or_else(|| syntax_error(line, "missing end state"))?;
+                    ret.end_state = Some(parse_stable_state(line, state)?);
+                    idx += 2;
+                }
+                x => return Err(syntax_error(line, format!("unexpected `{}`", x))),
+            },
+        }
+    }
+    Ok(ret)
+}
+
+/// Parse the contents of an SVF file.
+pub fn parse(text: &str) -> io::Result<Vec<SvfCommand>> {
+    let mut ret = Vec::new();
+    for (line, stmt) in statements(text) {
+        let tokens = tokenize(line, &stmt)?;
+        let (name, args) = match tokens.split_first() {
+            Some(x) => x,
+            None => continue,
+        };
+        let arg = |idx: usize| {
+            args.get(idx)
+                .map(|x| x.as_str())
+                .ok_or_else(|| syntax_error(line, format!("missing argument for `{}`", name)))
+        };
+        let cmd = match name.as_str() {
+            "ENDDR" => SvfCommand::EndDr(parse_stable_state(line, arg(0)?)?),
+            "ENDIR" => SvfCommand::EndIr(parse_stable_state(line, arg(0)?)?),
+            "FREQUENCY" => match args.first() {
+                Some(x) => SvfCommand::Frequency(Some(parse_number(line, x)?)),
+                None => SvfCommand::Frequency(None),
+            },
+            "HDR" => SvfCommand::Hdr(parse_scan(line, args)?),
+            "HIR" => SvfCommand::Hir(parse_scan(line, args)?),
+            "TDR" => SvfCommand::Tdr(parse_scan(line, args)?),
+            "TIR" => SvfCommand::Tir(parse_scan(line, args)?),
+            "SDR" => SvfCommand::Sdr(parse_scan(line, args)?),
+            "SIR" => SvfCommand::Sir(parse_scan(line, args)?),
+            "RUNTEST" => SvfCommand::RunTest(parse_run_test(line, args)?),
+            "STATE" => SvfCommand::State(
+                args.iter()
+                    .map(|x| parse_state(line, x))
+                    .collect::<io::Result<_>>()?,
+            ),
+            "TRST" => SvfCommand::Trst(match arg(0)? {
+                "ON" => TrstMode::On,
+                "OFF" => TrstMode::Off,
+                "Z" => TrstMode::Z,
+                "ABSENT" => TrstMode::Absent,
+                x => return Err(syntax_error(line, format!("invalid TRST mode `{}`", x))),
+            }),
+            x => return Err(syntax_error(line, format!("unsupported command `{}`", x))),
+        };
+        ret.push(cmd);
+    }
+    Ok(ret)
+}
+
+/// Keeps the values of a scan pattern which carry over to the next scan of the same length.
+#[derive(Default)]
+struct Pattern {
+    length: usize,
+    tdi: Vec<u8>,
+    tdo: Option<Vec<u8>>,
+    mask: Vec<u8>,
+}
+
+impl Pattern {
+    fn update(&mut self, scan: &ScanPattern) {
+        let bytes = scan.length.div_ceil(8);
+        if scan.length != self.length {
+            self.tdi = vec![0; bytes];
+            self.mask = vec![0xFF; bytes];
+            self.length = scan.length;
+        }
+        if let Some(tdi) = &scan.tdi {
+            self.tdi = tdi.clone();
+        }
+        if let Some(mask) = &scan.mask {
+            self.mask = mask.clone();
+        }
+        // TDO is only compared for the scan which specifies it
+        self.tdo = scan.tdo.clone();
+    }
+}
+
+fn concat_bits(parts: &[(&[u8], usize)]) -> Vec<u8> {
+    let total: usize = parts.iter().map(|(_, len)| len).sum();
+    let mut ret = vec![0_u8; total.div_ceil(8)];
+    let mut pos = 0;
+    for (data, len) in parts {
+        for idx in 0..*len {
+            if (data[idx / 8] >> (idx % 8)) & 0x01 != 0 {
+                ret[pos / 8] |= 1 << (pos % 8);
+            }
+            pos += 1;
+        }
+    }
+    ret
+}
+
+fn extract_bits(data: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    let mut ret = vec![0_u8; len.div_ceil(8)];
+    for idx in 0..len {
+        let pos = offset + idx;
+        if (data[pos / 8] >> (pos % 8)) & 0x01 != 0 {
+            ret[idx / 8] |= 1 << (idx % 8);
+        }
+    }
+    ret
+}
+
+pub struct SvfPlayer<'a> {
+    jtag: &'a mut Jtag,
+    end_dr: TapState,
+    end_ir: TapState,
+    run_state: TapState,
+    run_end_state: TapState,
+    frequency: Option<f64>,
+    hdr: Pattern,
+    hir: Pattern,
+    tdr: Pattern,
+    tir: Pattern,
+    sdr: Pattern,
+    sir: Pattern,
+}
+
+impl<'a> SvfPlayer<'a> {
+    pub fn new(jtag: &'a mut Jtag) -> Self {
+        Self {
+            jtag,
+            end_dr: TapState::RunTestIdle,
+            end_ir: TapState::RunTestIdle,
+            run_state: TapState::RunTestIdle,
+            run_end_state: TapState::RunTestIdle,
+            frequency: None,
+            hdr: Default::default(),
+            hir: Default::default(),
+            tdr: Default::default(),
+            tir: Default::default(),
+            sdr: Default::default(),
+            sir: Default::default(),
+        }
+    }
+
+    /// Parse and execute the contents of an SVF file.
+    pub async fn play_str(&mut self, text: &str) -> io::Result<()> {
+        let commands = parse(text)?;
+        self.play(&commands).await
+    }
+
+    pub async fn play(&mut self, commands: &[SvfCommand]) -> io::Result<()> {
+        for (idx, cmd) in commands.iter().enumerate() {
+            log::debug!("SVF command {}: {:?}", idx, cmd);
+            self.execute(cmd).await.map_err(|err| {
+                io::Error::new(err.kind(), format!("SVF command {} failed: {}", idx, err))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub async fn execute(&mut self, cmd: &SvfCommand) -> io::Result<()> {
+        match cmd {
+            SvfCommand::EndDr(state) => self.end_dr = *state,
+            SvfCommand::EndIr(state) => self.end_ir = *state,
+            SvfCommand::Frequency(frequency) => {
+                if let Some(frequency) = frequency {
+                    self.jtag.set_frequency(*frequency as u32).await?;
+                }
+                self.frequency = *frequency;
+            }
+            SvfCommand::Hdr(scan) => self.hdr.update(scan),
+            SvfCommand::Hir(scan) => self.hir.update(scan),
+            SvfCommand::Tdr(scan) => self.tdr.update(scan),
+            SvfCommand::Tir(scan) => self.tir.update(scan),
+            SvfCommand::Sdr(scan) => {
+                self.sdr.update(scan);
+                let end = self.end_dr;
+                self.scan(false, end).await?;
+            }
+            SvfCommand::Sir(scan) => {
+                self.sir.update(scan);
+                let end = self.end_ir;
+                self.scan(true, end).await?;
+            }
+            SvfCommand::RunTest(run) => self.run_test(run).await?,
+            SvfCommand::State(states) => {
+                for state in states {
+                    self.jtag.goto(*state).await?;
+                }
+            }
+            SvfCommand::Trst(mode) => {
+                if *mode == TrstMode::On {
+                    self.jtag.reset().await?;
+                } else {
+                    log::debug!("No TRST pin available, ignoring TRST {:?}", mode);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn scan(&mut self, ir: bool, end: TapState) -> io::Result<()> {
+        let (header, body, trailer) = if ir {
+            (&self.hir, &self.sir, &self.tir)
+        } else {
+            (&self.hdr, &self.sdr, &self.tdr)
+        };
+        if body.length == 0 {
+            // a scan of length zero only moves to the end state
+            return self.jtag.goto(end).await;
+        }
+        let tdi = concat_bits(&[
+            (&header.tdi, header.length),
+            (&body.tdi, body.length),
+            (&trailer.tdi, trailer.length),
+        ]);
+        let total = header.length + body.length + trailer.length;
+        let offset = header.length;
+        let expected = body.tdo.clone();
+        let mask = body.mask.clone();
+        let length = body.length;
+        let tdo = if ir {
+            self.jtag.shift_ir(&tdi, total, end).await?
+        } else {
+            self.jtag.shift_dr(&tdi, total, end).await?
+        };
+        if let Some(expected) = expected {
+            let actual = extract_bits(&tdo, offset, length);
+            let mismatch = actual
+                .iter()
+                .zip(expected.iter())
+                .zip(mask.iter())
+                .any(|((a, e), m)| (a & m) != (e & m));
+            if mismatch {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "TDO mismatch, expected {:02x?} but got {:02x?} (mask {:02x?})",
+                        expected, actual, mask
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_test(&mut self, run: &RunTest) -> io::Result<()> {
+        if let Some(state) = run.run_state {
+            self.run_state = state;
+            // the end state defaults to the run state if a run state is given
+            self.run_end_state = state;
+        }
+        if let Some(state) = run.end_state {
+            self.run_end_state = state;
+        }
+        let mut cycles = run.run_count;
+        if let (Some(min_time), Some(frequency)) = (run.min_time, self.frequency) {
+            cycles = cycles.max((min_time.as_secs_f64() * frequency).ceil() as usize);
+        }
+        self.jtag.run_in_state(self.run_state, cycles).await?;
+        if let (Some(min_time), None) = (run.min_time, self.frequency) {
+            // without a known TCK frequency the clocks cannot be used to measure time
+            tokio::time::sleep(min_time).await;
+        }
+        self.jtag.goto(self.run_end_state).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(length: usize) -> ScanPattern {
+        ScanPattern {
+            length,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_scans_with_masks() {
+        let commands = parse(
+            "HDR 0;\n\
+             HIR 2 TDI (3);\n\
+             TDR 1 TDI (1) SMASK (1);\n\
+             TIR 0;\n\
+             SIR 10 TDI (2A5) TDO (155) MASK (3FF);\n\
+             SDR 32 TDI (00000000)\n  TDO (4BA00477) MASK (0FFFFFFF);",
+        )
+        .unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                SvfCommand::Hdr(scan(0)),
+                SvfCommand::Hir(ScanPattern {
+                    tdi: Some(vec![0x03]),
+                    ..scan(2)
+                }),
+                SvfCommand::Tdr(ScanPattern {
+                    tdi: Some(vec![0x01]),
+                    smask: Some(vec![0x01]),
+                    ..scan(1)
+                }),
+                SvfCommand::Tir(scan(0)),
+                SvfCommand::Sir(ScanPattern {
+                    tdi: Some(vec![0xA5, 0x02]),
+                    tdo: Some(vec![0x55, 0x01]),
+                    mask: Some(vec![0xFF, 0x03]),
+                    ..scan(10)
+                }),
+                SvfCommand::Sdr(ScanPattern {
+                    tdi: Some(vec![0x00; 4]),
+                    tdo: Some(vec![0x77, 0x04, 0xA0, 0x4B]),
+                    mask: Some(vec![0xFF, 0xFF, 0xFF, 0x0F]),
+                    ..scan(32)
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_hex_longer_than_scan() {
+        assert!(parse("SIR 4 TDI (1F);").is_err());
+        // leading zeros are fine
+        assert!(parse("SIR 4 TDI (0F);").is_ok());
+    }
+
+    #[test]
+    fn parse_run_test() {
+        let commands = parse(
+            "RUNTEST 100 TCK;\n\
+             RUNTEST IDLE 10 TCK 1E-3 SEC MAXIMUM 1 SEC ENDSTATE DRPAUSE;\n\
+             RUNTEST DRPAUSE 5E-2 SEC;",
+        )
+        .unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                SvfCommand::RunTest(RunTest {
+                    run_state: None,
+                    run_count: 100,
+                    min_time: None,
+                    end_state: None,
+                }),
+                SvfCommand::RunTest(RunTest {
+                    run_state: Some(TapState::RunTestIdle),
+                    run_count: 10,
+                    min_time: Some(Duration::from_millis(1)),
+                    end_state: Some(TapState::PauseDr),
+                }),
+                SvfCommand::RunTest(RunTest {
+                    run_state: Some(TapState::PauseDr),
+                    run_count: 0,
+                    min_time: Some(Duration::from_millis(50)),
+                    end_state: None,
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_end_states() {
+        let commands = parse("ENDIR IRPAUSE;\nENDDR IDLE;").unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                SvfCommand::EndIr(TapState::PauseIr),
+                SvfCommand::EndDr(TapState::RunTestIdle),
+            ]
+        );
+        // shift states and transient states are no valid end states
+        assert!(parse("ENDDR DRSHIFT;").is_err());
+        assert!(parse("ENDIR IRUPDATE;").is_err());
+    }
+
+    #[test]
+    fn parse_state_paths() {
+        let commands = parse("STATE RESET;\nSTATE DRSELECT DRCAPTURE DREXIT1 DRPAUSE;").unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                SvfCommand::State(vec![TapState::TestLogicReset]),
+                SvfCommand::State(vec![
+                    TapState::SelectDr,
+                    TapState::CaptureDr,
+                    TapState::Exit1Dr,
+                    TapState::PauseDr,
+                ]),
+            ]
+        );
+        assert!(parse("STATE NOWHERE;").is_err());
+    }
+
+    #[test]
+    fn parse_comments_and_line_numbers() {
+        let commands = parse(
+            "! header comment\n\
+             // another comment\n\
+             trst off; frequency 1e6 hz;\n\
+             FREQUENCY;",
+        )
+        .unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                SvfCommand::Trst(TrstMode::Off),
+                SvfCommand::Frequency(Some(1e6)),
+                SvfCommand::Frequency(None),
+            ]
+        );
+        let err = parse("ENDDR IDLE;\n\nPIO (HLX);").unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{}", err);
+    }
+
+    #[test]
+    fn pattern_carries_over_tdi_and_mask() {
+        let mut pattern = Pattern::default();
+        pattern.update(&ScanPattern {
+            tdi: Some(vec![0x0A]),
+            tdo: Some(vec![0x05]),
+            mask: Some(vec![0x0C]),
+            ..scan(4)
+        });
+        pattern.update(&scan(4));
+        assert_eq!(pattern.tdi, vec![0x0A]);
+        assert_eq!(pattern.mask, vec![0x0C]);
+        assert_eq!(pattern.tdo, None);
+        // a different length resets the pattern
+        pattern.update(&scan(12));
+        assert_eq!(pattern.tdi, vec![0x00, 0x00]);
+        assert_eq!(pattern.mask, vec![0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn concat_and_extract_bits() {
+        let data = concat_bits(&[(&[0x03], 2), (&[0xA5, 0x02], 10), (&[0x01], 1)]);
+        assert_eq!(data, vec![0x97, 0x1A]);
+        assert_eq!(extract_bits(&data, 2, 10), vec![0xA5, 0x02]);
+        assert_eq!(extract_bits(&data, 12, 1), vec![0x01]);
+    }
+}
@@ -0,0 +1,405 @@
+//! ARM Serial Wire Debug on top of the MPSSE engine.
+//!
+//! The pins are assigned as follows:
+//!
+//! * ADBUS0: SWCLK
+//! * ADBUS1: SWDIO (out), connected to SWDIO through a series resistor
+//! * ADBUS2: SWDIO (in)
+//!
+//! Every access takes two USB transfers: the request together with the acknowledge, and
+//! the data phase. If the target answers with WAIT or FAULT, only the turnaround is
+//! clocked instead of the data phase, as expected by a target without overrun detection.
+
+use std::fmt;
+use std::io;
+
+use crate::mpsse::{clock_divisor, ClockMode, Mpsse, MpsseCmd};
+use crate::Ftdi;
+
+const SWCLK: u8 = 0x01;
+const SWDIO: u8 = 0x02;
+
+const ACK_OK: u8 = 0b001;
+const ACK_WAIT: u8 = 0b010;
+const ACK_FAULT: u8 = 0b100;
+
+/// Number of times an access is retried if the target answers with WAIT.
+const WAIT_RETRIES: usize = 100;
+
+/// Magic sequence switching the debug port from JTAG to SWD, sent LSB first.
+const JTAG_TO_SWD: u16 = 0xE79E;
+
+pub const DP_DPIDR: u8 = 0x00;
+pub const DP_ABORT: u8 = 0x00;
+pub const DP_CTRL_STAT: u8 = 0x04;
+pub const DP_SELECT: u8 = 0x08;
+pub const DP_RDBUFF: u8 = 0x0C;
+
+const ABORT_CLEAR_ALL: u32 = 0x1E;
+const CTRL_STAT_CDBGPWRUPREQ: u32 = 1 << 28;
+const CTRL_STAT_CDBGPWRUPACK: u32 = 1 << 29;
+const CTRL_STAT_CSYSPWRUPREQ: u32 = 1 << 30;
+const CTRL_STAT_CSYSPWRUPACK: u32 = 1 << 31;
+
+const MEM_AP_CSW: u8 = 0x00;
+const MEM_AP_TAR: u8 = 0x04;
+const MEM_AP_DRW: u8 = 0x0C;
+
+/// 32-bit accesses with single auto-increment, privileged debugger data accesses.
+const CSW_WORD_AUTO_INCREMENT: u32 = 0x2300_0052;
+
+/// The TAR auto-increment is only guaranteed to work within a 1 KiB block.
+const AUTO_INCREMENT_BLOCK: u32 = 0x400;
+
+#[derive(Debug)]
+pub enum SwdError {
+    /// The target kept answering with WAIT.
+    Wait,
+    /// The target answered with FAULT, the sticky error flags have been cleared.
+    Fault,
+    /// The target answered with an invalid acknowledge, usually the target is not connected.
+    Protocol(u8),
+    /// The parity of the data read from the target did not match.
+    Parity,
+    Io(io::Error),
+}
+
+impl fmt::Display for SwdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwdError::Wait => write!(f, "SWD target answered with WAIT"),
+            SwdError::Fault => write!(f, "SWD target answered with FAULT"),
+            SwdError::Protocol(ack) => write!(f, "Invalid SWD acknowledge 0b{:03b}", ack),
+            SwdError::Parity => write!(f, "SWD parity error"),
+            SwdError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SwdError {}
+
+impl From<io::Error> for SwdError {
+    fn from(x: io::Error) -> Self {
+        SwdError::Io(x)
+    }
+}
+
+impl From<SwdError> for io::Error {
+    fn from(x: SwdError) -> Self {
+        match x {
+            SwdError::Io(err) => err,
+            x => io::Error::other(x),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Port {
+    Dp,
+    Ap,
+}
+
+fn request_byte(port: Port, read: bool, addr: u8) -> u8 {
+    let ap = port == Port::Ap;
+    let a2 = addr & 0x04 != 0;
+    let a3 = addr & 0x08 != 0;
+    let parity = ap ^ read ^ a2 ^ a3;
+    0x01 | (ap as u8) << 1
+        | (read as u8) << 2
+        | (a2 as u8) << 3
+        | (a3 as u8) << 4
+        | (parity as u8) << 5
+        | 0x80
+}
+
+fn check_ack(ack: u8) -> Result<(), SwdError> {
+    match ack {
+        ACK_OK => Ok(()),
+        ACK_WAIT => Err(SwdError::Wait),
+        ACK_FAULT => Err(SwdError::Fault),
+        x => Err(SwdError::Protocol(x)),
+    }
+}
+
+pub struct Swd {
+    mpsse: Mpsse,
+    select: Option<u32>,
+}
+
+impl Swd {
+    pub async fn new(ftdi: Ftdi, frequency: u32) -> io::Result<Self> {
+        let mpsse = Mpsse::new(ftdi).await?;
+        Self::from_mpsse(mpsse, frequency).await
+    }
+
+    pub async fn from_mpsse(mpsse: Mpsse, frequency: u32) -> io::Result<Self> {
+        let this = Self {
+            mpsse,
+            select: None,
+        };
+        let mut cmd = MpsseCmd::new();
+        cmd.three_phase_clocking(false)
+            .adaptive_clocking(false)
+            .set_clock_divisor(clock_divisor(frequency, false));
+        this.drive(&mut cmd, true);
+        this.mpsse.execute(cmd).await?;
+        Ok(this)
+    }
+
+    pub fn mpsse(&self) -> &Mpsse {
+        &self.mpsse
+    }
+
+    /// Clock at least 50 cycles with SWDIO high, followed by idle cycles.
+    pub async fn line_reset(&mut self) -> io::Result<()> {
+        let mut cmd = MpsseCmd::new();
+        self.push_line_reset(&mut cmd);
+        cmd.clock_bits_out(ClockMode::LSB_FIRST, 0x00, 8);
+        self.mpsse.execute(cmd).await?;
+        self.select = None;
+        Ok(())
+    }
+
+    /// Switch a SWJ-DP from JTAG to SWD, clear all errors and return the DPIDR.
+    pub async fn jtag_to_swd(&mut self) -> Result<u32, SwdError> {
+        let mut cmd = MpsseCmd::new();
+        self.push_line_reset(&mut cmd);
+        cmd.clock_bytes_out(ClockMode::LSB_FIRST, &JTAG_TO_SWD.to_le_bytes());
+        self.push_line_reset(&mut cmd);
+        cmd.clock_bits_out(ClockMode::LSB_FIRST, 0x00, 8);
+        self.mpsse.execute(cmd).await?;
+        self.select = None;
+        // reading DPIDR is mandatory to leave the reset state
+        let dpidr = self.read_dp(DP_DPIDR).await?;
+        self.write_dp(DP_ABORT, ABORT_CLEAR_ALL).await?;
+        Ok(dpidr)
+    }
+
+    /// Request debug and system power.
+    pub async fn power_up(&mut self) -> Result<(), SwdError> {
+        self.write_dp(
+            DP_CTRL_STAT,
+            CTRL_STAT_CSYSPWRUPREQ | CTRL_STAT_CDBGPWRUPREQ,
+        )
+        .await?;
+        let acks = CTRL_STAT_CSYSPWRUPACK | CTRL_STAT_CDBGPWRUPACK;
+        for _ in 0..WAIT_RETRIES {
+            if self.read_dp(DP_CTRL_STAT).await? & acks == acks {
+                return Ok(());
+            }
+        }
+        Err(SwdError::Io(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "Target did not acknowledge the power-up request",
+        )))
+    }
+
+    /// Clear the sticky error flags in CTRL/STAT.
+    pub async fn clear_errors(&mut self) -> Result<(), SwdError> {
+        self.write_dp(DP_ABORT, ABORT_CLEAR_ALL).await
+    }
+
+    pub async fn read_dp(&mut self, addr: u8) -> Result<u32, SwdError> {
+        self.read(Port::Dp, addr).await
+    }
+
+    pub async fn write_dp(&mut self, addr: u8, value: u32) -> Result<(), SwdError> {
+        self.write(Port::Dp, addr, value).await
+    }
+
+    /// Read an AP register, the posted result is fetched from RDBUFF.
+    pub async fn read_ap(&mut self, ap: u8, addr: u8) -> Result<u32, SwdError> {
+        self.select_ap(ap, addr).await?;
+        self.read(Port::Ap, addr).await?;
+        self.read(Port::Dp, DP_RDBUFF).await
+    }
+
+    pub async fn write_ap(&mut self, ap: u8, addr: u8, value: u32) -> Result<(), SwdError> {
+        self.select_ap(ap, addr).await?;
+        self.write(Port::Ap, addr, value).await
+    }
+
+    /// Access the memory behind the MEM-AP with index `ap`.
+    pub fn mem_ap(&mut self, ap: u8) -> MemAp<'_> {
+        MemAp { swd: self, ap }
+    }
+
+    async fn select_ap(&mut self, ap: u8, addr: u8) -> Result<(), SwdError> {
+        let select = (ap as u32) << 24 | (addr as u32 & 0xF0);
+        if self.select != Some(select) {
+            self.write(Port::Dp, DP_SELECT, select).await?;
+            self.select = Some(select);
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self, port: Port, addr: u8) -> Result<u32, SwdError> {
+        self.access_with_retry(port, addr, None).await
+    }
+
+    async fn write(&mut self, port: Port, addr: u8, value: u32) -> Result<(), SwdError> {
+        self.access_with_retry(port, addr, Some(value)).await?;
+        Ok(())
+    }
+
+    /// Repeat an access while the target answers with WAIT, clear the errors on FAULT.
+    async fn access_with_retry(
+        &mut self,
+        port: Port,
+        addr: u8,
+        value: Option<u32>,
+    ) -> Result<u32, SwdError> {
+        for _ in 0..WAIT_RETRIES {
+            match self.access(port, addr, value).await {
+                Err(SwdError::Wait) => continue,
+                Err(SwdError::Fault) => {
+                    log::debug!("SWD fault, clearing sticky errors");
+                    self.access(Port::Dp, DP_ABORT, Some(ABORT_CLEAR_ALL))
+                        .await?;
+                    return Err(SwdError::Fault);
+                }
+                x => return x,
+            }
+        }
+        Err(SwdError::Wait)
+    }
+
+    /// Execute a single access, writing `value` or reading if it is `None`.
+    async fn access(&mut self, port: Port, addr: u8, value: Option<u32>) -> Result<u32, SwdError> {
+        let mut cmd = MpsseCmd::new();
+        let request = request_byte(port, value.is_none(), addr);
+        cmd.clock_bits_out(ClockMode::LSB_FIRST, request, 8);
+        self.drive(&mut cmd, false);
+        // turnaround and acknowledge
+        cmd.clock_bits_in(ClockMode::LSB_FIRST, 4);
+        let response = self.mpsse.execute(cmd).await?;
+        let ack = (response[0] >> 5) & 0x07;
+
+        if let Err(err) = check_ack(ack) {
+            // no data phase follows WAIT and FAULT, only the turnaround
+            let mut cmd = MpsseCmd::new();
+            cmd.clock_bits_in(ClockMode::LSB_FIRST, 1);
+            self.drive(&mut cmd, true);
+            self.push_idle(&mut cmd);
+            self.mpsse.execute(cmd).await?;
+            return Err(err);
+        }
+
+        let mut cmd = MpsseCmd::new();
+        match value {
+            Some(value) => {
+                // turnaround
+                cmd.clock_bits_in(ClockMode::LSB_FIRST, 1);
+                self.drive(&mut cmd, true);
+                cmd.clock_bytes_out(ClockMode::LSB_FIRST, &value.to_le_bytes());
+                let parity = (value.count_ones() % 2) as u8;
+                cmd.clock_bits_out(ClockMode::LSB_FIRST, parity, 1);
+                self.push_idle(&mut cmd);
+                self.mpsse.execute(cmd).await?;
+                Ok(value)
+            }
+            None => {
+                cmd.clock_bytes_in(ClockMode::LSB_FIRST, 4);
+                // parity and turnaround
+                cmd.clock_bits_in(ClockMode::LSB_FIRST, 2);
+                self.drive(&mut cmd, true);
+                self.push_idle(&mut cmd);
+                let response = self.mpsse.execute(cmd).await?;
+                let value =
+                    u32::from_le_bytes([response[0], response[1], response[2], response[3]]);
+                let parity = (response[4] >> 6) & 0x01;
+                if value.count_ones() % 2 != parity as u32 {
+                    return Err(SwdError::Parity);
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    fn push_line_reset(&self, cmd: &mut MpsseCmd) {
+        self.drive(cmd, true);
+        cmd.clock_bytes_out(ClockMode::LSB_FIRST, &[0xFF; 7]);
+    }
+
+    fn push_idle(&self, cmd: &mut MpsseCmd) {
+        cmd.clock_bits_out(ClockMode::LSB_FIRST, 0x00, 2);
+    }
+
+    /// Drive SWDIO from the host, or release it for the target if `drive` is false.
+    fn drive(&self, cmd: &mut MpsseCmd, drive: bool) {
+        if drive {
+            self.mpsse.set_protocol_pins(cmd, SWDIO, SWCLK | SWDIO);
+        } else {
+            self.mpsse.set_protocol_pins(cmd, SWDIO, SWCLK);
+        }
+    }
+}
+
+/// Memory access through a MEM-AP, obtained from [`Swd::mem_ap`].
+pub struct MemAp<'a> {
+    swd: &'a mut Swd,
+    ap: u8,
+}
+
+impl<'a> MemAp<'a> {
+    pub async fn read_u32(&mut self, addr: u32) -> Result<u32, SwdError> {
+        self.setup(addr).await?;
+        self.swd.read_ap(self.ap, MEM_AP_DRW).await
+    }
+
+    pub async fn write_u32(&mut self, addr: u32, value: u32) -> Result<(), SwdError> {
+        self.setup(addr).await?;
+        self.swd.write_ap(self.ap, MEM_AP_DRW, value).await
+    }
+
+    /// Read consecutive words starting at the word aligned address `addr`.
+    pub async fn read_block(&mut self, addr: u32, data: &mut [u32]) -> Result<(), SwdError> {
+        check_aligned(addr)?;
+        let mut idx = 0;
+        while idx < data.len() {
+            let current = addr + 4 * idx as u32;
+            let words = ((AUTO_INCREMENT_BLOCK - current % AUTO_INCREMENT_BLOCK) / 4) as usize;
+            let end = data.len().min(idx + words);
+            self.setup(current).await?;
+            // AP reads are posted, each read returns the result of the previous one
+            self.swd.select_ap(self.ap, MEM_AP_DRW).await?;
+            self.swd.read(Port::Ap, MEM_AP_DRW).await?;
+            for x in data[idx..end - 1].iter_mut() {
+                *x = self.swd.read(Port::Ap, MEM_AP_DRW).await?;
+            }
+            data[end - 1] = self.swd.read(Port::Dp, DP_RDBUFF).await?;
+            idx = end;
+        }
+        Ok(())
+    }
+
+    /// Write consecutive words starting at the word aligned address `addr`.
+    pub async fn write_block(&mut self, addr: u32, data: &[u32]) -> Result<(), SwdError> {
+        check_aligned(addr)?;
+        for (idx, x) in data.iter().enumerate() {
+            let current = addr + 4 * idx as u32;
+            if idx == 0 || current.is_multiple_of(AUTO_INCREMENT_BLOCK) {
+                self.setup(current).await?;
+            }
+            self.swd.write_ap(self.ap, MEM_AP_DRW, *x).await?;
+        }
+        Ok(())
+    }
+
+    async fn setup(&mut self, addr: u32) -> Result<(), SwdError> {
+        self.swd
+            .write_ap(self.ap, MEM_AP_CSW, CSW_WORD_AUTO_INCREMENT)
+            .await?;
+        self.swd.write_ap(self.ap, MEM_AP_TAR, addr).await
+    }
+}
+
+fn check_aligned(addr: u32) -> Result<(), SwdError> {
+    if !addr.is_multiple_of(4) {
+        return Err(SwdError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Address 0x{:08x} is not word aligned", addr),
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,241 @@
+//! FT245 synchronous FIFO mode of the FT232H and FT2232H.
+//!
+//! The chip must be configured for 245 FIFO mode in its EEPROM. Once switched into
+//! synchronous FIFO mode, the handler thread stops serving the UART byte stream and a
+//! dedicated reader thread continuously reads from the device in large blocks instead of
+//! polling the receive queue.
+//!
+//! The D2XX library offers no overlapped reads on Linux and macOS. Its driver keeps the
+//! bulk IN transfers queued on its own, sized by the chunk size, and the reader keeps an
+//! `FT_Read` outstanding at all times: it never waits for writes, commands or the
+//! consumer, and reads into reused buffers queued towards the consumer. Whether this
+//! sustains the 40 MB/s of the FIFO interface depends on the host; measure it with the
+//! `sync_fifo_throughput` example.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_core::Stream;
+use libftd2xx::{FtStatus, FtdiCommon};
+use libftd2xx_ffi::{FT_Read, DWORD, FT_HANDLE};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender, UnboundedSender};
+
+use crate::{request, status_to_io_error, BitMode, Command, Ftdi, Handler};
+
+#[derive(Debug, Clone)]
+pub struct SyncFifoConfig {
+    /// Size of a single read, every [`SyncFifoEvent::Data`] is at most this large.
+    pub chunk_size: usize,
+    /// Number of chunks buffered before the reader reports an overrun.
+    pub queue_depth: usize,
+}
+
+impl Default for SyncFifoConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 64 * 1024,
+            queue_depth: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SyncFifoEvent {
+    Data(Bytes),
+    /// The consumer fell behind and `lost_bytes` bytes were discarded.
+    Overrun {
+        lost_bytes: usize,
+    },
+}
+
+/// A device in synchronous FIFO mode, received data is delivered as a [`Stream`].
+#[derive(Debug)]
+pub struct SyncFifo {
+    ftdi: Ftdi,
+    rx: EventReceiver,
+}
+
+impl Ftdi {
+    /// Switch the device into synchronous FIFO mode.
+    pub async fn into_sync_fifo(self, config: SyncFifoConfig) -> io::Result<SyncFifo> {
+        let rx = request(&self.command_tx, |answer| Command::StartSyncFifo {
+            config,
+            answer,
+        })
+        .await?;
+        Ok(SyncFifo { ftdi: self, rx })
+    }
+}
+
+impl SyncFifo {
+    /// Queue `data` to be written to the FIFO.
+    pub fn write(&self, data: &[u8]) -> io::Result<()> {
+        self.ftdi
+            .command_tx
+            .send(Command::Send(data.to_vec()))
+            .map_err(|_| crate::disconnected_error())
+    }
+
+    pub async fn close(self) {
+        self.ftdi.close().await
+    }
+}
+
+impl Stream for SyncFifo {
+    type Item = io::Result<SyncFifoEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+type EventSender = Sender<io::Result<SyncFifoEvent>>;
+type EventReceiver = Receiver<io::Result<SyncFifoEvent>>;
+
+impl Handler {
+    pub(crate) fn start_sync_fifo(
+        &mut self,
+        config: &SyncFifoConfig,
+    ) -> io::Result<(EventSender, EventReceiver)> {
+        if config.chunk_size == 0 || config.queue_depth == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Chunk size and queue depth must not be zero",
+            ));
+        }
+        self.set_bit_mode(0xFF, BitMode::Reset)?;
+        thread::sleep(Duration::from_millis(10));
+        self.set_bit_mode(0xFF, BitMode::SyncFifo)?;
+        self.device
+            .set_usb_parameters(config.chunk_size.min(64 * 1024) as u32)
+            .map_err(status_to_io_error)?;
+        self.device
+            .set_flow_control_rts_cts()
+            .map_err(status_to_io_error)?;
+        self.device.purge_rx().map_err(status_to_io_error)?;
+        Ok(channel(config.queue_depth))
+    }
+
+    /// Serve commands while the reader thread streams data, only returns once the device
+    /// is closed or reading failed.
+    pub(crate) fn run_sync_fifo(&mut self, tx: EventSender, chunk_size: usize) -> io::Result<()> {
+        let stop = AtomicBool::new(false);
+        let reader = Reader {
+            handle: RawHandle(self.device.handle()),
+            tx,
+            wake: self.command_tx.clone(),
+            stop: &stop,
+            chunk_size,
+        };
+        thread::scope(|scope| {
+            let reader = scope.spawn(move || reader.run());
+            let mut result = Ok(());
+            // the reader wakes us up with a `PollRead` once it stopped by itself
+            while !reader.is_finished() {
+                match self.command_rx.blocking_recv() {
+                    None | Some(Command::Cancel) => break,
+                    Some(Command::Send(data)) => {
+                        if let Err(err) = self.send_data(data) {
+                            result = Err(err);
+                            break;
+                        }
+                    }
+                    Some(Command::PollRead) => {}
+                    Some(cmd) => cmd.reject("Command not available in synchronous FIFO mode"),
+                }
+            }
+            stop.store(true, Ordering::Relaxed);
+            let read_result = reader.join().expect("FIFO reader panicked");
+            result.and(read_result)
+        })
+    }
+}
+
+/// Device handle shared with the reader thread.
+///
+/// D2XX allows reading and writing a handle from different threads. The handler joins the
+/// reader before the device can be closed.
+struct RawHandle(FT_HANDLE);
+
+unsafe impl Send for RawHandle {}
+
+struct Reader<'a> {
+    handle: RawHandle,
+    tx: EventSender,
+    wake: UnboundedSender<Command>,
+    stop: &'a AtomicBool,
+    chunk_size: usize,
+}
+
+impl Reader<'_> {
+    fn run(self) -> io::Result<()> {
+        let result = self.read_loop();
+        let _ = self.wake.send(Command::PollRead);
+        result
+    }
+
+    fn read_loop(&self) -> io::Result<()> {
+        // Chunks are split off this buffer, its allocation is reclaimed by `reserve` once
+        // the consumer dropped all of them.
+        let mut buf = BytesMut::with_capacity(self.chunk_size);
+        let mut lost_bytes = 0;
+        while !self.stop.load(Ordering::Relaxed) {
+            buf.reserve(self.chunk_size);
+            let num_bytes = match self.read(&mut buf) {
+                Ok(x) => x,
+                Err(err) => {
+                    let _ = self.tx.try_send(Err(crate::clone_io_error(&err)));
+                    return Err(err);
+                }
+            };
+            if lost_bytes > 0 {
+                match self.tx.try_send(Ok(SyncFifoEvent::Overrun { lost_bytes })) {
+                    Ok(()) => lost_bytes = 0,
+                    Err(TrySendError::Full(_)) => {}
+                    Err(TrySendError::Closed(_)) => return Ok(()),
+                }
+            }
+            if num_bytes == 0 {
+                continue;
+            }
+            let chunk = buf.split().freeze();
+            match self.tx.try_send(Ok(SyncFifoEvent::Data(chunk))) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    log::debug!("FIFO consumer fell behind, dropping {} bytes", num_bytes);
+                    lost_bytes += num_bytes;
+                }
+                Err(TrySendError::Closed(_)) => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read up to the chunk size into the spare capacity of `buf`, which is not zeroed.
+    fn read(&self, buf: &mut BytesMut) -> io::Result<usize> {
+        let spare = buf.chunk_mut();
+        let len = spare.len().min(self.chunk_size);
+        let mut num_bytes: DWORD = 0;
+        let status = unsafe {
+            FT_Read(
+                self.handle.0,
+                spare.as_mut_ptr() as *mut _,
+                len as DWORD,
+                &mut num_bytes,
+            )
+        };
+        if status != 0 {
+            return Err(status_to_io_error(FtStatus::from(status)));
+        }
+        let num_bytes = (num_bytes as usize).min(len);
+        // D2XX initialized the first `num_bytes` bytes of the spare capacity
+        unsafe { buf.advance_mut(num_bytes) };
+        Ok(num_bytes)
+    }
+}
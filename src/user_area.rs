@@ -0,0 +1,512 @@
+//! Key-value store in the EEPROM user area, e.g. for calibration data.
+//!
+//! The user area is the part of the EEPROM not used by the chip configuration. Its
+//! size depends on the chip type and on the length of the USB strings. The store uses
+//! the following layout, all integers little endian:
+//!
+//! ```text
+//! magic "KV" | version: u8 | length: u16 | entries | crc16: u16
+//! entry: tag: u8 | key length: u8 | key | value length: u8 | value
+//! ```
+//!
+//! The CRC-16/CCITT covers everything from the magic up to the last entry. If the area
+//! is blank, from a different layout version or fails the CRC check, the store reads as
+//! empty and the next write replaces the contents.
+
+use std::collections::BTreeMap;
+use std::io;
+
+use libftd2xx::{FtStatus, FtdiCommon};
+use libftd2xx_ffi::{FT_EE_UARead, FT_EE_UASize, FT_EE_UAWrite, DWORD};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{request, status_to_io_error, Command, Ftdi, Handler};
+
+const MAGIC: [u8; 2] = *b"KV";
+const VERSION: u8 = 1;
+/// Magic, version and length.
+const HEADER_LEN: usize = 5;
+const CRC_LEN: usize = 2;
+
+const TAG_BOOL: u8 = 0;
+const TAG_U32: u8 = 1;
+const TAG_I64: u8 = 2;
+const TAG_F64: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_BYTES: u8 = 5;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UserValue {
+    Bool(bool),
+    U32(u32),
+    I64(i64),
+    F64(f64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl UserValue {
+    fn tag(&self) -> u8 {
+        match self {
+            UserValue::Bool(_) => TAG_BOOL,
+            UserValue::U32(_) => TAG_U32,
+            UserValue::I64(_) => TAG_I64,
+            UserValue::F64(_) => TAG_F64,
+            UserValue::String(_) => TAG_STRING,
+            UserValue::Bytes(_) => TAG_BYTES,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            UserValue::Bool(x) => vec![*x as u8],
+            UserValue::U32(x) => x.to_le_bytes().to_vec(),
+            UserValue::I64(x) => x.to_le_bytes().to_vec(),
+            UserValue::F64(x) => x.to_le_bytes().to_vec(),
+            UserValue::String(x) => x.as_bytes().to_vec(),
+            UserValue::Bytes(x) => x.clone(),
+        }
+    }
+
+    fn decode(tag: u8, data: &[u8]) -> Option<Self> {
+        Some(match tag {
+            TAG_BOOL if data.len() == 1 => UserValue::Bool(data[0] != 0),
+            TAG_U32 => UserValue::U32(u32::from_le_bytes(data.try_into().ok()?)),
+            TAG_I64 => UserValue::I64(i64::from_le_bytes(data.try_into().ok()?)),
+            TAG_F64 => UserValue::F64(f64::from_le_bytes(data.try_into().ok()?)),
+            TAG_STRING => UserValue::String(String::from_utf8(data.to_vec()).ok()?),
+            TAG_BYTES => UserValue::Bytes(data.to_vec()),
+            _ => return None,
+        })
+    }
+}
+
+impl From<bool> for UserValue {
+    fn from(x: bool) -> Self {
+        UserValue::Bool(x)
+    }
+}
+
+impl From<u32> for UserValue {
+    fn from(x: u32) -> Self {
+        UserValue::U32(x)
+    }
+}
+
+impl From<i64> for UserValue {
+    fn from(x: i64) -> Self {
+        UserValue::I64(x)
+    }
+}
+
+impl From<f64> for UserValue {
+    fn from(x: f64) -> Self {
+        UserValue::F64(x)
+    }
+}
+
+impl From<&str> for UserValue {
+    fn from(x: &str) -> Self {
+        UserValue::String(x.to_string())
+    }
+}
+
+impl From<String> for UserValue {
+    fn from(x: String) -> Self {
+        UserValue::String(x)
+    }
+}
+
+impl From<Vec<u8>> for UserValue {
+    fn from(x: Vec<u8>) -> Self {
+        UserValue::Bytes(x)
+    }
+}
+
+/// Condition of the user area as found when it was read.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UserAreaState {
+    Valid,
+    /// The area does not contain a store yet.
+    Blank,
+    /// The area contains a store of an unknown version or failed the CRC check.
+    Corrupted,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct UserAreaCapacity {
+    /// Size of the user area of this chip in bytes.
+    pub total: usize,
+    /// Bytes used by the store including its header and CRC.
+    pub used: usize,
+}
+
+impl UserAreaCapacity {
+    pub fn free(&self) -> usize {
+        self.total.saturating_sub(self.used)
+    }
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for x in data {
+        crc ^= (*x as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let ret = data.get(*pos..*pos + len)?;
+    *pos += len;
+    Some(ret)
+}
+
+fn decode_entry(data: &[u8], pos: &mut usize) -> Option<(String, UserValue)> {
+    let tag = take(data, pos, 1)?[0];
+    let key_len = take(data, pos, 1)?[0] as usize;
+    let key = String::from_utf8(take(data, pos, key_len)?.to_vec()).ok()?;
+    let value_len = take(data, pos, 1)?[0] as usize;
+    let value = UserValue::decode(tag, take(data, pos, value_len)?)?;
+    Some((key, value))
+}
+
+fn decode(data: &[u8]) -> (UserAreaState, BTreeMap<String, UserValue>) {
+    let mut entries = BTreeMap::new();
+    if data.len() < HEADER_LEN + CRC_LEN || data[..2] != MAGIC {
+        return (UserAreaState::Blank, entries);
+    }
+    let len = u16::from_le_bytes([data[3], data[4]]) as usize;
+    let end = HEADER_LEN + len;
+    if data[2] != VERSION || end + CRC_LEN > data.len() {
+        return (UserAreaState::Corrupted, entries);
+    }
+    let crc = u16::from_le_bytes([data[end], data[end + 1]]);
+    if crc != crc16(&data[..end]) {
+        return (UserAreaState::Corrupted, entries);
+    }
+
+    let payload = &data[..end];
+    let mut pos = HEADER_LEN;
+    while pos < end {
+        match decode_entry(payload, &mut pos) {
+            Some((key, value)) => {
+                entries.insert(key, value);
+            }
+            None => return (UserAreaState::Corrupted, BTreeMap::new()),
+        }
+    }
+    (UserAreaState::Valid, entries)
+}
+
+fn load(data: &[u8]) -> (UserAreaState, BTreeMap<String, UserValue>) {
+    let (state, entries) = decode(data);
+    if state == UserAreaState::Corrupted {
+        log::warn!("EEPROM user area is corrupted, treating it as empty");
+    }
+    (state, entries)
+}
+
+fn encode(entries: &BTreeMap<String, UserValue>) -> io::Result<Vec<u8>> {
+    let mut ret = Vec::new();
+    ret.extend(MAGIC);
+    ret.push(VERSION);
+    ret.extend([0, 0]);
+    for (key, value) in entries {
+        let data = value.encode();
+        if key.len() > u8::MAX as usize || data.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Key or value of {} exceeds 255 bytes", key),
+            ));
+        }
+        ret.push(value.tag());
+        ret.push(key.len() as u8);
+        ret.extend(key.as_bytes());
+        ret.push(data.len() as u8);
+        ret.extend(data);
+    }
+    let len = (ret.len() - HEADER_LEN) as u16;
+    ret[3..5].copy_from_slice(&len.to_le_bytes());
+    let crc = crc16(&ret);
+    ret.extend(crc.to_le_bytes());
+    Ok(ret)
+}
+
+/// Handle to the key-value store in the user area, obtained from [`Ftdi::user_store`].
+///
+/// Every operation reads the user area, so changes made through other handles or tools
+/// are always visible. [`UserStore::set`] and [`UserStore::remove`] read, modify and write
+/// the area in a single request to the handler thread, such that concurrent updates
+/// through clones of a store do not overwrite each other.
+#[derive(Debug, Clone)]
+pub struct UserStore {
+    command_tx: UnboundedSender<Command>,
+}
+
+impl Ftdi {
+    pub fn user_store(&self) -> UserStore {
+        UserStore {
+            command_tx: self.command_tx.clone(),
+        }
+    }
+}
+
+impl UserStore {
+    async fn read_area(&self) -> io::Result<Vec<u8>> {
+        request(&self.command_tx, |answer| Command::UserAreaRead { answer }).await
+    }
+
+    async fn update(&self, key: &str, value: Option<UserValue>) -> io::Result<Option<UserValue>> {
+        request(&self.command_tx, |answer| Command::UserAreaUpdate {
+            key: key.to_string(),
+            value,
+            answer,
+        })
+        .await
+    }
+
+    async fn write(&self, entries: &BTreeMap<String, UserValue>) -> io::Result<()> {
+        let data = encode(entries)?;
+        request(&self.command_tx, |answer| Command::UserAreaWrite {
+            data,
+            answer,
+        })
+        .await
+    }
+
+    /// Read all entries and the state the user area was found in.
+    pub async fn load(&self) -> io::Result<(UserAreaState, BTreeMap<String, UserValue>)> {
+        Ok(load(&self.read_area().await?))
+    }
+
+    pub async fn get(&self, key: &str) -> io::Result<Option<UserValue>> {
+        let (_, mut entries) = self.load().await?;
+        Ok(entries.remove(key))
+    }
+
+    /// Store `value` under `key`, fails if the store would exceed the user area.
+    pub async fn set(&self, key: &str, value: impl Into<UserValue>) -> io::Result<()> {
+        self.update(key, Some(value.into())).await?;
+        Ok(())
+    }
+
+    /// Remove `key` and return its previous value.
+    pub async fn remove(&self, key: &str) -> io::Result<Option<UserValue>> {
+        self.update(key, None).await
+    }
+
+    /// Remove all entries, this also repairs a corrupted store.
+    pub async fn clear(&self) -> io::Result<()> {
+        self.write(&BTreeMap::new()).await
+    }
+
+    pub async fn capacity(&self) -> io::Result<UserAreaCapacity> {
+        let area = self.read_area().await?;
+        let (_, entries) = decode(&area);
+        Ok(UserAreaCapacity {
+            total: area.len(),
+            used: encode(&entries)?.len(),
+        })
+    }
+}
+
+impl Handler {
+    fn user_area_size(&mut self) -> io::Result<usize> {
+        let mut size: DWORD = 0;
+        let status = unsafe { FT_EE_UASize(self.device.handle(), &mut size) };
+        if status != 0 {
+            return Err(status_to_io_error(FtStatus::from(status)));
+        }
+        Ok(size as usize)
+    }
+
+    pub(crate) fn user_area_read(&mut self) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; self.user_area_size()?];
+        let mut read: DWORD = 0;
+        let status = unsafe {
+            FT_EE_UARead(
+                self.device.handle(),
+                buf.as_mut_ptr(),
+                buf.len() as _,
+                &mut read,
+            )
+        };
+        if status != 0 {
+            return Err(status_to_io_error(FtStatus::from(status)));
+        }
+        buf.truncate(read as usize);
+        Ok(buf)
+    }
+
+    pub(crate) fn user_area_write(&mut self, mut data: Vec<u8>) -> io::Result<()> {
+        let size = self.user_area_size()?;
+        if data.len() > size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} bytes do not fit into the user area of {} bytes",
+                    data.len(),
+                    size
+                ),
+            ));
+        }
+        let status =
+            unsafe { FT_EE_UAWrite(self.device.handle(), data.as_mut_ptr(), data.len() as _) };
+        if status != 0 {
+            return Err(status_to_io_error(FtStatus::from(status)));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn user_area_update(
+        &mut self,
+        key: String,
+        value: Option<UserValue>,
+    ) -> io::Result<Option<UserValue>> {
+        let (_, mut entries) = load(&self.user_area_read()?);
+        let ret = match value {
+            Some(value) => entries.insert(key, value),
+            None => match entries.remove(&key) {
+                Some(x) => Some(x),
+                None => return Ok(None),
+            },
+        };
+        self.user_area_write(encode(&entries)?)?;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> BTreeMap<String, UserValue> {
+        BTreeMap::from([
+            ("enabled".to_string(), UserValue::Bool(true)),
+            ("count".to_string(), UserValue::U32(0xDEAD_BEEF)),
+            ("offset".to_string(), UserValue::I64(-42)),
+            ("gain".to_string(), UserValue::F64(1.25)),
+            ("name".to_string(), UserValue::from("probe")),
+            ("raw".to_string(), UserValue::Bytes(vec![0, 1, 2, 0xFF])),
+        ])
+    }
+
+    /// Replace the CRC of an encoded store after modifying its payload.
+    fn fix_crc(data: &mut [u8]) {
+        let end = data.len() - CRC_LEN;
+        let crc = crc16(&data[..end]);
+        data[end..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn crc16_ccitt() {
+        // check value of CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), 0xFFFF);
+    }
+
+    #[test]
+    fn encode_layout() {
+        let data = encode(&BTreeMap::from([("a".to_string(), UserValue::Bool(true))])).unwrap();
+        let crc = crc16(&data[..10]).to_le_bytes();
+        assert_eq!(
+            data,
+            [b'K', b'V', VERSION, 5, 0, TAG_BOOL, 1, b'a', 1, 1, crc[0], crc[1]]
+        );
+        let data = encode(&BTreeMap::new()).unwrap();
+        assert_eq!(data.len(), HEADER_LEN + CRC_LEN);
+        assert_eq!(data[3..5], [0, 0]);
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let mut data = encode(&entries()).unwrap();
+        assert_eq!(decode(&data), (UserAreaState::Valid, entries()));
+        // the rest of the user area after the CRC is ignored
+        data.extend([0xFF; 16]);
+        assert_eq!(decode(&data), (UserAreaState::Valid, entries()));
+    }
+
+    #[test]
+    fn encode_rejects_long_values() {
+        let long = BTreeMap::from([("x".to_string(), UserValue::Bytes(vec![0; 256]))]);
+        assert!(encode(&long).is_err());
+        let long = BTreeMap::from([("x".repeat(256), UserValue::Bool(false))]);
+        assert!(encode(&long).is_err());
+    }
+
+    #[test]
+    fn decode_blank() {
+        for data in [vec![], vec![0xFF; 64], vec![0x00; 64], b"KV".to_vec()] {
+            assert_eq!(decode(&data), (UserAreaState::Blank, BTreeMap::new()));
+        }
+    }
+
+    #[test]
+    fn decode_corrupted_crc() {
+        let mut data = encode(&entries()).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        assert_eq!(decode(&data), (UserAreaState::Corrupted, BTreeMap::new()));
+
+        let mut data = encode(&entries()).unwrap();
+        data[HEADER_LEN + 3] ^= 0x80;
+        assert_eq!(decode(&data), (UserAreaState::Corrupted, BTreeMap::new()));
+    }
+
+    #[test]
+    fn decode_unknown_version() {
+        let mut data = encode(&entries()).unwrap();
+        data[2] = VERSION + 1;
+        fix_crc(&mut data);
+        assert_eq!(decode(&data), (UserAreaState::Corrupted, BTreeMap::new()));
+    }
+
+    #[test]
+    fn decode_truncated_length() {
+        // the length field points past the end of the data
+        let data = encode(&entries()).unwrap();
+        let truncated = &data[..data.len() - 4];
+        assert_eq!(
+            decode(truncated),
+            (UserAreaState::Corrupted, BTreeMap::new())
+        );
+
+        // the length field cuts the last entry short, but the CRC matches
+        let mut data = encode(&entries()).unwrap();
+        let len = u16::from_le_bytes([data[3], data[4]]) - 1;
+        data[3..5].copy_from_slice(&len.to_le_bytes());
+        data.remove(HEADER_LEN + len as usize);
+        fix_crc(&mut data);
+        assert_eq!(decode(&data), (UserAreaState::Corrupted, BTreeMap::new()));
+    }
+
+    #[test]
+    fn decode_invalid_value() {
+        // a u32 entry with a value of two bytes
+        let mut data = vec![b'K', b'V', VERSION, 6, 0, TAG_U32, 1, b'x', 2, 0, 0, 0, 0];
+        fix_crc(&mut data);
+        assert_eq!(decode(&data), (UserAreaState::Corrupted, BTreeMap::new()));
+    }
+
+    #[test]
+    fn capacity() {
+        let capacity = UserAreaCapacity {
+            total: 10,
+            used: 12,
+        };
+        assert_eq!(capacity.free(), 0);
+        let capacity = UserAreaCapacity {
+            total: 64,
+            used: 12,
+        };
+        assert_eq!(capacity.free(), 52);
+    }
+}
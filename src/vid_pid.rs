@@ -0,0 +1,117 @@
+//! Registration of custom USB vendor and product IDs.
+//!
+//! On Linux, the D2XX library only enumerates devices with FTDI's default IDs and the
+//! single custom pair set with `FT_SetVIDPID`. To support several custom pairs, every
+//! enumeration and every open runs once per registered pair while holding a global lock,
+//! such that concurrent calls cannot change the active pair underneath each other.
+
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+
+use libftd2xx::list_devices;
+use tokio::task::spawn_blocking;
+
+use crate::{status_to_io_error, DeviceInfo, Ftdi};
+
+static REGISTERED: Mutex<Vec<(u16, u16)>> = Mutex::new(Vec::new());
+
+/// Held while the active pair of the D2XX library is changed or used.
+static ENUMERATION: Mutex<()> = Mutex::new(());
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // the protected data stays consistent even if a holder panicked
+    mutex.lock().unwrap_or_else(|x| x.into_inner())
+}
+
+#[cfg(target_os = "linux")]
+fn activate(vendor_id: u16, product_id: u16) -> io::Result<()> {
+    use libftd2xx::FtStatus;
+    use libftd2xx_ffi::FT_SetVIDPID;
+
+    let status = unsafe { FT_SetVIDPID(vendor_id as _, product_id as _) };
+    if status != 0 {
+        return Err(status_to_io_error(FtStatus::from(status)));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn activate(_vendor_id: u16, _product_id: u16) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Custom VID/PID registration is only required on Linux",
+    ))
+}
+
+/// Run `f` once for every registered pair, or once if none is registered.
+pub(crate) fn for_each_pair<T>(mut f: impl FnMut() -> io::Result<T>) -> io::Result<Vec<T>> {
+    let _guard = lock(&ENUMERATION);
+    let pairs = lock(&REGISTERED).clone();
+    if pairs.is_empty() {
+        return Ok(vec![f()?]);
+    }
+    let mut ret = Vec::with_capacity(pairs.len());
+    for (vendor_id, product_id) in pairs {
+        activate(vendor_id, product_id)?;
+        ret.push(f()?);
+    }
+    Ok(ret)
+}
+
+/// Run `f` with each registered pair active until it succeeds.
+pub(crate) fn try_each_pair<T>(mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let _guard = lock(&ENUMERATION);
+    let pairs = lock(&REGISTERED).clone();
+    let mut ret = f();
+    for (vendor_id, product_id) in pairs {
+        if ret.is_ok() {
+            break;
+        }
+        activate(vendor_id, product_id)?;
+        ret = f();
+    }
+    ret
+}
+
+/// List the devices visible with any of the registered pairs.
+pub(crate) fn list_all() -> io::Result<Vec<DeviceInfo>> {
+    let lists = for_each_pair(|| list_devices().map_err(status_to_io_error))?;
+    let mut ret: Vec<DeviceInfo> = Vec::new();
+    // devices with default IDs show up in every list
+    for device in lists.into_iter().flatten().map(DeviceInfo::from_d2xx) {
+        let duplicate = ret.iter().any(|x| {
+            x.vendor_id == device.vendor_id
+                && x.product_id == device.product_id
+                && x.serial_number == device.serial_number
+                && x.description == device.description
+        });
+        if !duplicate {
+            ret.push(device);
+        }
+    }
+    Ok(ret)
+}
+
+impl Ftdi {
+    /// Make devices with a custom vendor and product ID visible to the D2XX library.
+    ///
+    /// Only needed on Linux, fails with [`io::ErrorKind::Unsupported`] elsewhere.
+    pub async fn register_vid_pid(vendor_id: u16, product_id: u16) -> io::Result<()> {
+        spawn_blocking(move || {
+            let _guard = lock(&ENUMERATION);
+            activate(vendor_id, product_id)?;
+            let mut registered = lock(&REGISTERED);
+            if !registered.contains(&(vendor_id, product_id)) {
+                registered.push((vendor_id, product_id));
+            }
+            Ok(())
+        })
+        .await
+        .unwrap()
+    }
+
+    /// The vendor and product IDs registered with [`Ftdi::register_vid_pid`].
+    pub fn registered_vid_pids() -> Vec<(u16, u16)> {
+        lock(&REGISTERED).clone()
+    }
+}
@@ -0,0 +1,353 @@
+//! Edge detection on GPIO pins by periodic sampling.
+//!
+//! The pins are sampled by the handler thread between commands, one sample serves all
+//! watches which are due. Commands take precedence, but a sample which is overdue is
+//! taken before the next command. In MPSSE mode all 16 pins are read with the GPIO read
+//! opcodes, in the bit-bang modes the pins are read with `FT_GetBitMode`, which only
+//! covers the lower 8 bits.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use libftd2xx::FtdiCommon;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::mpsse::Mpsse;
+use crate::{request, status_to_io_error, BitMode, Command, Ftdi, Handler};
+
+const GET_PINS: [u8; 3] = [0x81, 0x83, 0x87];
+
+pub(crate) type PinReceiver = UnboundedReceiver<io::Result<PinEvent>>;
+
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// Time between two samples of the pins.
+    pub interval: Duration,
+    /// A new level is only reported once it was stable for this long.
+    pub debounce: Duration,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(1),
+            debounce: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PinEvent {
+    pub pin: u8,
+    /// New level of the pin.
+    pub high: bool,
+    /// Time at which the new level was sampled first.
+    pub timestamp: Instant,
+}
+
+/// Edge events of the watched pins, sampling stops once this is dropped.
+#[derive(Debug)]
+pub struct PinWatch {
+    levels: u16,
+    rx: PinReceiver,
+}
+
+impl PinWatch {
+    /// Debounced level of all pins as of the last event returned by the stream.
+    pub fn levels(&self) -> u16 {
+        self.levels
+    }
+
+    pub fn level(&self, pin: u8) -> bool {
+        self.levels & (1 << pin) != 0
+    }
+}
+
+impl Stream for PinWatch {
+    type Item = io::Result<PinEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let ret = self.rx.poll_recv(cx);
+        if let Poll::Ready(Some(Ok(event))) = &ret {
+            let mask = 1 << event.pin;
+            self.levels = if event.high {
+                self.levels | mask
+            } else {
+                self.levels & !mask
+            };
+        }
+        ret
+    }
+}
+
+impl Ftdi {
+    /// Watch the pins in `mask` for edges using the default [`WatchConfig`].
+    pub async fn watch_pins(&self, mask: u16) -> io::Result<PinWatch> {
+        self.watch_pins_with_config(mask, WatchConfig::default())
+            .await
+    }
+
+    pub async fn watch_pins_with_config(
+        &self,
+        mask: u16,
+        config: WatchConfig,
+    ) -> io::Result<PinWatch> {
+        let (levels, rx) = request(&self.command_tx, |answer| Command::WatchPins {
+            mask,
+            config,
+            answer,
+        })
+        .await?;
+        Ok(PinWatch { levels, rx })
+    }
+
+    /// Wait until `pin` reaches `high`, returns immediately if it already has this level.
+    pub async fn wait_for(&self, pin: u8, high: bool, timeout: Duration) -> io::Result<()> {
+        let watch = self.watch_pins(1 << pin).await?;
+        wait_for(watch, pin, high, timeout).await
+    }
+}
+
+impl Mpsse {
+    pub async fn watch_pins(&self, mask: u16) -> io::Result<PinWatch> {
+        self.ftdi().watch_pins(mask).await
+    }
+
+    pub async fn watch_pins_with_config(
+        &self,
+        mask: u16,
+        config: WatchConfig,
+    ) -> io::Result<PinWatch> {
+        self.ftdi().watch_pins_with_config(mask, config).await
+    }
+
+    pub async fn wait_for(&self, pin: u8, high: bool, timeout: Duration) -> io::Result<()> {
+        self.ftdi().wait_for(pin, high, timeout).await
+    }
+}
+
+async fn wait_for(mut watch: PinWatch, pin: u8, high: bool, timeout: Duration) -> io::Result<()> {
+    let wait = async {
+        while watch.level(pin) != high {
+            match std::future::poll_fn(|cx| Pin::new(&mut watch).poll_next(cx)).await {
+                Some(x) => x?,
+                None => return Err(crate::disconnected_error()),
+            };
+        }
+        Ok(())
+    };
+    tokio::time::timeout(timeout, wait).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "Pin {} did not become {}",
+                pin,
+                if high { "high" } else { "low" }
+            ),
+        )
+    })?
+}
+
+struct Watcher {
+    mask: u16,
+    interval: Duration,
+    debounce: Duration,
+    /// Time of the next sample.
+    next: Instant,
+    levels: u16,
+    /// Time at which a level different from `levels` was first seen, per pin.
+    pending: [Option<Instant>; 16],
+    tx: UnboundedSender<io::Result<PinEvent>>,
+}
+
+impl Watcher {
+    fn update(&mut self, sample: u16, now: Instant) -> bool {
+        for pin in 0..16 {
+            let mask = 1 << pin;
+            if self.mask & mask == 0 {
+                continue;
+            }
+            if (sample ^ self.levels) & mask == 0 {
+                self.pending[pin] = None;
+                continue;
+            }
+            let since = *self.pending[pin].get_or_insert(now);
+            if now.duration_since(since) < self.debounce {
+                continue;
+            }
+            self.pending[pin] = None;
+            self.levels ^= mask;
+            let event = PinEvent {
+                pin: pin as u8,
+                high: sample & mask != 0,
+                timestamp: since,
+            };
+            if self.tx.send(Ok(event)).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Watches registered with a handler.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    watchers: Vec<Watcher>,
+    /// Used to wait for commands with a timeout, created with the first watch.
+    runtime: Option<Runtime>,
+}
+
+impl Watchers {
+    fn next_sample(&self) -> Option<Instant> {
+        self.watchers.iter().map(|x| x.next).min()
+    }
+}
+
+impl Handler {
+    pub(crate) fn watch_pins(
+        &mut self,
+        mask: u16,
+        config: WatchConfig,
+    ) -> io::Result<(u16, PinReceiver)> {
+        if self.watchers.runtime.is_none() {
+            self.watchers.runtime = Some(Builder::new_current_thread().enable_time().build()?);
+        }
+        let levels = self.sample_pins()?;
+        let (tx, rx) = unbounded_channel();
+        self.watchers.watchers.push(Watcher {
+            mask,
+            interval: config.interval,
+            debounce: config.debounce,
+            next: Instant::now() + config.interval,
+            levels,
+            pending: Default::default(),
+            tx,
+        });
+        Ok((levels, rx))
+    }
+
+    /// Wait for the next command, sampling the pins for the watches in the meantime.
+    pub(crate) fn next_command(&mut self) -> Option<Command> {
+        loop {
+            self.watchers.watchers.retain(|x| !x.tx.is_closed());
+            let (next, runtime) = match (self.watchers.next_sample(), &self.watchers.runtime) {
+                (Some(next), Some(runtime)) => (next, runtime),
+                _ => return self.command_rx.blocking_recv(),
+            };
+            let timeout = next.saturating_duration_since(Instant::now());
+            if !timeout.is_zero() {
+                let recv = tokio::time::timeout(timeout, self.command_rx.recv());
+                if let Ok(command) = runtime.block_on(recv) {
+                    return command;
+                }
+            }
+            self.sample_watchers();
+        }
+    }
+
+    /// Take one sample for all watches which are due.
+    fn sample_watchers(&mut self) {
+        let now = Instant::now();
+        let result = self.sample_pins();
+        self.watchers.watchers.retain_mut(|watcher| {
+            if watcher.next > now {
+                return true;
+            }
+            watcher.next = now + watcher.interval;
+            match &result {
+                Ok(sample) => watcher.update(*sample, now),
+                Err(err) => {
+                    let _ = watcher.tx.send(Err(crate::clone_io_error(err)));
+                    false
+                }
+            }
+        });
+    }
+
+    fn sample_pins(&mut self) -> io::Result<u16> {
+        if self.mode == BitMode::Mpsse {
+            let response = self.transfer(GET_PINS.to_vec(), 2)?;
+            Ok(response[0] as u16 | (response[1] as u16) << 8)
+        } else {
+            Ok(self.device.bit_mode().map_err(status_to_io_error)? as u16)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watcher(mask: u16, debounce: Duration) -> (Watcher, PinReceiver) {
+        let (tx, rx) = unbounded_channel();
+        let watcher = Watcher {
+            mask,
+            interval: Duration::from_millis(1),
+            debounce,
+            next: Instant::now(),
+            levels: 0,
+            pending: Default::default(),
+            tx,
+        };
+        (watcher, rx)
+    }
+
+    fn events(rx: &mut PinReceiver) -> Vec<PinEvent> {
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|x| x.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn debounce() {
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+        let (mut watcher, mut rx) = watcher(0x0003, ms(10));
+
+        // a glitch shorter than the debounce time is not reported
+        assert!(watcher.update(0x0001, start));
+        assert!(watcher.update(0x0001, start + ms(5)));
+        assert!(watcher.update(0x0000, start + ms(6)));
+        assert!(events(&mut rx).is_empty());
+
+        // pins outside of the mask are ignored
+        assert!(watcher.update(0x0005, start + ms(7)));
+        assert!(watcher.update(0x0005, start + ms(16)));
+        assert!(events(&mut rx).is_empty());
+        assert!(watcher.update(0x0005, start + ms(17)));
+        let expected = PinEvent {
+            pin: 0,
+            high: true,
+            timestamp: start + ms(7),
+        };
+        assert_eq!(events(&mut rx), [expected]);
+        assert_eq!(watcher.levels, 0x0001);
+
+        // the stable level is not reported again
+        assert!(watcher.update(0x0001, start + ms(40)));
+        assert!(events(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn without_debounce() {
+        let start = Instant::now();
+        let (mut watcher, mut rx) = watcher(0x8000, Duration::ZERO);
+        assert!(watcher.update(0x8000, start));
+        let expected = PinEvent {
+            pin: 15,
+            high: true,
+            timestamp: start,
+        };
+        assert_eq!(events(&mut rx), [expected]);
+
+        // a dropped receiver ends the watch once there is something to report
+        drop(rx);
+        assert!(watcher.update(0x8000, start));
+        assert!(!watcher.update(0x0000, start));
+    }
+}